@@ -0,0 +1,19 @@
+//! Feeds arbitrary bytes to the backend message parser, which must return
+//! an error rather than panic on anything the server might send.
+//!
+//!     cargo +nightly fuzz run backend_message
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use rpsql::messages::backend::BackendMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    while (cursor.position() as usize) < data.len() {
+        if BackendMessage::read_next_message(&mut cursor).is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,103 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, LitStr, PathArguments,
+    Type,
+};
+
+/// Derives `rpsql::FromRow` for a struct with named fields, reading each
+/// field from the column of the same name. `#[column(rename = "...")]`
+/// reads a field from a differently named column, and an `Option<T>` field
+/// takes NULL as None.
+#[proc_macro_derive(FromRow, attributes(column))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input,
+            "FromRow can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &input,
+            "FromRow can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut initializers = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let column = column_name(field)?.unwrap_or_else(|| ident.to_string());
+        let getter = match option_inner(&field.ty) {
+            Some(inner) => quote!(get_opt::<#inner>),
+            None => {
+                let ty = &field.ty;
+                quote!(get::<#ty>)
+            }
+        };
+        initializers.push(quote! {
+            #ident: row.#getter(row.require_column(#column)?)?
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rpsql::FromRow for #name #ty_generics #where_clause {
+            fn from_row(
+                row: &::rpsql::Row<'_>,
+            ) -> ::std::result::Result<Self, ::std::boxed::Box<dyn ::std::error::Error>> {
+                Ok(Self {
+                    #(#initializers,)*
+                })
+            }
+        }
+    })
+}
+
+// The column named by `#[column(rename = "...")]`, if the field has one.
+fn column_name(field: &syn::Field) -> Result<Option<String>, Error> {
+    let mut rename = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("column"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                rename = Some(name.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `rename = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(rename)
+}
+
+// The `T` of an `Option<T>` field.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(inner) if arguments.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
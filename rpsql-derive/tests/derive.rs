@@ -0,0 +1,44 @@
+use std::error::Error;
+
+use rpsql::{
+    messages::backend::{DataRow, RowDescription},
+    types::TypeOid,
+    FromRow, Row,
+};
+
+#[derive(Debug, PartialEq, FromRow)]
+struct User {
+    id: i32,
+    #[column(rename = "user_name")]
+    name: Option<String>,
+}
+
+#[test]
+fn test_derive_from_row() -> Result<(), Box<dyn Error>> {
+    let description = RowDescription::builder()
+        .string_field("user_name")
+        .binary_field("id", TypeOid::Int4.as_u32())
+        .build();
+
+    let data_row = DataRow::builder()
+        .string_field("alice")
+        .binary_field(1i32.to_be_bytes())
+        .build();
+    let user = User::from_row(&Row::new(&description, &data_row)?)?;
+    assert_eq!(
+        user,
+        User {
+            id: 1,
+            name: Some("alice".to_string()),
+        }
+    );
+
+    let data_row = DataRow::builder()
+        .null_field()
+        .binary_field(2i32.to_be_bytes())
+        .build();
+    let user = User::from_row(&Row::new(&description, &data_row)?)?;
+    assert_eq!(user, User { id: 2, name: None });
+
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! The parts of the PostgreSQL wire protocol that need only `core` and
+//! `alloc`, so that they can be used without the standard library.
+//!
+//! So far that is the byte readers and ReadyForQuery. The rest of the
+//! messages still live in `rpsql::messages`, which needs `std`; they read
+//! through [`ByteReader`] already, so they can move across one at a time.
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod reader;
+pub use reader::{read_bytes, read_string, read_u16, read_u32, read_u8, ByteReader};
+
+mod ready_for_query;
+pub use ready_for_query::{ReadyForQuery, TransactionStatus};
@@ -0,0 +1,82 @@
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::error::Error;
+
+/// Where message parsers read their bytes from. With the `std` feature
+/// every `std::io::Read` is a ByteReader; without it, byte slices are.
+pub trait ByteReader {
+    /// Fills the whole of `buffer`, or fails.
+    fn read_exact_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Reads everything that is left, for the variable-length end of a
+    /// message body.
+    fn read_remaining(&mut self) -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> ByteReader for R {
+    fn read_exact_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        Ok(self.read_exact(buffer)?)
+    }
+
+    fn read_remaining(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut remaining = Vec::new();
+        self.read_to_end(&mut remaining)?;
+        Ok(remaining)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteReader for &[u8] {
+    fn read_exact_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        if self.len() < buffer.len() {
+            return Err("unexpected end of message".into());
+        }
+        let (head, tail) = self.split_at(buffer.len());
+        buffer.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+
+    fn read_remaining(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let remaining = self.to_vec();
+        *self = &[];
+        Ok(remaining)
+    }
+}
+
+pub fn read_u8(reader: &mut impl ByteReader) -> Result<u8, Box<dyn Error>> {
+    let mut buffer: [u8; 1] = [0; 1];
+    reader.read_exact_bytes(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+pub fn read_u16(reader: &mut impl ByteReader) -> Result<u16, Box<dyn Error>> {
+    let mut buffer: [u8; 2] = [0; 2];
+    reader.read_exact_bytes(&mut buffer)?;
+    Ok(u16::from_be_bytes(buffer))
+}
+
+pub fn read_u32(reader: &mut impl ByteReader) -> Result<u32, Box<dyn Error>> {
+    let mut buffer: [u8; 4] = [0; 4];
+    reader.read_exact_bytes(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+pub fn read_bytes(length: usize, reader: &mut impl ByteReader) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut buffer: Vec<u8> = vec![0; length];
+    reader.read_exact_bytes(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Reads a null-terminated string, and the terminator.
+pub fn read_string(reader: &mut impl ByteReader) -> Result<String, Box<dyn Error>> {
+    let mut buffer: Vec<u8> = vec![];
+    loop {
+        let byte = read_u8(reader)?;
+        if byte == 0 {
+            break;
+        }
+        buffer.push(byte);
+    }
+    Ok(String::from_utf8(buffer)?)
+}
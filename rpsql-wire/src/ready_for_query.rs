@@ -0,0 +1,92 @@
+use alloc::{boxed::Box, format, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{read_u8, ByteReader};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TransactionStatus {
+    #[default]
+    Unknown,
+    Idle,
+    InTransaction,
+    InFailedTransaction,
+}
+
+impl TransactionStatus {
+    pub fn from_u8(value: u8) -> Result<Self, Box<dyn Error>> {
+        match value {
+            b'I' => Ok(TransactionStatus::Idle),
+            b'T' => Ok(TransactionStatus::InTransaction),
+            b'E' => Ok(TransactionStatus::InFailedTransaction),
+            _ => Err(format!("unknown transaction status: {:?}", char::from(value)).into()),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            TransactionStatus::Idle => b'I',
+            TransactionStatus::InTransaction => b'T',
+            TransactionStatus::InFailedTransaction => b'E',
+            _ => {
+                panic!("unknown transaction status: {:?}", self);
+            }
+        }
+    }
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionStatus::Idle => write!(f, "Idle"),
+            TransactionStatus::InTransaction => write!(f, "In Transaction"),
+            TransactionStatus::InFailedTransaction => write!(f, "In Failed Transaction"),
+            _ => {
+                panic!("unknown transaction status: {:?}", self);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadyForQuery {
+    pub transaction_status: TransactionStatus,
+}
+
+impl ReadyForQuery {
+    /// Reads the body of a ReadyForQuery, after its tag and length.
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let transaction_status = TransactionStatus::from_u8(read_u8(stream)?)?;
+
+        Ok(Self { transaction_status })
+    }
+
+    pub fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'Z');
+        buffer.extend_from_slice(&5u32.to_be_bytes());
+        buffer.push(self.transaction_status.to_u8());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_ready_for_query() -> Result<(), Box<dyn Error>> {
+        let ready = ReadyForQuery {
+            transaction_status: TransactionStatus::InTransaction,
+        };
+        let mut encoded = Vec::new();
+        ready.encode_into(&mut encoded);
+        assert_eq!(encoded, vec![b'Z', 0, 0, 0, 5, b'T']);
+
+        let mut body = &encoded[5..];
+        assert_eq!(ReadyForQuery::read_next_message(&mut body)?, ready);
+        assert!(body.is_empty());
+        assert!(ReadyForQuery::read_next_message(&mut body).is_err());
+        assert!(ReadyForQuery::read_next_message(&mut &b"X"[..]).is_err());
+
+        Ok(())
+    }
+}
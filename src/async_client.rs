@@ -0,0 +1,439 @@
+use std::{collections::HashMap, error::Error, str};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    auth::{select_sasl_mechanism, supported_auth_methods, unsupported_auth, ScramClient},
+    messages::{
+        backend::{BackendMessage, DataRow, ErrorResponse},
+        frontend::{PasswordMessage, SASLInitialResponse, SASLResponse, SimpleQuery},
+        startup::{Startup, StartupResponse},
+    },
+    spans,
+    state::{Authentication, BackendKeyData, ParameterStatus},
+    AsyncBackend, AsyncTransaction, Config, FromRow, MaybeTlsStream, Row,
+};
+
+/// The async twin of [`Client`](crate::Client), over an [`AsyncBackend`].
+/// SQL passed to it as a `&str` is sent as written, as it is by `Client`.
+#[derive(Debug)]
+pub struct AsyncClient<S = TcpStream> {
+    backend: AsyncBackend<S>,
+    parameters: HashMap<String, String>,
+    key_data: Option<BackendKeyData>,
+}
+
+impl AsyncClient<MaybeTlsStream> {
+    /// Like [`Config::connect`]: tries each host in order, and returns the
+    /// first session that starts up and matches `target_session_attrs`.
+    /// With [`Config::tls`], each host is asked for TLS first.
+    pub async fn connect(config: &Config) -> Result<Self, Box<dyn Error>> {
+        config.connect_async().await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncClient<S> {
+    pub fn new(backend: AsyncBackend<S>) -> Self {
+        Self {
+            backend,
+            parameters: HashMap::new(),
+            key_data: None,
+        }
+    }
+
+    /// Sends `startup`, answers the server's password request with
+    /// `password` if it makes one, and keeps the parameters and
+    /// cancellation key the server reports.
+    pub async fn startup(
+        &mut self,
+        startup: Startup,
+        password: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let user = startup.parameter("user").map(str::to_string);
+        let database = startup.parameter("database").map(str::to_string);
+        let authenticate = self.authenticate(startup, password);
+        spans::startup_async(user.as_deref(), database.as_deref(), authenticate).await
+    }
+
+    async fn authenticate(
+        &mut self,
+        startup: Startup,
+        password: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let user = startup.parameter("user").unwrap_or_default().to_string();
+        self.backend.send_message(startup).await?;
+        let mut scram: Option<ScramClient> = None;
+        // Whether the server has proved it knows the password, which a SCRAM
+        // exchange must do before authentication may succeed.
+        let mut server_verified = false;
+
+        // The password has to be sent mid-startup, so each response is read
+        // through its own short-lived stream.
+        loop {
+            let response = self
+                .backend
+                .read_startup_messages()
+                .next()
+                .await
+                .ok_or("connection closed during startup")?;
+            println!("AsyncClient startup: {response:?}");
+
+            match response {
+                StartupResponse::Authentication(Authentication::Ok) => {
+                    if scram.is_some() && !server_verified {
+                        return Err("server skipped its SCRAM signature".into());
+                    }
+                }
+                StartupResponse::Authentication(Authentication::CleartextPassword) => {
+                    let password = password.ok_or("the server asked for a password")?;
+                    self.backend
+                        .send_message(PasswordMessage::new(password))
+                        .await?;
+                }
+                StartupResponse::Authentication(Authentication::MD5Password { salt }) => {
+                    let password = password.ok_or("the server asked for a password")?;
+                    self.backend
+                        .send_message(PasswordMessage::md5(&user, password, salt))
+                        .await?;
+                }
+                StartupResponse::Authentication(Authentication::SASL { mechanisms }) => {
+                    let password = password.ok_or("the server asked for a password")?;
+                    // Without TLS there is no channel to bind to.
+                    let Some(mechanism) = select_sasl_mechanism(&mechanisms, false) else {
+                        let authentication = Authentication::SASL { mechanisms };
+                        return Err(
+                            unsupported_auth(&authentication, supported_auth_methods()).into()
+                        );
+                    };
+                    // PostgreSQL takes the user from the startup message.
+                    let client = ScramClient::new("", password)?;
+                    let initial =
+                        SASLInitialResponse::new(mechanism, client.client_first_message());
+                    self.backend.send_message(initial).await?;
+                    scram = Some(client);
+                }
+                StartupResponse::Authentication(Authentication::SASLContinue { data }) => {
+                    let client = scram.as_mut().ok_or("SASLContinue before SASL")?;
+                    let client_final = client.client_final_message(str::from_utf8(&data)?)?;
+                    self.backend
+                        .send_message(SASLResponse::new(client_final))
+                        .await?;
+                }
+                StartupResponse::Authentication(Authentication::SASLFinal { data }) => {
+                    let client = scram.as_ref().ok_or("SASLFinal before SASL")?;
+                    client.verify_server_final(str::from_utf8(&data)?)?;
+                    server_verified = true;
+                }
+                StartupResponse::ParameterStatus(ParameterStatus { name, value }) => {
+                    self.parameters.insert(name, value);
+                }
+                StartupResponse::BackendKeyData(key_data) => self.key_data = Some(key_data),
+                StartupResponse::ErrorResponse(error_response) => {
+                    return Err(error_response.into());
+                }
+                StartupResponse::Notice(notice) => {
+                    eprintln!("startup notice: {}: {}", notice.severity, notice.message);
+                }
+                StartupResponse::NegotiateProtocolVersion(_) => {}
+                StartupResponse::ReadyForQuery(_) => return Ok(()),
+            }
+        }
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    pub fn key_data(&self) -> Option<&BackendKeyData> {
+        self.key_data.as_ref()
+    }
+
+    /// Like [`Client::transaction`](crate::Client::transaction).
+    pub async fn transaction(&mut self) -> Result<AsyncTransaction<'_, S>, Box<dyn Error>> {
+        self.backend.transaction().await
+    }
+
+    pub async fn query(&mut self, query: &str) -> Result<Vec<DataRow>, Box<dyn Error>> {
+        let messages = self.simple_query(query).await?;
+
+        Ok(messages
+            .into_iter()
+            .filter_map(|message| match message {
+                BackendMessage::DataRow(data_row) => Some(data_row),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Runs `query` and decodes each row into a `T`.
+    pub async fn query_as<T: FromRow>(&mut self, query: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut description = None;
+        let mut rows = Vec::new();
+        for message in self.simple_query(query).await? {
+            match message {
+                BackendMessage::RowDescription(row_description) => {
+                    description = Some(row_description);
+                }
+                BackendMessage::DataRow(data_row) => {
+                    let description = description
+                        .as_ref()
+                        .ok_or("DataRow before RowDescription")?;
+                    rows.push(T::from_row(&Row::new(description, &data_row)?)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Asks the server whether the session is read-only, as it always is on
+    /// a hot standby.
+    pub async fn is_read_only(&mut self) -> Result<bool, Box<dyn Error>> {
+        let rows = self.query("SHOW transaction_read_only").await?;
+        match rows.first().and_then(|row| row.field(0)) {
+            Some(b"on") => Ok(true),
+            Some(b"off") => Ok(false),
+            value => Err(format!("unexpected transaction_read_only: {value:?}").into()),
+        }
+    }
+
+    /// Whether the server is a hot standby. Servers from PostgreSQL 14 on
+    /// report `in_hot_standby` at startup; older ones are asked.
+    pub async fn is_hot_standby(&mut self) -> Result<bool, Box<dyn Error>> {
+        if let Some(in_hot_standby) = self.parameter("in_hot_standby") {
+            return Ok(in_hot_standby == "on");
+        }
+
+        let rows = self.query("SELECT pg_is_in_recovery()").await?;
+        match rows.first().and_then(|row| row.field(0)) {
+            Some(b"t") => Ok(true),
+            Some(b"f") => Ok(false),
+            value => Err(format!("unexpected pg_is_in_recovery: {value:?}").into()),
+        }
+    }
+
+    /// Ends the session with a Termination and closes the connection.
+    pub async fn shutdown(mut self) -> Result<(), Box<dyn Error>> {
+        self.backend.shutdown().await
+    }
+
+    async fn simple_query(&mut self, query: &str) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        spans::query_async(query, self.run_simple_query(query)).await
+    }
+
+    async fn run_simple_query(
+        &mut self,
+        query: &str,
+    ) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        self.backend
+            .send_message(SimpleQuery::new_trusted(query))
+            .await?;
+
+        let mut messages = Vec::new();
+        let mut rows = 0;
+        let mut error = None;
+        let mut responses = self.backend.read_messages();
+        while let Some(message) = responses.next().await {
+            match &message {
+                BackendMessage::ParameterStatus(ParameterStatus { name, value }) => {
+                    self.parameters.insert(name.clone(), value.clone());
+                }
+                BackendMessage::BackendKeyData(key_data) => {
+                    self.key_data = Some(key_data.clone());
+                }
+                BackendMessage::CommandComplete(command_complete) => {
+                    rows = command_complete.rows_affected().unwrap_or_default();
+                }
+                BackendMessage::ErrorResponse(error_response) => {
+                    error = Some(error_response.clone());
+                }
+                _ => {}
+            }
+            messages.push(message);
+        }
+        spans::record_query(rows, error.as_ref().map(ErrorResponse::sqlstate));
+
+        if let Some(error) = error {
+            return Err(error.into());
+        }
+        if !matches!(messages.last(), Some(BackendMessage::ReadyForQuery(_))) {
+            return Err("connection closed before ReadyForQuery".into());
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, ReadyForQuery},
+            Message,
+        },
+        mock::MockServer,
+        state::TransactionStatus,
+    };
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::io::Cursor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(message);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn connect(responses: Vec<u8>) -> Result<(MockServer, AsyncClient), Box<dyn Error>> {
+        let (server, stream) = MockServer::start(responses);
+        stream.set_nonblocking(true)?;
+        let backend = AsyncBackend::new(TcpStream::from_std(stream)?);
+        Ok((server, AsyncClient::new(backend)))
+    }
+
+    fn complete(tag: &str, transaction_status: TransactionStatus) -> Vec<u8> {
+        let mut buffer = CommandComplete::builder().tag(tag).build().encode();
+        buffer.extend(ReadyForQuery { transaction_status }.encode());
+        buffer
+    }
+
+    // Reads one tagged message and returns its body.
+    async fn read_body(stream: &mut DuplexStream, tag: u8) -> Vec<u8> {
+        assert_eq!(stream.read_u8().await.unwrap(), tag);
+        let length = stream.read_u32().await.unwrap();
+        let mut body = vec![0; length as usize - 4];
+        stream.read_exact(&mut body).await.unwrap();
+        body
+    }
+
+    // The server side of a SCRAM-SHA-256 exchange, checking nothing but
+    // answering with the signature a server that knows `password` would.
+    // Without `send_final`, it skips SASLFinal and reports success anyway.
+    async fn scram_server(mut stream: DuplexStream, password: &str, send_final: bool) {
+        let length = stream.read_u32().await.unwrap();
+        let mut startup = vec![0; length as usize - 4];
+        stream.read_exact(&mut startup).await.unwrap();
+
+        let mechanisms = vec![
+            "SCRAM-SHA-256-PLUS".to_string(),
+            "SCRAM-SHA-256".to_string(),
+        ];
+        let offer = Authentication::SASL { mechanisms }.encode();
+        stream.write_all(&offer).await.unwrap();
+
+        let body = read_body(&mut stream, b'p').await;
+        let initial = SASLInitialResponse::read_next_message(&mut Cursor::new(body)).unwrap();
+        assert_eq!(initial.mechanism, "SCRAM-SHA-256");
+        let client_first = String::from_utf8(initial.data.unwrap()).unwrap();
+        let client_first_bare = client_first.strip_prefix("n,,").unwrap();
+        let nonce = client_first_bare.split_once(",r=").unwrap().1;
+
+        let salt = b"0123456789abcdef";
+        let server_first = format!("r={nonce}server,s={},i=4096", BASE64.encode(salt));
+        let data = server_first.clone().into_bytes();
+        let challenge = Authentication::SASLContinue { data }.encode();
+        stream.write_all(&challenge).await.unwrap();
+
+        let client_final = String::from_utf8(read_body(&mut stream, b'p').await).unwrap();
+        let without_proof = client_final.split_once(",p=").unwrap().0;
+        let auth_message = format!("{client_first_bare},{server_first},{without_proof}");
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, 4096, &mut salted_password);
+        let server_key = hmac(&salted_password, b"Server Key");
+        let signature = BASE64.encode(hmac(&server_key, auth_message.as_bytes()));
+
+        let data = format!("v={signature}").into_bytes();
+        let mut responses = Vec::new();
+        if send_final {
+            Authentication::SASLFinal { data }.encode_into(&mut responses);
+        }
+        responses.extend(Authentication::Ok.encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        stream.write_all(&responses).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_startup_scram() -> Result<(), Box<dyn Error>> {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let server = tokio::spawn(scram_server(server_stream, "pencil", true));
+
+        let mut client = AsyncClient::new(AsyncBackend::new(client_stream));
+        let mut startup = Startup::new();
+        startup.add_parameter("user", "postgres");
+        client.startup(startup, Some("pencil")).await?;
+        server.await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_startup_scram_wrong_password() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let server = tokio::spawn(scram_server(server_stream, "pencil", true));
+
+        let mut client = AsyncClient::new(AsyncBackend::new(client_stream));
+        let mut startup = Startup::new();
+        startup.add_parameter("user", "postgres");
+        let err = client.startup(startup, Some("crayon")).await.unwrap_err();
+        assert_eq!(err.to_string(), "server signature does not match");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_startup_scram_without_server_final() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let server = tokio::spawn(scram_server(server_stream, "pencil", false));
+
+        let mut client = AsyncClient::new(AsyncBackend::new(client_stream));
+        let mut startup = Startup::new();
+        startup.add_parameter("user", "postgres");
+        let err = client.startup(startup, Some("pencil")).await.unwrap_err();
+        assert_eq!(err.to_string(), "server skipped its SCRAM signature");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transaction_commit() -> Result<(), Box<dyn Error>> {
+        let mut responses = complete("BEGIN", TransactionStatus::InTransaction);
+        responses.extend(complete("COMMIT", TransactionStatus::Idle));
+        let (server, mut client) = connect(responses)?;
+
+        let transaction = client.transaction().await?;
+        transaction.commit().await?;
+        drop(client);
+
+        let mut expected = SimpleQuery::new("BEGIN").encode();
+        expected.extend(SimpleQuery::new("COMMIT").encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transaction_rollback() -> Result<(), Box<dyn Error>> {
+        let mut responses = complete("BEGIN", TransactionStatus::InTransaction);
+        responses.extend(complete("ROLLBACK", TransactionStatus::Idle));
+        let (server, mut client) = connect(responses)?;
+
+        let transaction = client.transaction().await?;
+        transaction.rollback().await?;
+        drop(client);
+
+        let mut expected = SimpleQuery::new("BEGIN").encode();
+        expected.extend(SimpleQuery::new("ROLLBACK").encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+use std::error::Error;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::{state::Authentication, ProtocolError};
+
+// RFC 7677 asks for at least 4096 iterations; fewer make the stored key
+// cheap to brute-force.
+const MIN_SCRAM_ITERATIONS: u32 = 4096;
+
+// PostgreSQL defaults to 4096. Each iteration is an HMAC, so a server
+// asking for billions could keep the client busy for minutes.
+const DEFAULT_MAX_SCRAM_ITERATIONS: u32 = 100_000;
+
+/// The authentication methods that [`AsyncClient`](crate::AsyncClient)
+/// can answer, named as by [`Authentication::describe`]. The sync
+/// [`Backend::startup`](crate::Backend::startup) only accepts trust.
+pub fn supported_auth_methods() -> &'static [&'static str] {
+    &[
+        "trust (ok)",
+        "cleartext password",
+        "MD5 password",
+        "SCRAM-SHA-256",
+    ]
+}
+
+/// The SASL mechanism to answer an AuthenticationSASL with, from those the
+/// server `offered`. SCRAM-SHA-256-PLUS is preferred when there is a TLS
+/// channel to bind to, and is useless without one, so a server that only
+/// offers it to a client without TLS gets None.
+pub fn select_sasl_mechanism(offered: &[String], channel_binding_available: bool) -> Option<&str> {
+    let preferred: &[&str] = if channel_binding_available {
+        &["SCRAM-SHA-256-PLUS", "SCRAM-SHA-256"]
+    } else {
+        &["SCRAM-SHA-256"]
+    };
+    preferred.iter().find_map(|mechanism| {
+        offered
+            .iter()
+            .find(|offered| offered == mechanism)
+            .map(String::as_str)
+    })
+}
+
+// The error for an authentication request that the client cannot answer.
+pub(crate) fn unsupported_auth(requested: &Authentication, supported: &[&str]) -> String {
+    format!(
+        "server asked for {} authentication, but the client supports only {}",
+        requested.describe(),
+        supported.join(", ")
+    )
+}
+
+// The form PostgreSQL stores in pg_authid: "md5" followed by the hex
+// digest of the password concatenated with the user name.
+pub fn md5_hash(user: &str, password: &str) -> String {
+    format!(
+        "md5{}",
+        md5_hex(&[password.as_bytes(), user.as_bytes()].concat())
+    )
+}
+
+// The response to an MD5Password challenge, computed from a stored hash.
+pub fn md5_salted(hash: &str, salt: [u8; 4]) -> String {
+    let hash = hash.strip_prefix("md5").unwrap_or(hash);
+    format!("md5{}", md5_hex(&[hash.as_bytes(), &salt].concat()))
+}
+
+fn md5_hex(bytes: &[u8]) -> String {
+    Md5::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The client side of a SCRAM-SHA-256 exchange (RFC 5802, RFC 7677),
+/// without channel binding. The password is used as given, without
+/// SASLprep, which makes no difference for ASCII passwords.
+#[derive(Clone)]
+pub struct ScramClient {
+    user: String,
+    password: String,
+    nonce: String,
+    max_iterations: u32,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl ScramClient {
+    /// PostgreSQL ignores the SCRAM user name in favour of the one sent at
+    /// startup, so `user` may be empty.
+    pub fn new(user: &str, password: &str) -> Result<Self, Box<dyn Error>> {
+        let mut nonce = [0; 18];
+        getrandom::getrandom(&mut nonce)?;
+        Ok(Self::with_nonce(user, password, &BASE64.encode(nonce)))
+    }
+
+    /// Like `new`, but with a chosen client nonce rather than a random one,
+    /// so that tests can reproduce known exchanges. The nonce must be
+    /// printable ASCII without commas.
+    pub fn with_nonce(user: &str, password: &str, nonce: &str) -> Self {
+        Self {
+            user: user.replace('=', "=3D").replace(',', "=2C"),
+            password: password.to_string(),
+            nonce: nonce.to_string(),
+            max_iterations: DEFAULT_MAX_SCRAM_ITERATIONS,
+            server_signature: None,
+        }
+    }
+
+    /// The most PBKDF2 iterations the server may ask for, 100,000 unless
+    /// set. Servers configured with a higher `scram_iterations` need it
+    /// raised.
+    pub fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// The client-first-message, sent in SASLInitialResponse.
+    pub fn client_first_message(&self) -> String {
+        format!("n,,{}", self.client_first_message_bare())
+    }
+
+    fn client_first_message_bare(&self) -> String {
+        format!("n={},r={}", self.user, self.nonce)
+    }
+
+    /// The client-final-message answering the server-first-message from
+    /// AuthenticationSASLContinue.
+    pub fn client_final_message(&mut self, server_first: &str) -> Result<String, Box<dyn Error>> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attribute in server_first.split(',') {
+            match attribute.split_once('=') {
+                Some(("r", value)) => nonce = Some(value),
+                Some(("s", value)) => salt = Some(decode_salt(value)?),
+                Some(("i", value)) => iterations = Some(self.check_iterations(value)?),
+                _ => {}
+            }
+        }
+        let (Some(nonce), Some(salt), Some(iterations)) = (nonce, salt, iterations) else {
+            return Err(format!("malformed server-first-message: {server_first:?}").into());
+        };
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err("server nonce does not extend the client nonce".into());
+        }
+
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(
+            self.password.as_bytes(),
+            &salt,
+            iterations,
+            &mut salted_password,
+        );
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+        let server_key = hmac_sha256(&salted_password, b"Server Key");
+
+        // "biws" is the base64 of the "n,," GS2 header.
+        let without_proof = format!("c=biws,r={nonce}");
+        let auth_message = format!(
+            "{},{server_first},{without_proof}",
+            self.client_first_message_bare()
+        );
+        let client_signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(&client_signature)
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+        self.server_signature = Some(hmac_sha256(&server_key, auth_message.as_bytes()));
+
+        Ok(format!("{without_proof},p={}", BASE64.encode(proof)))
+    }
+
+    fn check_iterations(&self, value: &str) -> Result<u32, ProtocolError> {
+        match value.parse::<u32>() {
+            Ok(iterations)
+                if (MIN_SCRAM_ITERATIONS..=self.max_iterations).contains(&iterations) =>
+            {
+                Ok(iterations)
+            }
+            _ => Err(ProtocolError::ScramParameterOutOfRange {
+                name: "i",
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    /// Checks the server-final-message from AuthenticationSASLFinal, which
+    /// proves that the server knows the password too.
+    pub fn verify_server_final(&self, server_final: &str) -> Result<(), Box<dyn Error>> {
+        let Some(expected) = &self.server_signature else {
+            return Err("server-final-message before client-final-message".into());
+        };
+        if let Some(error) = server_final.strip_prefix("e=") {
+            return Err(format!("SCRAM authentication failed: {error}").into());
+        }
+        let signature = server_final
+            .strip_prefix("v=")
+            .ok_or_else(|| format!("malformed server-final-message: {server_final:?}"))?;
+        if BASE64.decode(signature)? != *expected {
+            return Err("server signature does not match".into());
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ScramClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScramClient")
+            .field("user", &self.user)
+            .field("password", &"***")
+            .field("nonce", &self.nonce)
+            .field("max_iterations", &self.max_iterations)
+            .finish()
+    }
+}
+
+fn decode_salt(value: &str) -> Result<Vec<u8>, ProtocolError> {
+    match BASE64.decode(value) {
+        Ok(salt) if !salt.is_empty() => Ok(salt),
+        _ => Err(ProtocolError::ScramParameterOutOfRange {
+            name: "s",
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[test]
+fn test_select_sasl_mechanism() {
+    let offered = |mechanisms: &[&str]| -> Vec<String> {
+        mechanisms
+            .iter()
+            .map(|mechanism| mechanism.to_string())
+            .collect()
+    };
+    let both = offered(&["SCRAM-SHA-256", "SCRAM-SHA-256-PLUS"]);
+    let plain = offered(&["SCRAM-SHA-256"]);
+    let plus = offered(&["SCRAM-SHA-256-PLUS"]);
+
+    assert_eq!(
+        select_sasl_mechanism(&both, true),
+        Some("SCRAM-SHA-256-PLUS")
+    );
+    assert_eq!(select_sasl_mechanism(&both, false), Some("SCRAM-SHA-256"));
+    assert_eq!(select_sasl_mechanism(&plain, true), Some("SCRAM-SHA-256"));
+    assert_eq!(select_sasl_mechanism(&plain, false), Some("SCRAM-SHA-256"));
+    assert_eq!(
+        select_sasl_mechanism(&plus, true),
+        Some("SCRAM-SHA-256-PLUS")
+    );
+    assert_eq!(select_sasl_mechanism(&plus, false), None);
+    assert_eq!(
+        select_sasl_mechanism(&offered(&["OAUTHBEARER"]), true),
+        None
+    );
+}
+
+#[test]
+fn test_unsupported_auth() {
+    assert_eq!(
+        unsupported_auth(
+            &Authentication::SASL {
+                mechanisms: vec!["SCRAM-SHA-256".to_string()]
+            },
+            supported_auth_methods()
+        ),
+        "server asked for SCRAM-SHA-256 authentication, but the client supports only \
+         trust (ok), cleartext password, MD5 password, SCRAM-SHA-256"
+    );
+}
+
+#[test]
+fn test_md5_hash() {
+    assert_eq!(
+        md5_hash("postgres", "postgres"),
+        "md53175bce1d3201d16594cebf9d7eb3f9d"
+    );
+}
+
+#[test]
+fn test_md5_salted() {
+    let hash = md5_hash("postgres", "postgres");
+    let salted = md5_salted(&hash, [0x01, 0x02, 0x03, 0x04]);
+    assert_eq!(salted.len(), 35);
+    assert!(salted.starts_with("md5"));
+    assert_eq!(salted, md5_salted(&hash[3..], [0x01, 0x02, 0x03, 0x04]));
+    assert_ne!(salted, md5_salted(&hash, [0x04, 0x03, 0x02, 0x01]));
+}
+
+// The example exchange from RFC 7677, section 3.
+#[test]
+fn test_scram_rfc7677() -> Result<(), Box<dyn Error>> {
+    let mut scram = ScramClient::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    assert_eq!(
+        scram.client_first_message(),
+        "n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+    );
+
+    let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                        s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    assert_eq!(
+        scram.client_final_message(server_first)?,
+        "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+         p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+    );
+    scram.verify_server_final("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")?;
+    assert!(scram
+        .verify_server_final("v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_scram_random_nonce() -> Result<(), Box<dyn Error>> {
+    let first = ScramClient::new("", "pencil")?.client_first_message();
+    let second = ScramClient::new("", "pencil")?.client_first_message();
+    assert!(first.starts_with("n,,n=,r="));
+    assert_eq!(first.len(), "n,,n=,r=".len() + 24);
+    assert_ne!(first, second);
+
+    Ok(())
+}
+
+#[test]
+fn test_scram_rejects_foreign_nonce() {
+    let mut scram = ScramClient::with_nonce("", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    let server_first = "r=somethingElse,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    assert!(scram.client_final_message(server_first).is_err());
+}
+
+#[test]
+fn test_scram_iteration_limits() -> Result<(), Box<dyn Error>> {
+    let server_first = |iterations: &str| {
+        format!("r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa,s=W22ZaJ0SNY7soEsUEjb6gQ==,i={iterations}")
+    };
+    let out_of_range = |iterations: &str| ProtocolError::ScramParameterOutOfRange {
+        name: "i",
+        value: iterations.to_string(),
+    };
+    let mut scram = ScramClient::with_nonce("", "pencil", "rOprNGfwEbeRWgbNEkqO");
+
+    for iterations in ["4000000000", "100001", "4095", "-1"] {
+        let err = scram
+            .client_final_message(&server_first(iterations))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&out_of_range(iterations))
+        );
+    }
+
+    let mut scram = scram.max_iterations(200_000);
+    scram.client_final_message(&server_first("100001"))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_scram_rejects_invalid_salt() {
+    let mut scram = ScramClient::with_nonce("", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    for salt in ["not base64!", ""] {
+        let server_first = format!("r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa,s={salt},i=4096");
+        let err = scram.client_final_message(&server_first).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::ScramParameterOutOfRange {
+                name: "s",
+                value: salt.to_string(),
+            })
+        );
+    }
+}
@@ -5,71 +5,405 @@ use core::{
 };
 use futures_core::stream::Stream;
 use std::{
+    collections::VecDeque,
     error::Error,
-    ops::DerefMut,
+    ops::{Deref, DerefMut},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        Arc,
     },
 };
 use tokio::{
-    io::BufReader,
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
+    io::{
+        self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader, ReadBuf, ReadHalf, WriteHalf,
     },
+    net::TcpStream,
+    sync::Mutex,
 };
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+use tokio_stream::StreamExt;
 
-use crate::messages::{
-    backend::BackendMessage, ssl::SSLResponse, startup::StartupResponse, Message,
+use super::{
+    counter::CountingReader,
+    sync::{check_message_size, DEFAULT_MAX_MESSAGE_SIZE},
+};
+use crate::{
+    messages::{
+        backend::{BackendMessage, ErrorResponse, NotificationResponse, ReadyForQuery},
+        copy::CopyDone,
+        frontend::{SimpleQuery, Termination},
+        ssl::{SSLRequest, SSLResponse},
+        startup::StartupResponse,
+        Message,
+    },
+    state::TransactionStatus,
 };
 
 #[derive(Debug)]
-pub struct AsyncBackend {
-    reader: Arc<Mutex<BufReader<OwnedReadHalf>>>,
-    writer: Arc<Mutex<OwnedWriteHalf>>,
+pub struct AsyncBackend<S = TcpStream> {
+    reader: Arc<Mutex<BufReader<CountingReader<ReadHalf<S>>>>>,
+    writer: Arc<Mutex<WriteHalf<S>>>,
+    bytes_sent: AtomicU64,
+    bytes_received: Arc<AtomicU64>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    max_message_size: u32,
+}
+
+// Messages read by one stream that belong to the other: notifications that
+// arrive in the middle of a query response, and query responses read while
+// waiting for notifications.
+#[derive(Debug, Default)]
+struct Pending {
+    messages: VecDeque<BackendMessage>,
+    notifications: VecDeque<NotificationResponse>,
+}
+
+pub type AsyncTlsBackend = AsyncBackend<TlsStream<TcpStream>>;
+
+/// A connection that uses TLS if the server agreed to it, as with libpq's
+/// `sslmode=prefer`.
+#[derive(Debug)]
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl MaybeTlsStream {
+    /// Sends an SSLRequest and performs the TLS handshake if the server
+    /// answers S. If it answers N, the connection carries on in plaintext.
+    pub async fn prefer_tls(
+        mut stream: TcpStream,
+        domain: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self, Box<dyn Error>> {
+        stream.write_all(&SSLRequest.encode()).await?;
+        match stream.read_u8().await? {
+            b'S' => {
+                let domain = ServerName::try_from(domain.to_string())?;
+                let stream = TlsConnector::from(config).connect(domain, stream).await?;
+                Ok(MaybeTlsStream::Tls(Box::new(stream)))
+            }
+            b'N' => Ok(MaybeTlsStream::Plain(stream)),
+            response => Err(format!("unexpected SSLRequest response: {response}").into()),
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        matches!(self, MaybeTlsStream::Tls(_))
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
 }
 
 impl AsyncBackend {
-    pub fn new(stream: TcpStream) -> Self {
-        let (reader, writer) = stream.into_split();
+    // Performs the TLS handshake once the server has answered an SSLRequest
+    // with SSLResponse::S. Fails if any message streams are still alive.
+    pub async fn upgrade_tls(
+        self,
+        domain: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<AsyncTlsBackend, Box<dyn Error>> {
+        let reader = Arc::try_unwrap(self.reader)
+            .map_err(|_| "backend reader is still in use")?
+            .into_inner();
+        let writer = Arc::try_unwrap(self.writer)
+            .map_err(|_| "backend writer is still in use")?
+            .into_inner();
+
+        // Anything buffered here arrived unencrypted after the SSLResponse.
+        if !reader.buffer().is_empty() {
+            return Err("received unencrypted data after SSLResponse".into());
+        }
+
+        let stream = reader.into_inner().into_inner().unsplit(writer);
+        let domain = ServerName::try_from(domain.to_string())?;
+        let stream = TlsConnector::from(config).connect(domain, stream).await?;
+
+        // Keep counting across the upgrade; the handshake itself is not
+        // included.
+        let mut backend = AsyncBackend::new(stream);
+        backend.bytes_sent = self.bytes_sent;
+        backend.bytes_received = self.bytes_received;
+        backend.pending = self.pending;
+        Ok(backend)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncBackend<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = io::split(stream);
+        let bytes_received = Arc::new(AtomicU64::new(0));
+        let reader = CountingReader::new(reader, bytes_received.clone());
         Self {
             reader: Arc::new(Mutex::new(BufReader::new(reader))),
             writer: Arc::new(Mutex::new(writer)),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received,
+            pending: Arc::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
 
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes read from the stream, including any still buffered and not
+    /// yet decoded.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Like [`Backend::max_message_size`](super::Backend::max_message_size).
+    pub fn max_message_size(&self) -> u32 {
+        self.max_message_size
+    }
+
+    pub fn set_max_message_size(&mut self, max_message_size: u32) {
+        self.max_message_size = max_message_size;
+    }
+
     pub async fn send_message(
         &mut self,
         message: impl Message + std::fmt::Debug,
     ) -> Result<(), Box<dyn Error>> {
         println!("Backend send_message: {message:?}");
-        let mut message = message.encode();
 
-        loop {
-            let writer = self.writer.lock().unwrap();
-            writer.writable().await?;
+        let buffer = message.try_encode()?;
+        check_message_size(buffer.len(), self.max_message_size)?;
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&buffer).await?;
+        self.bytes_sent
+            .fetch_add(buffer.len() as u64, Ordering::Relaxed);
+        if message.requires_flush() {
+            writer.flush().await?;
+        }
+        Ok(())
+    }
 
-            match writer.try_write(&message) {
-                Ok(n) => {
-                    message = (&message[n..]).to_vec();
-                    if message.is_empty() {
+    /// Like [`Backend::send_and_collect`](super::Backend::send_and_collect).
+    /// Notifications that arrive in the meantime are kept for
+    /// [`notifications`](Self::notifications).
+    pub async fn send_and_collect(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        self.send_message(message).await?;
+
+        let mut messages = Vec::new();
+        loop {
+            let pending = self.pending.lock().unwrap().messages.pop_front();
+            let message = match pending {
+                Some(message) => message,
+                None => {
+                    let mut reader = self.reader.lock().await;
+                    if reader.fill_buf().await?.is_empty() {
                         break;
                     }
+                    match BackendMessage::read_next_message_async(&mut *reader).await? {
+                        BackendMessage::NotificationResponse(notification) => {
+                            let mut pending = self.pending.lock().unwrap();
+                            pending.notifications.push_back(notification);
+                            continue;
+                        }
+                        message => message,
+                    }
                 }
-                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(err) => {
-                    return Err(err.into());
+            };
+            let last = match &message {
+                BackendMessage::ReadyForQuery(_) => true,
+                BackendMessage::ErrorResponse(error) => error.is_fatal(),
+                _ => false,
+            };
+            messages.push(message);
+            if last {
+                break;
+            }
+        }
+        Ok(messages)
+    }
+
+    pub async fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_message(Termination).await?;
+        self.writer.lock().await.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn transaction(&mut self) -> Result<AsyncTransaction<'_, S>, Box<dyn Error>> {
+        self.transaction_command("BEGIN").await?;
+        Ok(AsyncTransaction {
+            backend: self,
+            finished: false,
+        })
+    }
+
+    async fn transaction_command(
+        &mut self,
+        command: &str,
+    ) -> Result<TransactionStatus, Box<dyn Error>> {
+        self.send_message(SimpleQuery::new_trusted(command)).await?;
+
+        let mut error = None;
+        let mut messages = self.read_messages();
+        while let Some(message) = messages.next().await {
+            match message {
+                BackendMessage::ErrorResponse(error_response) => error = Some(error_response),
+                BackendMessage::ReadyForQuery(ReadyForQuery { transaction_status }) => {
+                    if let Some(error) = error {
+                        return Err(error.into());
+                    }
+                    return Ok(transaction_status);
                 }
+                _ => {}
             }
         }
+
+        Err(format!("{command}: connection closed before ReadyForQuery").into())
+    }
+
+    /// Sends one chunk of a COPY FROM STDIN straight from `chunk`, without
+    /// building a message. The write waits for the socket to become
+    /// writable, so a slow server holds back the caller instead of input
+    /// piling up in memory. A chunk too big for one message under
+    /// `max_message_size` goes out as several.
+    ///
+    /// If the server has already aborted the copy, nothing is sent and its
+    /// ErrorResponse is returned.
+    pub async fn send_copy_data(&mut self, chunk: &[u8]) -> Result<(), Box<dyn Error>> {
+        if let Some(error) = self.copy_error().await? {
+            return Err(error.into());
+        }
+
+        let piece_size = (self.max_message_size as usize)
+            .checked_sub(5)
+            .filter(|&size| size > 0)
+            .ok_or("max_message_size leaves no room for COPY data")?;
+
+        let mut writer = self.writer.lock().await;
+        for piece in chunk.chunks(piece_size) {
+            let length = u32::try_from(piece.len() + 4).map_err(|_| "CopyData chunk too long")?;
+            let mut header = [b'd', 0, 0, 0, 0];
+            header[1..].copy_from_slice(&length.to_be_bytes());
+
+            writer.write_all(&header).await?;
+            writer.write_all(piece).await?;
+            self.bytes_sent
+                .fetch_add(header.len() as u64 + piece.len() as u64, Ordering::Relaxed);
+        }
         Ok(())
     }
 
+    /// Ends a COPY FROM STDIN and returns the number of rows copied.
+    pub async fn send_copy_done(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.send_message(CopyDone).await?;
+
+        let mut rows = 0;
+        let mut error = None;
+        let mut messages = self.read_messages();
+        while let Some(message) = messages.next().await {
+            match message {
+                BackendMessage::CommandComplete(command_complete) => {
+                    rows = command_complete.rows_affected().unwrap_or_default();
+                }
+                BackendMessage::ErrorResponse(error_response) => {
+                    error.get_or_insert(error_response);
+                }
+                BackendMessage::ReadyForQuery(_) => {
+                    return match error {
+                        Some(error) => Err(error.into()),
+                        None => Ok(rows),
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        Err("COPY: connection closed before ReadyForQuery".into())
+    }
+
+    // Checks, without waiting, whether the server has said anything since
+    // the copy started. The only thing that matters is an ErrorResponse,
+    // which is returned once the rest of the response up to ReadyForQuery
+    // has been read.
+    async fn copy_error(&mut self) -> Result<Option<ErrorResponse>, Box<dyn Error>> {
+        loop {
+            let mut reader = self.reader.lock().await;
+            let pending =
+                std::future::poll_fn(|cx| match Pin::new(&mut *reader).poll_fill_buf(cx) {
+                    Poll::Ready(Ok([])) => Poll::Ready(Err("connection closed during COPY".into())),
+                    Poll::Ready(Ok(_)) => Poll::Ready(Ok(false)),
+                    Poll::Ready(Err(err)) => Poll::Ready(Err(Box::<dyn Error>::from(err))),
+                    Poll::Pending => Poll::Ready(Ok(true)),
+                })
+                .await?;
+            if pending {
+                return Ok(None);
+            }
+
+            match BackendMessage::read_next_message_async(&mut *reader).await? {
+                BackendMessage::ErrorResponse(error_response) => {
+                    drop(reader);
+                    let mut messages = self.read_messages();
+                    while messages.next().await.is_some() {}
+                    return Ok(Some(error_response));
+                }
+                BackendMessage::NotificationResponse(notification) => {
+                    let mut pending = self.pending.lock().unwrap();
+                    pending.notifications.push_back(notification);
+                }
+                BackendMessage::NoticeMessage(_) | BackendMessage::ParameterStatus(_) => {}
+                message => {
+                    return Err(format!("unexpected message during COPY: {message:?}").into())
+                }
+            }
+        }
+    }
+
     pub async fn read_ssl_message(&mut self) -> Result<SSLResponse, Box<dyn Error>> {
-        let mut reader = self.reader.lock().unwrap();
+        let mut reader = self.reader.lock().await;
         match SSLResponse::read_next_message_async(reader.deref_mut()).await {
             Ok(message) => {
                 println!("Backend read_ssl_message: {message:?}");
@@ -77,92 +411,494 @@ impl AsyncBackend {
             }
             Err(err) => {
                 println!("error reading backend message: {err}");
-                Err(err.into())
+                Err(err)
             }
         }
     }
 
     pub fn read_startup_messages(&mut self) -> impl Stream<Item = StartupResponse> {
-        struct MessageIterator {
-            reader: Arc<Mutex<BufReader<OwnedReadHalf>>>,
-            finished: Arc<AtomicBool>,
+        ReadStream {
+            reader: self.reader.clone(),
+            pending: self.pending.clone(),
+            read: |reader, _| {
+                Box::pin(async move {
+                    let mut reader = reader.lock().await;
+                    StartupResponse::read_next_message_async(&mut *reader)
+                        .await?
+                        .ok_or_else(|| "unsupported startup response".into())
+                })
+            },
+            take_pending: |_| None,
+            is_last: |message| matches!(message, StartupResponse::ReadyForQuery(_)),
+            future: None,
+            finished: false,
         }
-        impl Stream for MessageIterator {
-            type Item = StartupResponse;
-
-            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-                if self.finished.load(Ordering::Relaxed) {
-                    return Poll::Ready(None);
-                }
+    }
 
-                let mut reader = self.reader.lock().unwrap();
-                let mut future = StartupResponse::read_next_message_async(&mut *reader);
-                let x = match std::pin::pin!(future).poll(cx) {
-                    Poll::Ready(Ok(Some(item))) => {
-                        if let StartupResponse::ReadyForQuery(_) = item {
-                            self.finished.store(true, Ordering::Relaxed);
-                        };
-                        Poll::Ready(Some(item))
-                    }
-                    Poll::Ready(Ok(None)) => {
-                        self.finished.store(true, Ordering::Relaxed);
-                        Poll::Ready(None)
+    /// Reads the response to a query, up to ReadyForQuery. Notifications
+    /// that arrive in the meantime are kept for [`notifications`](Self::notifications).
+    pub fn read_messages(&mut self) -> impl Stream<Item = BackendMessage> {
+        ReadStream {
+            reader: self.reader.clone(),
+            pending: self.pending.clone(),
+            read: |reader, pending| {
+                Box::pin(async move {
+                    let mut reader = reader.lock().await;
+                    loop {
+                        match BackendMessage::read_next_message_async(&mut *reader).await? {
+                            BackendMessage::NotificationResponse(notification) => {
+                                let mut pending = pending.lock().unwrap();
+                                pending.notifications.push_back(notification);
+                            }
+                            message => return Ok(message),
+                        }
                     }
-                    Poll::Ready(Err(err)) => {
-                        self.finished.store(true, Ordering::Relaxed);
-                        //Poll::Ready(Err(err.into()))
-                        eprintln!("error reading backend startup message: {err}");
-                        Poll::Ready(None)
+                })
+            },
+            take_pending: |pending| pending.messages.pop_front(),
+            is_last: |message| matches!(message, BackendMessage::ReadyForQuery(_)),
+            future: None,
+            finished: false,
+        }
+    }
+
+    /// Waits for notifications on an otherwise idle connection, such as
+    /// after a `LISTEN`. Anything else that arrives is kept for the next
+    /// [`read_messages`](Self::read_messages). The stream ends when the
+    /// connection does.
+    pub fn notifications(&mut self) -> impl Stream<Item = NotificationResponse> {
+        ReadStream {
+            reader: self.reader.clone(),
+            pending: self.pending.clone(),
+            read: |reader, pending| {
+                Box::pin(async move {
+                    let mut reader = reader.lock().await;
+                    loop {
+                        match BackendMessage::read_next_message_async(&mut *reader).await? {
+                            BackendMessage::NotificationResponse(notification) => {
+                                return Ok(notification);
+                            }
+                            message => pending.lock().unwrap().messages.push_back(message),
+                        }
                     }
-                    Poll::Pending => Poll::Pending,
+                })
+            },
+            take_pending: |pending| pending.notifications.pop_front(),
+            is_last: |_| false,
+            future: None,
+            finished: false,
+        }
+    }
+}
+
+type Reader<S> = Arc<Mutex<BufReader<CountingReader<ReadHalf<S>>>>>;
+type ReadFuture<T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + Send>>;
+
+// Reads one message at a time for the backend's streams. As with
+// AsyncFrontend's stream, a read that is waiting for the rest of a message
+// is kept between polls, since starting it over would lose the part it has
+// already consumed. It also waits its turn for the reader, rather than
+// polling for it.
+struct ReadStream<S, T> {
+    reader: Reader<S>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    read: fn(Reader<S>, Arc<std::sync::Mutex<Pending>>) -> ReadFuture<T>,
+    // Takes a message that the other stream set aside.
+    take_pending: fn(&mut Pending) -> Option<T>,
+    is_last: fn(&T) -> bool,
+    future: Option<ReadFuture<T>>,
+    finished: bool,
+}
+
+impl<S, T> Stream for ReadStream<S, T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
+        let pending = match this.future {
+            Some(_) => None,
+            None => (this.take_pending)(&mut this.pending.lock().unwrap()),
+        };
+        let result = match pending {
+            Some(item) => Ok(item),
+            None => {
+                let future = this
+                    .future
+                    .get_or_insert_with(|| (this.read)(this.reader.clone(), this.pending.clone()));
+                let Poll::Ready(result) = future.as_mut().poll(cx) else {
+                    return Poll::Pending;
                 };
-                x
+                this.future = None;
+                result
+            }
+        };
+
+        match result {
+            Ok(item) => {
+                this.finished = (this.is_last)(&item);
+                Poll::Ready(Some(item))
+            }
+            Err(err) => {
+                this.finished = true;
+                eprintln!("error reading backend message: {err}");
+                Poll::Ready(None)
             }
         }
+    }
+}
 
-        MessageIterator {
-            reader: self.reader.clone(),
-            finished: Arc::new(AtomicBool::new(false)),
+/// A transaction opened by [`AsyncBackend::transaction`].
+///
+/// Rust has no async `Drop`, so the guard cannot roll back on its own.
+/// Callers must finish it with [`commit`](Self::commit) or
+/// [`rollback`](Self::rollback); dropping an open guard leaves the
+/// connection inside the transaction and logs a warning.
+#[derive(Debug)]
+pub struct AsyncTransaction<'a, S = TcpStream> {
+    backend: &'a mut AsyncBackend<S>,
+    finished: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncTransaction<'_, S> {
+    pub async fn commit(mut self) -> Result<(), Box<dyn Error>> {
+        self.finished = true;
+        self.backend.transaction_command("COMMIT").await?;
+        Ok(())
+    }
+
+    pub async fn rollback(mut self) -> Result<(), Box<dyn Error>> {
+        self.finished = true;
+        self.backend.transaction_command("ROLLBACK").await?;
+        Ok(())
+    }
+}
+
+impl<S> Deref for AsyncTransaction<'_, S> {
+    type Target = AsyncBackend<S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.backend
+    }
+}
+
+impl<S> DerefMut for AsyncTransaction<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.backend
+    }
+}
+
+impl<S> Drop for AsyncTransaction<'_, S> {
+    fn drop(&mut self) {
+        if !self.finished {
+            eprintln!("transaction dropped without commit or rollback");
         }
     }
+}
 
-    pub fn read_messages(&mut self) -> impl Stream<Item = BackendMessage> {
-        struct MessageIterator {
-            reader: Arc<Mutex<BufReader<OwnedReadHalf>>>,
-            finished: Arc<AtomicBool>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{backend::CommandComplete, copy::CopyData},
+        mock::MockServer,
+    };
+
+    fn connect(responses: Vec<u8>) -> Result<(MockServer, AsyncBackend), Box<dyn Error>> {
+        let (server, stream) = MockServer::start(responses);
+        stream.set_nonblocking(true)?;
+        Ok((server, AsyncBackend::new(TcpStream::from_std(stream)?)))
+    }
+
+    fn complete(tag: &str, transaction_status: TransactionStatus) -> Vec<u8> {
+        let mut buffer = CommandComplete::builder().tag(tag).build().encode();
+        buffer.extend(ReadyForQuery { transaction_status }.encode());
+        buffer
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_sends_termination() -> Result<(), Box<dyn Error>> {
+        let (server, mut backend) = connect(vec![])?;
+
+        backend.shutdown().await?;
+
+        assert_eq!(server.received(), Termination.encode());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_and_collect() -> Result<(), Box<dyn Error>> {
+        let (server, mut backend) = connect(complete("SELECT 0", TransactionStatus::Idle))?;
+
+        let messages = backend.send_and_collect(SimpleQuery::new("SELECT")).await?;
+        assert_eq!(messages.len(), 2);
+        assert!(backend.send_and_collect(Termination).await?.is_empty());
+        drop(backend);
+
+        let mut expected = SimpleQuery::new("SELECT").encode();
+        expected.extend(Termination.encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_and_collect_truncated_message() -> Result<(), Box<dyn Error>> {
+        let mut responses = complete("SELECT 0", TransactionStatus::Idle);
+        responses.truncate(responses.len() - 1);
+        let (_server, mut backend) = connect(responses)?;
+
+        let result = backend.send_and_collect(SimpleQuery::new("SELECT")).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_messages_split_across_writes() -> Result<(), Box<dyn Error>> {
+        let (stream, mut server) = io::duplex(1024);
+        let mut backend = AsyncBackend::new(stream);
+
+        // The first write ends partway through CommandComplete's body.
+        let responses = complete("SELECT 0", TransactionStatus::Idle);
+        let (first, rest) = responses.split_at(8);
+        let (first, rest) = (first.to_vec(), rest.to_vec());
+        let server = tokio::spawn(async move {
+            server.write_all(&first).await?;
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            server.write_all(&rest).await?;
+            io::Result::Ok(server)
+        });
+
+        let messages = backend.read_messages().collect::<Vec<_>>();
+        let messages = tokio::time::timeout(std::time::Duration::from_secs(5), messages).await?;
+        assert_eq!(
+            messages,
+            vec![
+                BackendMessage::CommandComplete(CommandComplete::builder().tag("SELECT 0").build()),
+                BackendMessage::ReadyForQuery(ReadyForQuery {
+                    transaction_status: TransactionStatus::Idle
+                }),
+            ]
+        );
+        server.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_byte_counters() -> Result<(), Box<dyn Error>> {
+        let responses = complete("SELECT 0", TransactionStatus::Idle);
+        let (_server, mut backend) = connect(responses.clone())?;
+
+        let query = SimpleQuery::new("SELECT");
+        let expected = query.encode().len() as u64;
+        backend.send_message(query).await?;
+        assert_eq!(backend.bytes_sent(), expected);
+
+        let messages: Vec<_> = backend.read_messages().collect().await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(backend.bytes_received(), responses.len() as u64);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_copy_data() -> Result<(), Box<dyn Error>> {
+        use tokio::{io::AsyncReadExt, net::TcpListener};
+
+        let chunks: Vec<Vec<u8>> = (0..10).map(|i| format!("{i}\n").into_bytes()).collect();
+        let mut expected: Vec<u8> = chunks
+            .iter()
+            .flat_map(|chunk| CopyData::new(chunk.clone()).encode())
+            .collect();
+        expected.extend(CopyDone.encode());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let length = expected.len();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Nothing is answered until the whole copy has arrived.
+            let mut received = vec![0; length];
+            stream.read_exact(&mut received).await.unwrap();
+            stream
+                .write_all(&complete("COPY 10", TransactionStatus::Idle))
+                .await
+                .unwrap();
+            received
+        });
+
+        let mut backend = AsyncBackend::new(TcpStream::connect(address).await?);
+        for chunk in &chunks {
+            backend.send_copy_data(chunk).await?;
         }
-        impl Stream for MessageIterator {
-            type Item = BackendMessage;
+        assert_eq!(backend.send_copy_done().await?, 10);
+        assert_eq!(backend.bytes_sent(), length as u64);
 
-            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-                if self.finished.load(Ordering::Relaxed) {
-                    return Poll::Ready(None);
-                }
+        assert_eq!(server.await?, expected);
 
-                let mut reader = self.reader.lock().unwrap();
-                let mut future = BackendMessage::read_next_message_async(&mut *reader);
-                let x = match std::pin::pin!(future).poll(cx) {
-                    Poll::Ready(Ok(item)) => {
-                        if let BackendMessage::ReadyForQuery(_) = item {
-                            self.finished.store(true, Ordering::Relaxed);
-                        };
-                        Poll::Ready(Some(item))
-                    }
-                    Poll::Ready(Err(err)) => {
-                        self.finished.store(true, Ordering::Relaxed);
-                        //Poll::Ready(Err(err.into()))
-                        eprintln!("error reading backend message: {err}");
-                        Poll::Ready(None)
-                    }
-                    Poll::Pending => Poll::Pending,
-                };
-                x
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_copy_data_splits_chunk() -> Result<(), Box<dyn Error>> {
+        let (server, mut backend) = connect(vec![])?;
+        backend.set_max_message_size(9);
+
+        backend.send_copy_data(b"1\n2\n3\n").await?;
+        assert_eq!(backend.bytes_sent(), 2 * 5 + 6);
+        drop(backend);
+
+        let expected: Vec<u8> = [&b"1\n2\n"[..], b"3\n"]
+            .into_iter()
+            .flat_map(|piece| CopyData::new(piece).encode())
+            .collect();
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_copy_data_after_error() -> Result<(), Box<dyn Error>> {
+        use crate::messages::backend::Severity;
+
+        let mut responses =
+            ErrorResponse::new(Severity::Error, "22P02", "invalid input syntax").encode();
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
             }
-        }
+            .encode(),
+        );
+        let (server, mut backend) = connect(responses)?;
 
-        MessageIterator {
-            reader: self.reader.clone(),
-            finished: Arc::new(AtomicBool::new(false)),
+        // Give the mock time to deliver the error before the first chunk.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let error = backend.send_copy_data(b"1\n").await.unwrap_err();
+        assert!(error.to_string().contains("invalid input syntax"));
+        drop(backend);
+
+        assert!(server.received().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notifications_between_queries() -> Result<(), Box<dyn Error>> {
+        let notification = |payload: &str| NotificationResponse {
+            process_id: 42,
+            channel: "jobs".to_string(),
+            payload: payload.to_string(),
+        };
+
+        // The first notification interrupts the first query's response; the
+        // second arrives while the connection is idle.
+        let mut responses = CommandComplete::builder().tag("LISTEN").build().encode();
+        responses.extend(notification("1").encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        responses.extend(notification("2").encode());
+        responses.extend(complete("SELECT 0", TransactionStatus::Idle));
+        let (_server, mut backend) = connect(responses)?;
+
+        backend
+            .send_message(SimpleQuery::new("LISTEN jobs"))
+            .await?;
+        let messages: Vec<_> = backend.read_messages().collect().await;
+        assert_eq!(messages.len(), 2);
+
+        {
+            let mut notifications = std::pin::pin!(backend.notifications());
+            assert_eq!(notifications.next().await, Some(notification("1")));
+            assert_eq!(notifications.next().await, Some(notification("2")));
+            // The second query's response is read here and set aside.
+            assert_eq!(notifications.next().await, None);
         }
+
+        backend.send_message(SimpleQuery::new("SELECT")).await?;
+        let messages: Vec<_> = backend.read_messages().collect().await;
+        assert_eq!(
+            messages,
+            vec![
+                BackendMessage::CommandComplete(CommandComplete::builder().tag("SELECT 0").build()),
+                BackendMessage::ReadyForQuery(ReadyForQuery {
+                    transaction_status: TransactionStatus::Idle
+                }),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_tls() -> Result<(), Box<dyn Error>> {
+        use crate::messages::ssl::SSLRequest;
+        use tokio::{io::AsyncReadExt, net::TcpListener};
+        use tokio_rustls::{
+            rustls::{pki_types::PrivateKeyDer, RootCertStore, ServerConfig},
+            TlsAcceptor,
+        };
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let certificate = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(certified.signing_key.serialize_der().into());
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], key)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut ssl_request = [0; 8];
+            stream.read_exact(&mut ssl_request).await.unwrap();
+            assert_eq!(ssl_request.to_vec(), SSLRequest.encode());
+            stream.write_all(&SSLResponse::S.encode()).await.unwrap();
+
+            let acceptor = TlsAcceptor::from(Arc::new(server_config));
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            stream
+                .write_all(&complete("SELECT 0", TransactionStatus::Idle))
+                .await
+                .unwrap();
+            stream.flush().await.unwrap();
+
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let mut backend = AsyncBackend::new(TcpStream::connect(address).await?);
+        backend.send_message(SSLRequest).await?;
+        assert_eq!(backend.read_ssl_message().await?, SSLResponse::S);
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certificate)?;
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut backend = backend
+            .upgrade_tls("localhost", Arc::new(client_config))
+            .await?;
+
+        backend.send_message(SimpleQuery::new("SELECT")).await?;
+        let messages: Vec<_> = backend.read_messages().collect().await;
+        assert_eq!(messages.len(), 2);
+        backend.shutdown().await?;
+
+        let mut expected = SimpleQuery::new("SELECT").encode();
+        expected.extend(Termination.encode());
+        assert_eq!(server.await?, expected);
+
+        Ok(())
     }
 }
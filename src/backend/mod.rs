@@ -1,5 +1,9 @@
 mod r#async;
+mod counter;
+mod recording;
 mod sync;
 
-pub use r#async::AsyncBackend;
-pub use sync::Backend;
+pub use r#async::{AsyncBackend, AsyncTlsBackend, AsyncTransaction, MaybeTlsStream};
+pub use recording::{Direction, RecordedFrame, RecordingBackend, ReplayBackend};
+pub(crate) use sync::ReadTimeoutGuard;
+pub use sync::{Backend, DEFAULT_MAX_MESSAGE_SIZE};
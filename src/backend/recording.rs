@@ -0,0 +1,272 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Cursor, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    messages::{backend::BackendMessage, Message},
+    Backend,
+};
+
+// Each frame in a recording is laid out as
+//
+//   direction: u8, b'>' for sent and b'<' for received
+//   elapsed:   u64, microseconds since the recording started
+//   length:    u32
+//   bytes:     [u8; length], one whole protocol message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+    pub direction: Direction,
+    pub elapsed: Duration,
+    pub bytes: Vec<u8>,
+}
+
+impl RecordedFrame {
+    pub fn read_next_frame(stream: &mut impl Read) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut direction = [0];
+        if stream.read(&mut direction)? == 0 {
+            return Ok(None);
+        }
+        let direction = match direction[0] {
+            b'>' => Direction::Sent,
+            b'<' => Direction::Received,
+            other => return Err(format!("invalid frame direction: {other:#04x}").into()),
+        };
+
+        let mut elapsed = [0; 8];
+        stream.read_exact(&mut elapsed)?;
+        let mut length = [0; 4];
+        stream.read_exact(&mut length)?;
+
+        let mut bytes = vec![0; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut bytes)?;
+
+        Ok(Some(Self {
+            direction,
+            elapsed: Duration::from_micros(u64::from_be_bytes(elapsed)),
+            bytes,
+        }))
+    }
+
+    pub fn read_frames(stream: &mut impl Read) -> Result<Vec<Self>, Box<dyn Error>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = Self::read_next_frame(stream)? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.push(match self.direction {
+            Direction::Sent => b'>',
+            Direction::Received => b'<',
+        });
+        buffer.extend_from_slice(&(self.elapsed.as_micros() as u64).to_be_bytes());
+        buffer.extend_from_slice(&(self.bytes.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(&self.bytes);
+        buffer
+    }
+}
+
+/// A Backend that writes every message sent and received to `sink`.
+#[derive(Debug)]
+pub struct RecordingBackend<W: Write = BufWriter<File>> {
+    backend: Backend,
+    sink: W,
+    started: Instant,
+}
+
+impl RecordingBackend {
+    pub fn create(backend: Backend, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(backend, BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> RecordingBackend<W> {
+    pub fn new(backend: Backend, sink: W) -> Self {
+        Self {
+            backend,
+            sink,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn send_message(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<(), Box<dyn Error>> {
+        self.record(Direction::Sent, message.try_encode()?)?;
+        self.backend.send_message(message)
+    }
+
+    pub fn read_message(&mut self) -> Result<BackendMessage, Box<dyn Error>> {
+        let frame = self.backend.read_frame()?;
+        self.record(Direction::Received, frame.clone())?;
+        BackendMessage::read_next_message(&mut Cursor::new(frame))
+    }
+
+    pub fn send_and_collect(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        self.send_message(message)?;
+
+        let mut messages = Vec::new();
+        loop {
+            let message = self.read_message()?;
+            let finished = matches!(message, BackendMessage::ReadyForQuery(_));
+            messages.push(message);
+            if finished {
+                return Ok(messages);
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> Result<(Backend, W), Box<dyn Error>> {
+        self.sink.flush()?;
+        Ok((self.backend, self.sink))
+    }
+
+    fn record(&mut self, direction: Direction, bytes: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let frame = RecordedFrame {
+            direction,
+            elapsed: self.started.elapsed(),
+            bytes,
+        };
+        self.sink.write_all(&frame.encode())?;
+        Ok(())
+    }
+}
+
+/// Plays back the received side of a recording. Sent messages are accepted
+/// and dropped, so a replay never needs a server.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    received: VecDeque<Vec<u8>>,
+}
+
+impl ReplayBackend {
+    pub fn new(frames: impl IntoIterator<Item = RecordedFrame>) -> Self {
+        let received = frames
+            .into_iter()
+            .filter(|frame| frame.direction == Direction::Received)
+            .map(|frame| frame.bytes)
+            .collect();
+        Self { received }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        Ok(Self::new(RecordedFrame::read_frames(&mut file)?))
+    }
+
+    pub fn send_message(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<(), Box<dyn Error>> {
+        println!("ReplayBackend send_message: {message:?}");
+        Ok(())
+    }
+
+    pub fn read_message(&mut self) -> Result<BackendMessage, Box<dyn Error>> {
+        let frame = self.received.pop_front().ok_or("end of recording")?;
+        BackendMessage::read_next_message(&mut Cursor::new(frame))
+    }
+
+    pub fn send_and_collect(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        self.send_message(message)?;
+
+        let mut messages = Vec::new();
+        loop {
+            let message = self.read_message()?;
+            let finished = matches!(message, BackendMessage::ReadyForQuery(_));
+            messages.push(message);
+            if finished {
+                return Ok(messages);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, DataRow, ReadyForQuery, RowDescription},
+            frontend::SimpleQuery,
+        },
+        mock::MockServer,
+        state::TransactionStatus,
+        ProtocolError,
+    };
+
+    #[test]
+    fn test_record_and_replay() -> Result<(), Box<dyn Error>> {
+        let responses = vec![
+            BackendMessage::RowDescription(RowDescription::builder().string_field("a").build()),
+            BackendMessage::DataRow(DataRow::builder().string_field("1").build()),
+            BackendMessage::CommandComplete(CommandComplete::builder().tag("SELECT 1").build()),
+            BackendMessage::ReadyForQuery(ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }),
+        ];
+        let (_server, stream) =
+            MockServer::start(responses.iter().flat_map(Message::encode).collect());
+        let mut recording = RecordingBackend::new(Backend::new(stream), Vec::new());
+
+        let recorded = recording.send_and_collect(SimpleQuery::new("SELECT 1 AS a"))?;
+        assert_eq!(recorded, responses);
+        let (_backend, sink) = recording.into_inner()?;
+
+        let frames = RecordedFrame::read_frames(&mut sink.as_slice())?;
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[0].direction, Direction::Sent);
+        assert_eq!(frames[0].bytes, SimpleQuery::new("SELECT 1 AS a").encode());
+        assert!(frames[1..]
+            .iter()
+            .all(|frame| frame.direction == Direction::Received));
+        assert!(frames.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+
+        let mut replay = ReplayBackend::new(frames);
+        let replayed = replay.send_and_collect(SimpleQuery::new("SELECT 1 AS a"))?;
+        assert_eq!(replayed, recorded);
+        assert!(replay.read_message().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_refuses_message_too_long() -> Result<(), Box<dyn Error>> {
+        let mut responses = vec![b'D'];
+        responses.extend_from_slice(&u32::MAX.to_be_bytes());
+        let (_server, stream) = MockServer::start(responses);
+        let mut backend = Backend::new(stream);
+        backend.set_max_message_size(16);
+        let mut recording = RecordingBackend::new(backend, Vec::new());
+
+        let err = recording.read_message().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(ProtocolError::MessageTooLong { .. })
+        ));
+        let (_backend, sink) = recording.into_inner()?;
+        assert!(sink.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
 use clap::Parser;
 use rpsql::{
-    messages::backend::{BackendMessage, CommandComplete, DataRow, RowDescription},
+    messages::backend::{BackendMessage, CommandComplete, RowDescription},
     messages::frontend::{SimpleQuery, Termination},
     messages::startup::{Startup, StartupResponse},
     state::{Authentication, BackendKeyData, ParameterStatus, ReadyForQuery, TransactionStatus},
-    AsyncBackend as Backend,
+    AsyncBackend as Backend, Row,
 };
 use std::{collections::HashMap, error::Error};
 use tokio::net::TcpStream;
@@ -47,7 +47,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     loop {
         match prompt.readline(pg.prompt_prefix.as_str()) {
             Ok(line) => {
-                let query = SimpleQuery::new(line);
+                let query = SimpleQuery::new_trusted(line);
                 do_query(&mut pg, &mut backend, query).await?;
             }
             Err(err) => {
@@ -106,6 +106,12 @@ async fn do_startup(pg: &mut Pg, backend: &mut Backend) -> Result<(), Box<dyn Er
                 pg.key_data = Some(key_data);
             }
 
+            StartupResponse::ErrorResponse(error_response) => {
+                return Err(error_response.into());
+            }
+
+            StartupResponse::Notice(_) | StartupResponse::NegotiateProtocolVersion(_) => {}
+
             StartupResponse::ReadyForQuery(ReadyForQuery { transaction_status }) => {
                 match transaction_status {
                     TransactionStatus::Idle => {
@@ -129,10 +135,7 @@ async fn do_query(
     backend: &mut Backend,
     query: SimpleQuery,
 ) -> Result<(), Box<dyn Error>> {
-    backend.send_message(query).await?;
-
-    let mut query_messages = backend.read_messages();
-    while let Some(query_message) = query_messages.next().await {
+    for query_message in backend.send_and_collect(query).await? {
         eprintln!("{:?}", query_message);
 
         match query_message {
@@ -145,18 +148,21 @@ async fn do_query(
                 break;
             }
 
-            BackendMessage::DataRow(DataRow { fields }) => {
-                let field_names = pg.row_description.clone().unwrap_or_default().field_names();
-                assert_eq!(field_names.len(), fields.len());
+            BackendMessage::DataRow(data_row) => {
+                let row_description = pg.row_description.clone().unwrap_or_default();
+                let row = Row::new(&row_description, &data_row)?;
                 println!();
-                for (name, value) in field_names.into_iter().zip(fields) {
-                    println!("{} = {}", name, value.unwrap_or_else(|| "NULL".to_string()));
+                for (index, name) in row_description.field_names().into_iter().enumerate() {
+                    let value = row
+                        .raw(index)
+                        .map_or("NULL".into(), String::from_utf8_lossy);
+                    println!("{} = {}", name, value);
                 }
             }
 
             BackendMessage::CommandComplete(CommandComplete { tag }) => {
                 println!("command complete: {}", tag);
-                let _ = pg.row_description = None;
+                pg.row_description = None;
             }
 
             _ => {
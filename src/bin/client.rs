@@ -1,21 +1,19 @@
-use std::{
-    error::Error,
-    net::{IpAddr, SocketAddr, TcpStream},
-    time::Duration,
-};
+use std::{error::Error, time::Duration};
 
 use rpsql::{
     messages::{
         backend::{
-            BackendMessage, CommandComplete, DataRow, EmptyQueryResponse, NoticeMessage,
-            RowDescription,
+            BackendMessage, CommandComplete, EmptyQueryResponse, NoticeMessage, RowDescription,
         },
         frontend::{SimpleQuery, Termination},
         ssl::{SSLRequest, SSLResponse},
         startup::{Startup, StartupResponse},
     },
-    state::{Authentication, BackendKeyData, ParameterStatus, ReadyForQuery, TransactionStatus},
-    Backend,
+    state::{
+        Authentication, BackendKeyData, NegotiateProtocolVersion, ParameterStatus, ReadyForQuery,
+        TransactionStatus,
+    },
+    Backend, Row,
 };
 
 use clap::Parser;
@@ -43,9 +41,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
     let mut pg = Pg::new();
 
-    let host: IpAddr = args.host.parse()?;
-    let sockaddr: SocketAddr = (host, args.port).into();
-    let mut backend = pg.connect(sockaddr)?;
+    let mut backend = pg.connect(&args.host, args.port)?;
 
     if args.request_ssl {
         let ssl_message = SSLRequest;
@@ -68,7 +64,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     loop {
         match prompt.readline(pg.prompt_prefix.as_str()) {
             Ok(line) => {
-                let query = SimpleQuery::new(line);
+                let query = SimpleQuery::new_trusted(line);
                 do_query(&mut pg, &mut backend, query)?;
             }
             Err(err) => {
@@ -92,6 +88,14 @@ fn do_startup(pg: &mut Pg, backend: &mut Backend) -> Result<(), Box<dyn Error>>
                 pg.authentication = Some(Authentication::Ok);
             }
 
+            StartupResponse::Authentication(authentication) => {
+                return Err(format!(
+                    "server asked for {} authentication, which rpsql-client does not support",
+                    authentication.describe()
+                )
+                .into());
+            }
+
             StartupResponse::ParameterStatus(ParameterStatus { name, value }) => {
                 println!("parameter status: {name}, {value}");
                 pg.parameters.insert(name, value);
@@ -108,6 +112,23 @@ fn do_startup(pg: &mut Pg, backend: &mut Backend) -> Result<(), Box<dyn Error>>
                 });
             }
 
+            StartupResponse::ErrorResponse(error_response) => {
+                return Err(error_response.into());
+            }
+
+            StartupResponse::Notice(NoticeMessage {
+                severity, message, ..
+            }) => {
+                println!("notice: severity = {severity}, message = {message}");
+            }
+
+            StartupResponse::NegotiateProtocolVersion(NegotiateProtocolVersion {
+                newest_minor_version,
+                ..
+            }) => {
+                println!("server supports protocol 3.{newest_minor_version}");
+            }
+
             StartupResponse::ReadyForQuery(ReadyForQuery { transaction_status }) => {
                 println!("ready for query: {transaction_status}");
 
@@ -129,20 +150,21 @@ fn do_startup(pg: &mut Pg, backend: &mut Backend) -> Result<(), Box<dyn Error>>
 }
 
 fn do_query(pg: &mut Pg, backend: &mut Backend, query: SimpleQuery) -> Result<(), Box<dyn Error>> {
-    backend.send_message(query)?;
-
-    for message in backend.read_messages()? {
+    for message in backend.send_and_collect(query)? {
         match message {
             BackendMessage::RowDescription(row_description) => {
                 pg.row_description = Some(row_description);
             }
 
-            BackendMessage::DataRow(DataRow { fields }) => {
-                let field_names = pg.row_description.clone().unwrap_or_default().field_names();
-                assert_eq!(field_names.len(), fields.len());
+            BackendMessage::DataRow(data_row) => {
+                let row_description = pg.row_description.clone().unwrap_or_default();
+                let row = Row::new(&row_description, &data_row)?;
                 println!();
-                for (name, value) in field_names.into_iter().zip(fields) {
-                    println!("  {} = {}", name, value.unwrap_or_else(|| "NULL".into()));
+                for (index, name) in row_description.field_names().into_iter().enumerate() {
+                    let value = row
+                        .raw(index)
+                        .map_or("NULL".into(), String::from_utf8_lossy);
+                    println!("  {} = {}", name, value);
                 }
             }
 
@@ -165,6 +187,7 @@ fn do_query(pg: &mut Pg, backend: &mut Backend, query: SimpleQuery) -> Result<()
                 severity,
                 code,
                 message,
+                ..
             }) => {
                 println!("notice: severity = {severity}, code = {code}, message = {message}");
             }
@@ -197,11 +220,9 @@ impl Pg {
         Self::default()
     }
 
-    fn connect(&self, target: SocketAddr) -> Result<Backend, Box<dyn Error>> {
-        let stream = TcpStream::connect(target)?;
-        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-
-        let backend = Backend::new(stream);
+    fn connect(&self, host: &str, port: u16) -> Result<Backend, Box<dyn Error>> {
+        let backend = Backend::connect(host, port)?;
+        backend.set_read_timeout(Some(Duration::from_secs(5)))?;
         Ok(backend)
     }
 }
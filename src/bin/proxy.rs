@@ -1,11 +1,15 @@
 use std::{
+    collections::HashMap,
     error::Error,
     net::{TcpListener, TcpStream},
-    time::Duration,
+    thread,
 };
 
 use rpsql::{
-    messages::frontend::{FrontendMessage, Termination},
+    messages::{
+        extended::Target,
+        frontend::{FrontendMessage, Termination},
+    },
     Backend, Frontend,
 };
 
@@ -27,17 +31,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
-        for frontend_message in frontend.read_messages()? {
-            backend.send_message(frontend_message.clone())?;
-
-            if FrontendMessage::Termination(Termination) == frontend_message {
-                break;
-            }
-
-            for backend_message in backend.read_messages()? {
-                frontend.send_message(backend_message)?;
-            }
-        }
+        relay(&mut frontend, &mut backend)?;
 
         drop(backend);
         drop(frontend);
@@ -47,6 +41,99 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Forwards frontend messages to the backend, and the backend's replies back,
+// until the frontend terminates and the backend closes the connection.
+fn relay(frontend: &mut Frontend, backend: &mut Backend) -> Result<(), Box<dyn Error>> {
+    // Replies are pumped on their own thread, so that each reaches the
+    // client as soon as the backend sends it: after a Sync, a Flush, or
+    // the start of a COPY, without the proxy knowing which to expect.
+    let mut replies_from = backend.try_clone()?;
+    let mut replies_to = frontend.try_clone()?;
+    let replies = thread::spawn(move || -> Result<(), String> {
+        loop {
+            let message = match replies_from.read_message() {
+                Ok(message) => message,
+                // Most likely the backend closed the connection.
+                Err(err) => {
+                    println!("backend stopped replying: {err}");
+                    return Ok(());
+                }
+            };
+            replies_to
+                .send_message(message)
+                .map_err(|err| err.to_string())?;
+        }
+    });
+
+    let mut tracer = Tracer::default();
+    let mut terminated = false;
+    for frontend_message in frontend.read_messages()? {
+        if let Some(trace) = tracer.trace(&frontend_message) {
+            println!("{trace}");
+        }
+
+        terminated = FrontendMessage::Termination(Termination) == frontend_message;
+        backend.send_message(frontend_message)?;
+        if terminated {
+            break;
+        }
+    }
+    // A frontend that went away without saying so still ends the session.
+    if !terminated {
+        backend.send_message(Termination)?;
+    }
+
+    replies.join().map_err(|_| "reply thread panicked")??;
+    Ok(())
+}
+
+// Remembers the SQL behind each prepared statement and portal, so that Bind
+// and Execute can be logged with the query they run.
+#[derive(Debug, Default)]
+struct Tracer {
+    statements: HashMap<String, String>,
+    portals: HashMap<String, String>,
+}
+
+impl Tracer {
+    fn trace(&mut self, message: &FrontendMessage) -> Option<String> {
+        match message {
+            FrontendMessage::SimpleQuery(query) => Some(format!("query: {}", query.query())),
+            FrontendMessage::Parse(parse) => {
+                self.statements
+                    .insert(parse.name.clone(), parse.query.clone());
+                Some(format!("parse statement {:?}: {}", parse.name, parse.query))
+            }
+            FrontendMessage::Bind(bind) => {
+                let query = self.statements.get(&bind.statement)?.clone();
+                self.portals.insert(bind.portal.clone(), query.clone());
+                Some(format!(
+                    "bind portal {:?} to statement {:?}: {query}",
+                    bind.portal, bind.statement
+                ))
+            }
+            FrontendMessage::Execute(execute) => {
+                let query = self.portals.get(&execute.portal)?;
+                Some(format!("execute portal {:?}: {query}", execute.portal))
+            }
+            FrontendMessage::Close(close) => {
+                match &close.target {
+                    Target::Statement(name) => self.statements.remove(name),
+                    Target::Portal(name) => self.portals.remove(name),
+                };
+                None
+            }
+            // Unnamed portals only live until the end of the transaction,
+            // but a Sync is the latest point the proxy can forget them.
+            FrontendMessage::Sync(_) => {
+                self.portals.remove("");
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Pg {
     listener: TcpListener,
@@ -60,8 +147,6 @@ impl Pg {
 
     fn connect(&self, target: &str) -> Result<Backend, Box<dyn Error>> {
         let stream = TcpStream::connect(target)?;
-        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-
         let backend = Backend::new(stream);
         Ok(backend)
     }
@@ -73,3 +158,204 @@ impl Pg {
             .map(Frontend::new)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpsql::{
+        messages::{
+            backend::{CommandComplete, ReadyForQuery},
+            copy::{CopyData, CopyDone, CopyInResponse, CopyOutResponse},
+            extended::{Bind, Close, Execute, Flush, Parse, ParseComplete},
+            frontend::{SimpleQuery, Sync},
+            Message,
+        },
+        state::TransactionStatus,
+    };
+    use std::{
+        io::{Read, Write},
+        net::Shutdown,
+        thread::JoinHandle,
+        time::Duration,
+    };
+
+    // Accepts one connection on a loopback socket, writes `responses` and
+    // closes its side, then returns everything it received.
+    fn peer(listener: TcpListener, responses: Vec<u8>) -> JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&responses).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        })
+    }
+
+    #[test]
+    fn test_relay_copy_out() -> Result<(), Box<dyn Error>> {
+        let mut responses = CopyOutResponse {
+            format: 0,
+            column_formats: vec![0],
+        }
+        .encode();
+        for line in ["1\n", "2\n", "3\n"] {
+            CopyData::new(line).encode_into(&mut responses);
+        }
+        CopyDone.encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("COPY 3")
+            .build()
+            .encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode_into(&mut responses);
+
+        let mut requests = SimpleQuery::new("COPY t TO STDOUT").encode();
+        Termination.encode_into(&mut requests);
+
+        assert_relayed(requests, responses)
+    }
+
+    #[test]
+    fn test_relay_copy_in() -> Result<(), Box<dyn Error>> {
+        let mut responses = CopyInResponse {
+            format: 0,
+            column_formats: vec![0],
+        }
+        .encode();
+        CommandComplete::builder()
+            .tag("COPY 2")
+            .build()
+            .encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode_into(&mut responses);
+
+        let mut requests = SimpleQuery::new("COPY t FROM STDIN").encode();
+        CopyData::new("1\n").encode_into(&mut requests);
+        CopyData::new("2\n").encode_into(&mut requests);
+        CopyDone.encode_into(&mut requests);
+        Termination.encode_into(&mut requests);
+
+        assert_relayed(requests, responses)
+    }
+
+    // Relays between a client that sends `requests` and a server that
+    // answers with `responses`, and checks both arrive intact.
+    fn assert_relayed(requests: Vec<u8>, responses: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let client = peer(listener, requests.clone());
+        let mut frontend = Frontend::new(TcpStream::connect(address)?);
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let server = peer(listener, responses.clone());
+        let mut backend = Backend::new(TcpStream::connect(address)?);
+
+        relay(&mut frontend, &mut backend)?;
+        drop(frontend);
+        drop(backend);
+
+        assert_eq!(client.join().unwrap(), responses);
+        assert_eq!(server.join().unwrap(), requests);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_replies_after_flush() -> Result<(), Box<dyn Error>> {
+        let mut parse = Parse::new("s1", "SELECT 1").encode();
+        Flush.encode_into(&mut parse);
+        let sync = Sync.encode();
+        let ready = ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode();
+
+        // The client waits for ParseComplete before it sends the Sync.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let client = {
+            let (parse, sync, ready) = (parse.clone(), sync.clone(), ready.clone());
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                stream.write_all(&parse).unwrap();
+                let mut reply = vec![0; ParseComplete.encode().len()];
+                stream.read_exact(&mut reply).unwrap();
+                assert_eq!(reply, ParseComplete.encode());
+
+                stream.write_all(&sync).unwrap();
+                let mut reply = vec![0; ready.len()];
+                stream.read_exact(&mut reply).unwrap();
+                assert_eq!(reply, ready);
+                stream.write_all(&Termination.encode()).unwrap();
+            })
+        };
+        let mut frontend = Frontend::new(TcpStream::connect(address)?);
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut request = vec![0; parse.len()];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, parse);
+            stream.write_all(&ParseComplete.encode()).unwrap();
+
+            let mut request = vec![0; sync.len()];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, sync);
+            stream.write_all(&ready).unwrap();
+
+            let mut request = vec![0; Termination.encode().len()];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, Termination.encode());
+        });
+        let mut backend = Backend::new(TcpStream::connect(address)?);
+
+        relay(&mut frontend, &mut backend)?;
+        client.join().unwrap();
+        server.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracer_logs_parse() {
+        let mut tracer = Tracer::default();
+
+        let trace = tracer.trace(&FrontendMessage::Parse(Parse::new("s1", "SELECT $1")));
+        assert_eq!(trace.as_deref(), Some("parse statement \"s1\": SELECT $1"));
+
+        let trace = tracer.trace(&FrontendMessage::Bind(Bind::new("p1", "s1")));
+        assert_eq!(
+            trace.as_deref(),
+            Some("bind portal \"p1\" to statement \"s1\": SELECT $1")
+        );
+
+        let trace = tracer.trace(&FrontendMessage::Execute(Execute::new("p1", 0)));
+        assert_eq!(trace.as_deref(), Some("execute portal \"p1\": SELECT $1"));
+
+        tracer.trace(&FrontendMessage::Close(Close {
+            target: Target::Portal("p1".to_string()),
+        }));
+        assert_eq!(
+            tracer.trace(&FrontendMessage::Execute(Execute::new("p1", 0))),
+            None
+        );
+
+        let trace = tracer.trace(&FrontendMessage::SimpleQuery(SimpleQuery::new("SELECT 1")));
+        assert_eq!(trace.as_deref(), Some("query: SELECT 1"));
+    }
+}
@@ -1,78 +1,271 @@
-use std::{error::Error, net::TcpListener};
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
+use clap::Parser;
 use rpsql::{
+    auth,
     messages::{
-        backend::{CommandComplete, DataRow, ReadyForQuery, RowDescription},
-        frontend::FrontendMessage,
+        backend::{
+            CommandComplete, DataRow, EmptyQueryResponse, ErrorResponse, ReadyForQuery,
+            RowDescription, Severity,
+        },
+        frontend::{FrontendMessage, PasswordMessage},
         ssl::SSLResponse,
-        startup::StartupRequest,
+        startup::{CancelRequest, Startup, StartupRequest},
     },
-    state::{Authentication, TransactionStatus},
+    sql::split_statements,
+    state::{Authentication, BackendKeyData, TransactionStatus},
     Frontend,
 };
 
+#[derive(Debug, Clone, Parser)]
+#[command(author, version)]
+struct Args {
+    #[clap(long, default_value = "127.0.0.1:54321")]
+    listen: String,
+
+    #[clap(long, default_value = "postgres")]
+    user: String,
+
+    #[clap(long, requires = "password_hash")]
+    require_password: bool,
+
+    // As stored in pg_authid: "md5" followed by md5(password || user).
+    #[clap(long)]
+    password_hash: Option<String>,
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let pg = Pg::bind("127.0.0.1:54321")?;
-    println!("Listening on 127.0.0.1:54321");
+    let args = Args::parse();
+
+    let pg = Pg::bind(&args.listen)?;
+    println!("Listening on {}", pg.listener.local_addr()?);
 
-    'connection: for mut frontend in pg.connections() {
+    // Each connection gets its own thread, so that a CancelRequest can
+    // arrive while another connection is running a query.
+    for frontend in pg.connections() {
         println!("New connection from frontend");
+        let args = args.clone();
+        let sessions = pg.sessions.clone();
+        thread::spawn(move || {
+            if let Err(err) = serve(&args, &sessions, frontend) {
+                eprintln!("connection failed: {err}");
+            }
+        });
+    }
 
-        for startup_request in frontend.read_startup_messages()? {
-            match startup_request {
-                StartupRequest::CancelRequest(_) => continue 'connection,
-                StartupRequest::SSLRequest(_) => {
-                    let ssl_response = SSLResponse::N;
-                    frontend.send_message(ssl_response)?;
-                    continue;
-                }
-                StartupRequest::Startup(_) => {
-                    frontend.send_message(Authentication::Ok)?;
+    Ok(())
+}
 
-                    frontend.send_message(ReadyForQuery {
-                        transaction_status: TransactionStatus::Idle,
-                    })?;
-                    break;
+fn serve(args: &Args, sessions: &Sessions, mut frontend: Frontend) -> Result<(), Box<dyn Error>> {
+    for startup_request in frontend.read_startup_messages()? {
+        match startup_request {
+            StartupRequest::CancelRequest(cancel_request) => {
+                sessions.cancel(&cancel_request);
+                return Ok(());
+            }
+            StartupRequest::SSLRequest(_) => {
+                let ssl_response = SSLResponse::N;
+                frontend.send_message(ssl_response)?;
+                continue;
+            }
+            StartupRequest::Startup(startup) => {
+                if args.require_password {
+                    if let Some(error_response) = authenticate(args, &mut frontend, &startup)? {
+                        frontend.send_message(error_response)?;
+                        return Ok(());
+                    }
                 }
+
+                frontend.send_message(Authentication::Ok)?;
+                break;
             }
         }
+    }
+
+    let session = sessions.register()?;
+    frontend.send_message(session.key_data.clone())?;
+    frontend.send_message(ReadyForQuery {
+        transaction_status: TransactionStatus::Idle,
+    })?;
+
+    let result = serve_queries(&session, &mut frontend);
+    sessions.remove(&session);
+    result
+}
 
-        for message in frontend.read_messages()? {
-            println!("Message from frontend: {:?}", message);
+fn serve_queries(session: &Session, frontend: &mut Frontend) -> Result<(), Box<dyn Error>> {
+    // After an error in an extended query, messages are discarded until
+    // the next Sync.
+    let mut skip_until_sync = false;
+
+    for message in frontend.read_messages()? {
+        println!("Message from frontend: {:?}", message);
+
+        match message {
+            FrontendMessage::SimpleQuery(query) => {
+                // As in postgres, a cancel that arrives between queries has
+                // nothing to cancel.
+                session.cancelled.store(false, Ordering::Relaxed);
+
+                let statements = split_statements(query.query());
+                if statements.is_empty() {
+                    frontend.send_message(EmptyQueryResponse)?;
+                }
 
-            match message {
-                FrontendMessage::SimpleQuery(_query) => {
-                    let row_description =
-                        RowDescription::builder().string_field("greeting").build();
-                    frontend.send_message(row_description)?;
+                for statement in statements {
+                    if let Some(duration) = pg_sleep(statement) {
+                        if !session.sleep(duration) {
+                            frontend.send_message(ErrorResponse::new(
+                                Severity::Error,
+                                "57014",
+                                "canceling statement due to user request",
+                            ))?;
+                            break;
+                        }
+                    }
 
-                    let data_row = DataRow::builder().string_field("Hello, world!").build();
-                    frontend.send_message(data_row)?;
+                    let tag = match command_tag(statement) {
+                        Some(tag) => tag,
+                        None => {
+                            let row_description =
+                                RowDescription::builder().string_field("greeting").build();
+                            frontend.send_message(row_description)?;
 
-                    let command_complete = CommandComplete::builder().tag("GREETING").build();
+                            let data_row = DataRow::builder().string_field("Hello, world!").build();
+                            frontend.send_message(data_row)?;
+
+                            "SELECT 1".to_string()
+                        }
+                    };
+
+                    let command_complete = CommandComplete::builder().tag(tag).build();
                     frontend.send_message(command_complete)?;
+                }
 
-                    frontend.send_message(ReadyForQuery {
-                        transaction_status: TransactionStatus::Idle,
-                    })?;
+                frontend.send_message(ReadyForQuery {
+                    transaction_status: TransactionStatus::Idle,
+                })?;
+            }
+            FrontendMessage::Sync(_) => {
+                skip_until_sync = false;
+                frontend.send_message(ReadyForQuery {
+                    transaction_status: TransactionStatus::Idle,
+                })?;
+            }
+            // Like postgres, ignore copy messages outside of a COPY.
+            FrontendMessage::CopyData(_)
+            | FrontendMessage::CopyDone(_)
+            | FrontendMessage::CopyFail(_) => {}
+            FrontendMessage::Flush(_) => {}
+            FrontendMessage::Parse(_)
+            | FrontendMessage::Bind(_)
+            | FrontendMessage::Describe(_)
+            | FrontendMessage::Execute(_)
+            | FrontendMessage::Close(_) => {
+                if !skip_until_sync {
+                    skip_until_sync = true;
+                    frontend.send_message(ErrorResponse::new(
+                        Severity::Error,
+                        "0A000",
+                        "extended query protocol is not supported",
+                    ))?;
                 }
-                FrontendMessage::Termination(_) => continue 'connection,
             }
+            FrontendMessage::PasswordMessage(_) => return Ok(()),
+            FrontendMessage::Termination(_) => return Ok(()),
         }
     }
 
     Ok(())
 }
 
+// The seconds to sleep for, if `query` is `SELECT pg_sleep(seconds)`.
+fn pg_sleep(query: &str) -> Option<Duration> {
+    let query = query.trim().trim_end_matches(';').to_lowercase();
+    let seconds = query
+        .strip_prefix("select pg_sleep(")?
+        .strip_suffix(')')?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+// The tag for statements the server pretends to execute without returning
+// rows. Anything else is answered with the one-row greeting.
+fn command_tag(query: &str) -> Option<String> {
+    let verb = query
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(';')
+        .to_uppercase();
+
+    match verb.as_str() {
+        "INSERT" => Some("INSERT 0 1".to_string()),
+        "UPDATE" | "DELETE" => Some(format!("{verb} 1")),
+        "BEGIN" | "COMMIT" | "ROLLBACK" | "SET" => Some(verb),
+        _ => None,
+    }
+}
+
+fn authenticate(
+    args: &Args,
+    frontend: &mut Frontend,
+    startup: &Startup,
+) -> Result<Option<ErrorResponse>, Box<dyn Error>> {
+    let user = startup.parameter("user").unwrap_or_default();
+    if user != args.user {
+        return Ok(Some(ErrorResponse::new(
+            Severity::Fatal,
+            "28000",
+            format!("role \"{user}\" does not exist"),
+        )));
+    }
+
+    let mut salt = [0; 4];
+    getrandom::getrandom(&mut salt)?;
+    frontend.send_message(Authentication::MD5Password { salt })?;
+
+    let password_hash = args.password_hash.as_deref().unwrap_or_default();
+    let expected = auth::md5_salted(password_hash, salt);
+    match frontend.read_messages()?.next() {
+        Some(FrontendMessage::PasswordMessage(PasswordMessage { password }))
+            if password == expected =>
+        {
+            Ok(None)
+        }
+        _ => Ok(Some(ErrorResponse::new(
+            Severity::Fatal,
+            "28P01",
+            format!("password authentication failed for user \"{user}\""),
+        ))),
+    }
+}
+
 #[derive(Debug)]
 struct Pg {
     listener: TcpListener,
+    sessions: Sessions,
 }
 
 impl Pg {
     fn bind(target: &str) -> Result<Self, Box<dyn Error>> {
         let listener = TcpListener::bind(target)?;
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            sessions: Sessions::default(),
+        })
     }
 
     fn connections(&self) -> impl Iterator<Item = Frontend> + '_ {
@@ -82,3 +275,75 @@ impl Pg {
             .map(Frontend::new)
     }
 }
+
+// The connections past startup, by the process id handed out in their
+// BackendKeyData, for CancelRequests to find.
+#[derive(Debug, Default, Clone)]
+struct Sessions {
+    sessions: Arc<Mutex<HashMap<u32, Session>>>,
+    next_process_id: Arc<AtomicU32>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    key_data: BackendKeyData,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Sessions {
+    fn register(&self) -> Result<Session, Box<dyn Error>> {
+        let process_id = self.next_process_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut secret_key = [0; 4];
+        getrandom::getrandom(&mut secret_key)?;
+        let secret_key = u32::from_be_bytes(secret_key);
+        let session = Session {
+            key_data: BackendKeyData {
+                process_id,
+                secret_key,
+            },
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(process_id, session.clone());
+        Ok(session)
+    }
+
+    fn remove(&self, session: &Session) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(&session.key_data.process_id);
+    }
+
+    // Flags the session's running query, if the key matches. Like postgres,
+    // nothing is sent back either way.
+    fn cancel(&self, cancel_request: &CancelRequest) {
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.get(&cancel_request.process_id) {
+            Some(session) if session.key_data.secret_key == cancel_request.secret_key => {
+                println!("Cancelling process {}", cancel_request.process_id);
+                session.cancelled.store(true, Ordering::Relaxed);
+            }
+            _ => println!("Ignoring {cancel_request:?}"),
+        }
+    }
+}
+
+impl Session {
+    // Sleeps for `duration`, or until the query is cancelled. Returns
+    // whether the sleep finished.
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.cancelled.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(10)));
+        }
+        false
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    error::Error,
+    io::Write,
+    net::{SocketAddr, TcpStream},
+};
+
+use crate::{
+    messages::{startup::CancelRequest, Message},
+    state::BackendKeyData,
+};
+
+/// Everything needed to cancel a session's running query without
+/// borrowing its Client, from [`Client::cancel_token`](crate::Client::cancel_token).
+///
+/// The token holds the key that was current when it was taken, so take a
+/// new one if the server may have sent another BackendKeyData since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelToken {
+    address: SocketAddr,
+    key_data: BackendKeyData,
+}
+
+impl CancelToken {
+    pub(crate) fn new(address: SocketAddr, key_data: BackendKeyData) -> Self {
+        Self { address, key_data }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn key_data(&self) -> &BackendKeyData {
+        &self.key_data
+    }
+
+    /// Sends a CancelRequest over a new connection to the server. Whether
+    /// or not the cancel takes effect, the query's response still arrives
+    /// on the session's own connection.
+    pub fn cancel(&self) -> Result<(), Box<dyn Error>> {
+        let cancel_request = CancelRequest {
+            process_id: self.key_data.process_id,
+            secret_key: self.key_data.secret_key,
+        };
+        println!("Backend cancel: {cancel_request:?}");
+
+        let mut stream = TcpStream::connect(self.address)?;
+        stream.write_all(&cancel_request.encode())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1310 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::ReadTimeoutGuard,
+    messages::{
+        backend::{BackendMessage, CommandComplete, DataRow, ErrorResponse, NoticeMessage},
+        frontend::SimpleQuery,
+        startup::{Startup, StartupResponse},
+    },
+    state::{BackendKeyData, ParameterStatus},
+    spans::{self, Span},
+    statement::CachedStatement,
+    Backend, CancelToken, FromRow, Row, StatementKey, Transaction,
+};
+
+// How long transaction_retry waits before its first retry. Each retry after
+// that waits twice as long as the one before, up to MAX_RETRY_BACKOFF.
+const RETRY_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A query the server has answered, as passed to the observer set with
+/// [`Client::set_query_observer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryEvent {
+    pub query: String,
+    pub start: Instant,
+    pub end: Instant,
+    /// The row count from the last CommandComplete, or 0 if it had none.
+    pub rows: u64,
+    /// The SQLSTATE of the ErrorResponse, if the query failed.
+    pub sqlstate: Option<String>,
+}
+
+type QueryObserver = Box<dyn FnMut(&QueryEvent) + Send>;
+type NoticeHandler = Box<dyn FnMut(&NoticeMessage) + Send>;
+
+/// The rows of a simple query, with the notices, such as `RAISE NOTICE`
+/// output, that the server sent while running it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryResult {
+    pub rows: Vec<DataRow>,
+    pub notices: Vec<NoticeMessage>,
+}
+
+/// A session on a server, over a [`Backend`].
+///
+/// Methods that take SQL as a `&str`, such as [`query`](Self::query), send
+/// it as written, as [`SimpleQuery::new_trusted`] does: anything spliced
+/// into it must already be escaped. Dynamic values are safer as the
+/// parameters of a prepared statement, or quoted with
+/// [`SimpleQuery::builder`].
+pub struct Client {
+    pub(crate) backend: Backend,
+    pub(crate) parameters: HashMap<String, String>,
+    pub(crate) key_data: Option<BackendKeyData>,
+    pub(crate) protocol_version: (u16, u16),
+    // Prepared statements by query text and parameter types.
+    pub(crate) statements: HashMap<StatementKey, CachedStatement>,
+    pub(crate) next_statement: u32,
+    // Names of dropped statements, to close before the next query.
+    pub(crate) pending_closes: Arc<Mutex<Vec<String>>>,
+    pub(crate) query_observer: Option<QueryObserver>,
+    pub(crate) notice_handler: Option<NoticeHandler>,
+    // Set by with_read_timeout; None leaves the socket's own timeout.
+    pub(crate) read_timeout: Option<Option<Duration>>,
+    pub(crate) closed: bool,
+}
+
+impl Client {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            parameters: HashMap::new(),
+            key_data: None,
+            protocol_version: (3, 0),
+            statements: HashMap::new(),
+            next_statement: 0,
+            pending_closes: Arc::default(),
+            query_observer: None,
+            notice_handler: None,
+            read_timeout: None,
+            closed: false,
+        }
+    }
+
+    /// Calls `observer` once for every simple or extended query the server
+    /// answers, whether it succeeded or failed. Queries that never got an
+    /// answer, such as those cut off by a closed connection, are not seen.
+    pub fn set_query_observer(&mut self, observer: Box<dyn FnMut(&QueryEvent) + Send>) {
+        self.query_observer = Some(observer);
+    }
+
+    /// Calls `handler` with every notice the server sends during a query,
+    /// simple or extended. Notices are otherwise dropped, except by
+    /// [`Client::query_with_notices`].
+    pub fn set_notice_handler(&mut self, handler: Box<dyn FnMut(&NoticeMessage) + Send>) {
+        self.notice_handler = Some(handler);
+    }
+
+    /// Reads with `timeout` during every operation from now on, where
+    /// `None` waits forever, so that a connection can start up under a
+    /// short timeout and then run a long query with none. The socket's own
+    /// timeout is put back after each operation.
+    pub fn with_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    // Applies the timeout from with_read_timeout until the guard is dropped.
+    pub(crate) fn operation_timeout(&self) -> Result<Option<ReadTimeoutGuard>, Box<dyn Error>> {
+        self.read_timeout
+            .map(|timeout| self.backend.override_read_timeout(timeout))
+            .transpose()
+    }
+
+    /// Whether the server has ended the session with a FATAL or PANIC
+    /// error. The connection can't be used again and should be discarded.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Reads the next message, failing at once on a FATAL or PANIC error,
+    // which the server follows by closing the connection rather than with
+    // ReadyForQuery. A BackendKeyData, which a pooler may send with any
+    // query, replaces the key to cancel with.
+    pub(crate) fn read_message(&mut self) -> Result<BackendMessage, Box<dyn Error>> {
+        match self.backend.read_message()? {
+            BackendMessage::ErrorResponse(error_response) if error_response.is_fatal() => {
+                self.closed = true;
+                Err(error_response.into())
+            }
+            BackendMessage::BackendKeyData(key_data) => {
+                self.key_data = Some(key_data.clone());
+                Ok(BackendMessage::BackendKeyData(key_data))
+            }
+            message => Ok(message),
+        }
+    }
+
+    pub(crate) fn handle_notice(&mut self, notice: &NoticeMessage) {
+        if let Some(handler) = &mut self.notice_handler {
+            handler(notice);
+        }
+    }
+
+    pub(crate) fn observe_query(
+        &mut self,
+        query: &str,
+        start: Instant,
+        command_complete: Option<&CommandComplete>,
+        error: Option<&ErrorResponse>,
+    ) {
+        let rows = command_complete
+            .and_then(CommandComplete::rows_affected)
+            .unwrap_or_default();
+        spans::record_query(rows, error.map(ErrorResponse::sqlstate));
+
+        if let Some(observer) = &mut self.query_observer {
+            observer(&QueryEvent {
+                query: query.to_string(),
+                start,
+                end: Instant::now(),
+                rows,
+                sqlstate: error.map(|error| error.sqlstate().to_string()),
+            });
+        }
+    }
+
+    /// Sends `startup` and keeps the parameters and cancellation key the
+    /// server reports.
+    pub fn startup(&mut self, startup: Startup) -> Result<(), Box<dyn Error>> {
+        self.startup_with_password(startup, None)
+    }
+
+    /// Like `startup`, but answers a cleartext or MD5 password request with
+    /// `password`.
+    pub fn startup_with_password(
+        &mut self,
+        startup: Startup,
+        password: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (major, minor) = (
+            startup.protocol_major_version,
+            startup.protocol_minor_version,
+        );
+        self.protocol_version = (major, minor);
+
+        let _span = Span::startup(
+            &self.backend,
+            startup.parameter("user"),
+            startup.parameter("database"),
+        );
+        let _timeout = self.operation_timeout()?;
+        for response in self.backend.startup_with_password(startup, password)? {
+            match response {
+                StartupResponse::ParameterStatus(ParameterStatus { name, value }) => {
+                    self.parameters.insert(name, value);
+                }
+                StartupResponse::BackendKeyData(key_data) => self.key_data = Some(key_data),
+                StartupResponse::NegotiateProtocolVersion(negotiate) => {
+                    let newest = u16::try_from(negotiate.newest_minor_version).unwrap_or(u16::MAX);
+                    self.protocol_version = (major, minor.min(newest));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// A token for cancelling this session's queries from elsewhere, with
+    /// the latest key the server has sent.
+    pub fn cancel_token(&self) -> Result<CancelToken, Box<dyn Error>> {
+        let key_data = self
+            .key_data
+            .clone()
+            .ok_or("no BackendKeyData to cancel with")?;
+        Ok(CancelToken::new(self.backend.peer_addr()?, key_data))
+    }
+
+    /// The protocol version in use: the one requested at startup, unless
+    /// the server negotiated it down.
+    pub fn protocol_version(&self) -> (u16, u16) {
+        self.protocol_version
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    /// The server's version as `(major, minor)`, from `server_version_num`
+    /// if the server reports it and from `server_version` if not. Before
+    /// PostgreSQL 10 the major version had two parts, so 9.6.24 is `(9, 6)`.
+    pub fn server_version(&self) -> Option<(u32, u32)> {
+        if let Some(version) = self
+            .parameter("server_version_num")
+            .and_then(|version| version.parse::<u32>().ok())
+        {
+            return Some(match version {
+                100000.. => (version / 10000, version % 10000),
+                _ => (version / 10000, version / 100 % 100),
+            });
+        }
+
+        // Such as "16.2", "9.6.24", "17beta1" or "16.2 (Debian 16.2-1)".
+        let version: String = self
+            .parameter("server_version")?
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+        let mut parts = version.split('.').map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().and_then(Result::ok).unwrap_or(0);
+        Some((major, minor))
+    }
+
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: &str,
+    ) -> Result<Option<ParameterStatus>, Box<dyn Error>> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(format!("invalid parameter name: {name:?}").into());
+        }
+
+        let value = value.replace('\'', "''");
+        let messages = self.simple_query(&format!("SET {name} = '{value}'"))?;
+
+        Ok(messages
+            .into_iter()
+            .rev()
+            .find_map(|message| match message {
+                BackendMessage::ParameterStatus(status) => Some(status),
+                _ => None,
+            }))
+    }
+
+    pub fn query(&mut self, query: &str) -> Result<Vec<DataRow>, Box<dyn Error>> {
+        let messages = self.simple_query(query)?;
+
+        Ok(messages
+            .into_iter()
+            .filter_map(|message| match message {
+                BackendMessage::DataRow(data_row) => Some(data_row),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Like `query`, but also returns the notices sent while it ran.
+    pub fn query_with_notices(&mut self, query: &str) -> Result<QueryResult, Box<dyn Error>> {
+        let mut result = QueryResult::default();
+        for message in self.simple_query(query)? {
+            match message {
+                BackendMessage::DataRow(data_row) => result.rows.push(data_row),
+                BackendMessage::NoticeMessage(notice) => result.notices.push(notice),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+
+    /// Runs `query` and decodes each row into a `T`.
+    pub fn query_as<T: FromRow>(&mut self, query: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut description = None;
+        let mut rows = Vec::new();
+        for message in self.simple_query(query)? {
+            match message {
+                BackendMessage::RowDescription(row_description) => {
+                    description = Some(row_description);
+                }
+                BackendMessage::DataRow(data_row) => {
+                    let description = description
+                        .as_ref()
+                        .ok_or("DataRow before RowDescription")?;
+                    rows.push(T::from_row(&Row::new(description, &data_row)?)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Like `query_as`, but fails unless there is exactly one row.
+    pub fn query_one_as<T: FromRow>(&mut self, query: &str) -> Result<T, Box<dyn Error>> {
+        self.query_opt_as(query)?
+            .ok_or_else(|| "query returned no rows".into())
+    }
+
+    /// Like `query_as`, but fails if there is more than one row.
+    pub fn query_opt_as<T: FromRow>(&mut self, query: &str) -> Result<Option<T>, Box<dyn Error>> {
+        let mut rows = self.query_as(query)?;
+        if rows.len() > 1 {
+            return Err(format!("query returned {} rows, expected at most one", rows.len()).into());
+        }
+        Ok(rows.pop())
+    }
+
+    /// Runs `query`, cancelling it if it has not finished within `timeout`.
+    /// A cancelled query fails with the server's query_canceled (57014)
+    /// ErrorResponse.
+    pub fn query_timeout(
+        &mut self,
+        query: &str,
+        timeout: Duration,
+    ) -> Result<Vec<DataRow>, Box<dyn Error>> {
+        if self.key_data.is_none() {
+            return Err("no BackendKeyData to cancel with".into());
+        }
+        let _span = Span::query(&self.backend, query);
+        let _timeout = self.operation_timeout()?;
+        self.close_dropped_statements()?;
+        let start = Instant::now();
+        let deadline = start + timeout;
+        self.backend.send_message(SimpleQuery::new_trusted(query))?;
+
+        let mut cancelled = false;
+        let mut rows = Vec::new();
+        let mut command_complete = None;
+        let mut error = None;
+        loop {
+            // Only wait between messages, so that the deadline never cuts a
+            // message in half. A query that keeps sending rows is cancelled
+            // all the same once the deadline has passed, and the rest of its
+            // response read through to ReadyForQuery.
+            if !cancelled {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() || !self.backend.wait_readable(Some(remaining))? {
+                    self.cancel_token()?.cancel()?;
+                    cancelled = true;
+                    continue;
+                }
+            }
+
+            match self.read_message()? {
+                BackendMessage::DataRow(data_row) => rows.push(data_row),
+                BackendMessage::CommandComplete(complete) => command_complete = Some(complete),
+                BackendMessage::ParameterStatus(ParameterStatus { name, value }) => {
+                    self.parameters.insert(name, value);
+                }
+                BackendMessage::NoticeMessage(notice) => self.handle_notice(&notice),
+                BackendMessage::ErrorResponse(error_response) => error = Some(error_response),
+                BackendMessage::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+
+        self.observe_query(query, start, command_complete.as_ref(), error.as_ref());
+        match error {
+            Some(error) => Err(error.into()),
+            None => Ok(rows),
+        }
+    }
+
+    /// Returns the session to a clean state with `DISCARD ALL`, which also
+    /// deallocates every prepared statement. An open transaction is rolled
+    /// back first, since `DISCARD ALL` cannot run inside one.
+    pub fn reset(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Err(err) = self.simple_query("DISCARD ALL") {
+            match err.downcast_ref::<ErrorResponse>() {
+                // active_sql_transaction or in_failed_sql_transaction
+                Some(error_response) if error_response.is_class("25") => {
+                    self.simple_query("ROLLBACK")?;
+                    self.simple_query("DISCARD ALL")?;
+                }
+                _ => return Err(err),
+            }
+        }
+
+        self.statements.clear();
+        Ok(())
+    }
+
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, Box<dyn Error>> {
+        Transaction::begin(self)
+    }
+
+    /// Runs `f` in a transaction and commits it, starting over when the
+    /// transaction fails with a serialization failure (40001) or deadlock
+    /// (40P01), as transactions at SERIALIZABLE isolation are expected to.
+    /// `f` may run up to `retries + 1` times, so it must be safe to repeat.
+    pub fn transaction_retry<T>(
+        &mut self,
+        retries: u32,
+        mut f: impl FnMut(&mut Transaction) -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let mut transaction = self.transaction()?;
+            let result = match f(&mut transaction) {
+                Ok(value) => transaction.commit().map(|()| value),
+                Err(err) => {
+                    // The transaction's own error is the one worth returning,
+                    // but without a clean rollback it is not safe to retry.
+                    if let Err(rollback_err) = transaction.rollback() {
+                        eprintln!("transaction_retry: rollback failed: {rollback_err}");
+                        return Err(err);
+                    }
+                    Err(err)
+                }
+            };
+
+            let err = match result {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let retryable = err
+                .downcast_ref::<ErrorResponse>()
+                .is_some_and(|error| error.is_serialization_failure() || error.is_deadlock());
+            if !retryable || attempt == retries {
+                return Err(err);
+            }
+
+            thread::sleep(retry_backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    /// Asks the server whether the session is read-only, as it always is on
+    /// a hot standby.
+    pub fn is_read_only(&mut self) -> Result<bool, Box<dyn Error>> {
+        let rows = self.query("SHOW transaction_read_only")?;
+        match rows.first().and_then(|row| row.field(0)) {
+            Some(b"on") => Ok(true),
+            Some(b"off") => Ok(false),
+            value => Err(format!("unexpected transaction_read_only: {value:?}").into()),
+        }
+    }
+
+    /// Whether the server is a hot standby. Servers from PostgreSQL 14 on
+    /// report `in_hot_standby` at startup; older ones are asked.
+    pub fn is_hot_standby(&mut self) -> Result<bool, Box<dyn Error>> {
+        if let Some(in_hot_standby) = self.parameter("in_hot_standby") {
+            return Ok(in_hot_standby == "on");
+        }
+
+        let rows = self.query("SELECT pg_is_in_recovery()")?;
+        match rows.first().and_then(|row| row.field(0)) {
+            Some(b"t") => Ok(true),
+            Some(b"f") => Ok(false),
+            value => Err(format!("unexpected pg_is_in_recovery: {value:?}").into()),
+        }
+    }
+
+    /// Checks that the server answers an empty query within `timeout`.
+    ///
+    /// A connection that fails the check may still have a late reply in
+    /// flight and should be discarded rather than reused.
+    pub fn is_valid(&mut self, timeout: Duration) -> bool {
+        !self.closed && self.ping(timeout).is_ok()
+    }
+
+    fn ping(&mut self, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        let deadline = Instant::now() + timeout;
+        let previous_timeout = self.backend.read_timeout()?;
+
+        self.backend.send_message(SimpleQuery::new(""))?;
+        let result = self.wait_for_ready(deadline);
+
+        self.backend.set_read_timeout(previous_timeout)?;
+        result
+    }
+
+    // An ErrorResponse is returned only once its ReadyForQuery has been
+    // read, so that nothing is left on the socket for the next query.
+    fn wait_for_ready(&mut self, deadline: Instant) -> Result<(), Box<dyn Error>> {
+        let mut error: Option<ErrorResponse> = None;
+        loop {
+            // A zero read timeout would mean blocking forever.
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or("timed out waiting for ReadyForQuery")?;
+            self.backend.set_read_timeout(Some(remaining))?;
+
+            match self.read_message()? {
+                BackendMessage::ReadyForQuery(_) => {
+                    return match error {
+                        Some(error_response) => Err(error_response.into()),
+                        None => Ok(()),
+                    };
+                }
+                BackendMessage::ErrorResponse(error_response) => error = Some(error_response),
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn simple_query(
+        &mut self,
+        query: &str,
+    ) -> Result<Vec<BackendMessage>, Box<dyn Error>> {
+        let _span = Span::query(&self.backend, query);
+        let _timeout = self.operation_timeout()?;
+        self.close_dropped_statements()?;
+        let start = Instant::now();
+        let messages = self.backend.send_and_collect(SimpleQuery::new_trusted(query))?;
+
+        let mut command_complete = None;
+        let mut error = None;
+        for message in &messages {
+            match message {
+                BackendMessage::CommandComplete(complete) => command_complete = Some(complete),
+                BackendMessage::ParameterStatus(ParameterStatus { name, value }) => {
+                    self.parameters.insert(name.clone(), value.clone());
+                }
+                BackendMessage::BackendKeyData(key_data) => {
+                    self.key_data = Some(key_data.clone());
+                }
+                BackendMessage::NoticeMessage(notice) => self.handle_notice(notice),
+                BackendMessage::ErrorResponse(error_response) => {
+                    self.closed |= error_response.is_fatal();
+                    error = Some(error_response.clone());
+                }
+                _ => {}
+            }
+        }
+
+        if !matches!(messages.last(), Some(BackendMessage::ReadyForQuery(_))) && error.is_none() {
+            return Err("connection closed before ReadyForQuery".into());
+        }
+        self.observe_query(query, start, command_complete, error.as_ref());
+        if let Some(error) = error {
+            return Err(error.into());
+        }
+        Ok(messages)
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("backend", &self.backend)
+            .field("parameters", &self.parameters)
+            .field("key_data", &self.key_data)
+            .field("protocol_version", &self.protocol_version)
+            .field("statements", &self.statements)
+            .field("next_statement", &self.next_statement)
+            .field("pending_closes", &self.pending_closes)
+            .field("query_observer", &self.query_observer.is_some())
+            .field("notice_handler", &self.notice_handler.is_some())
+            .finish()
+    }
+}
+
+fn retry_backoff(attempt: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+    RETRY_BACKOFF.saturating_mul(factor).min(MAX_RETRY_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{EmptyQueryResponse, ReadyForQuery, RowDescription, Severity},
+            extended::BindComplete,
+            startup::CancelRequest,
+            Message,
+        },
+        mock::MockServer,
+        state::{Authentication, NegotiateProtocolVersion, TransactionStatus},
+        FromRowError, Statement,
+    };
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+    };
+
+    #[test]
+    fn test_client_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Client>();
+    }
+
+    #[test]
+    fn test_set_captures_parameter_status() -> Result<(), Box<dyn Error>> {
+        let status = ParameterStatus {
+            name: "application_name".to_string(),
+            value: "x".to_string(),
+        };
+        let mut responses = status.encode();
+        responses.extend(CommandComplete::builder().tag("SET").build().encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        assert_eq!(client.set("application_name", "x")?, Some(status));
+        assert_eq!(client.parameter("application_name"), Some("x"));
+        drop(client);
+
+        assert_eq!(
+            server.received(),
+            SimpleQuery::new("SET application_name = 'x'").encode()
+        );
+
+        Ok(())
+    }
+
+    fn ready(transaction_status: TransactionStatus) -> Vec<u8> {
+        ReadyForQuery { transaction_status }.encode()
+    }
+
+    fn prepared(client: &mut Client, query: &str) -> Statement {
+        let statement = Statement {
+            name: format!("s{}", client.next_statement),
+            query: query.to_string(),
+            parameter_types: vec![],
+            description: None,
+            handle: None,
+        };
+        client.next_statement += 1;
+        client.cache_statement(StatementKey::new(query, vec![]), statement)
+    }
+
+    #[test]
+    fn test_reset() -> Result<(), Box<dyn Error>> {
+        let mut responses = CommandComplete::builder()
+            .tag("DISCARD ALL")
+            .build()
+            .encode();
+        responses.extend(ready(TransactionStatus::Idle));
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let _statement = prepared(&mut client, "SELECT 1");
+
+        client.reset()?;
+        assert!(client.statements.is_empty());
+        drop(client);
+
+        assert_eq!(server.received(), SimpleQuery::new("DISCARD ALL").encode());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_rolls_back_open_transaction() -> Result<(), Box<dyn Error>> {
+        let mut responses = ErrorResponse::new(
+            Severity::Error,
+            "25001",
+            "DISCARD ALL cannot run inside a transaction block",
+        )
+        .encode();
+        responses.extend(ready(TransactionStatus::InFailedTransaction));
+        responses.extend(CommandComplete::builder().tag("ROLLBACK").build().encode());
+        responses.extend(ready(TransactionStatus::Idle));
+        responses.extend(
+            CommandComplete::builder()
+                .tag("DISCARD ALL")
+                .build()
+                .encode(),
+        );
+        responses.extend(ready(TransactionStatus::Idle));
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let _statement = prepared(&mut client, "SELECT 1");
+
+        client.reset()?;
+        assert!(client.statements.is_empty());
+        drop(client);
+
+        let mut expected = SimpleQuery::new("DISCARD ALL").encode();
+        expected.extend(SimpleQuery::new("ROLLBACK").encode());
+        expected.extend(SimpleQuery::new("DISCARD ALL").encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    fn complete(tag: &str, transaction_status: TransactionStatus) -> Vec<u8> {
+        let mut responses = CommandComplete::builder().tag(tag).build().encode();
+        responses.extend(ready(transaction_status));
+        responses
+    }
+
+    #[test]
+    fn test_transaction_retry() -> Result<(), Box<dyn Error>> {
+        let mut responses = complete("BEGIN", TransactionStatus::InTransaction);
+        responses.extend(
+            ErrorResponse::new(
+                Severity::Error,
+                "40001",
+                "could not serialize access due to concurrent update",
+            )
+            .encode(),
+        );
+        responses.extend(ready(TransactionStatus::InFailedTransaction));
+        responses.extend(complete("ROLLBACK", TransactionStatus::Idle));
+        responses.extend(complete("BEGIN", TransactionStatus::InTransaction));
+        responses.extend(complete("UPDATE 1", TransactionStatus::InTransaction));
+        responses.extend(complete("COMMIT", TransactionStatus::Idle));
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut attempts = 0;
+        client.transaction_retry(3, |transaction| {
+            attempts += 1;
+            transaction.query("UPDATE t SET n = n + 1")
+        })?;
+        assert_eq!(attempts, 2);
+        drop(client);
+
+        let mut expected = vec![];
+        for query in [
+            "BEGIN",
+            "UPDATE t SET n = n + 1",
+            "ROLLBACK",
+            "BEGIN",
+            "UPDATE t SET n = n + 1",
+            "COMMIT",
+        ] {
+            expected.extend(SimpleQuery::new(query).encode());
+        }
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_retry_keeps_error_when_rollback_fails() -> Result<(), Box<dyn Error>> {
+        let mut responses = complete("BEGIN", TransactionStatus::InTransaction);
+        responses.extend(
+            ErrorResponse::new(
+                Severity::Error,
+                "40001",
+                "could not serialize access due to concurrent update",
+            )
+            .encode(),
+        );
+        responses.extend(ready(TransactionStatus::InFailedTransaction));
+        responses.extend(ErrorResponse::new(Severity::Error, "XX000", "internal error").encode());
+        responses.extend(ready(TransactionStatus::InFailedTransaction));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut attempts = 0;
+        let err = client
+            .transaction_retry(3, |transaction| {
+                attempts += 1;
+                transaction.query("UPDATE t SET n = n + 1")
+            })
+            .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert_eq!(
+            err.downcast_ref::<ErrorResponse>()
+                .map(ErrorResponse::sqlstate),
+            Some("40001")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(0), RETRY_BACKOFF);
+        assert_eq!(retry_backoff(2), RETRY_BACKOFF * 4);
+        assert_eq!(retry_backoff(40), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Greeting {
+        id: i32,
+        text: String,
+    }
+
+    impl FromRow for Greeting {
+        fn from_row(row: &Row<'_>) -> Result<Self, Box<dyn Error>> {
+            Ok(Self {
+                id: row.get(row.require_column("id")?)?,
+                text: row.get(row.require_column("text")?)?,
+            })
+        }
+    }
+
+    fn greetings(description: RowDescription) -> Vec<u8> {
+        let mut responses = description.encode();
+        for (id, text) in [("1", "hello"), ("2", "hi")] {
+            DataRow::builder()
+                .string_field(id)
+                .string_field(text)
+                .build()
+                .encode_into(&mut responses);
+        }
+        responses.extend(complete("SELECT 2", TransactionStatus::Idle));
+        responses
+    }
+
+    #[test]
+    fn test_query_as() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .string_field("id")
+            .string_field("text")
+            .build();
+        let (_server, stream) = MockServer::start(greetings(description));
+        let mut client = Client::new(Backend::new(stream));
+
+        let rows: Vec<Greeting> = client.query_as("SELECT id, text FROM greetings")?;
+        assert_eq!(
+            rows,
+            vec![
+                Greeting {
+                    id: 1,
+                    text: "hello".to_string()
+                },
+                Greeting {
+                    id: 2,
+                    text: "hi".to_string()
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_as_missing_column() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .string_field("id")
+            .string_field("greeting")
+            .build();
+        let (_server, stream) = MockServer::start(greetings(description));
+        let mut client = Client::new(Backend::new(stream));
+
+        let err = client
+            .query_as::<Greeting>("SELECT id, greeting FROM greetings")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FromRowError>(),
+            Some(&FromRowError::MissingColumn {
+                name: "text".to_string()
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_valid() -> Result<(), Box<dyn Error>> {
+        let mut responses = EmptyQueryResponse.encode();
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        assert!(client.is_valid(Duration::from_secs(5)));
+        drop(client);
+
+        assert_eq!(server.received(), SimpleQuery::new("").encode());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_valid_reads_through_error() -> Result<(), Box<dyn Error>> {
+        let mut responses = ErrorResponse::new(
+            Severity::Error,
+            "57014",
+            "canceling statement due to user request",
+        )
+        .encode();
+        responses.extend(ready(TransactionStatus::Idle));
+        responses.extend(DataRow::builder().string_field("1").build().encode());
+        responses.extend(complete("SELECT 1", TransactionStatus::Idle));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        assert!(!client.is_valid(Duration::from_secs(5)));
+        assert_eq!(client.query("SELECT 1")?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_valid_times_out() -> Result<(), Box<dyn Error>> {
+        // The connection sits in the listen backlog and is never answered.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let mut client = Client::new(Backend::new(stream));
+
+        let start = Instant::now();
+        assert!(!client.is_valid(Duration::from_millis(50)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_read_timeout() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        let backend = Backend::new(stream);
+        backend.set_read_timeout(Some(Duration::from_millis(50)))?;
+        // The kernel may have rounded it.
+        let connect_timeout = backend.read_timeout()?;
+        let mut client = Client::new(backend);
+
+        // The server never answers the first operation.
+        let start = Instant::now();
+        client.with_read_timeout(Some(Duration::from_millis(10)));
+        assert!(client.prepare("SELECT 1").is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(client.backend.read_timeout()?, connect_timeout);
+
+        // It answers the second only after the connect timeout has passed.
+        let answer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let mut responses = DataRow::builder().string_field("1").build().encode();
+            responses.extend(complete("SELECT 1", TransactionStatus::Idle));
+            server.write_all(&responses)
+        });
+        client.with_read_timeout(None);
+        assert_eq!(client.query("SELECT 1 FROM pg_sleep(0.2)")?.len(), 1);
+        assert_eq!(client.backend.read_timeout()?, connect_timeout);
+        answer.join().unwrap()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fatal_error_closes_client() -> Result<(), Box<dyn Error>> {
+        let mut responses =
+            ErrorResponse::new(Severity::Error, "42P01", "relation \"t\" does not exist").encode();
+        responses.extend(ready(TransactionStatus::Idle));
+        // No ReadyForQuery follows a FATAL error.
+        ErrorResponse::new(
+            Severity::Fatal,
+            "57P01",
+            "terminating connection due to administrator command",
+        )
+        .encode_into(&mut responses);
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        assert!(client.query("SELECT * FROM t").is_err());
+        assert!(!client.is_closed());
+
+        let err = client.query("SELECT 1").unwrap_err();
+        assert!(err.downcast_ref::<ErrorResponse>().unwrap().is_fatal());
+        assert!(client.is_closed());
+        assert!(!client.is_valid(Duration::from_secs(5)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protocol_version_negotiated_down() -> Result<(), Box<dyn Error>> {
+        let mut responses = NegotiateProtocolVersion {
+            newest_minor_version: 0,
+            unrecognized_options: vec!["_pq_.x".to_string()],
+        }
+        .encode();
+        responses.extend(Authentication::Ok.encode());
+        responses.extend(ready(TransactionStatus::Idle));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut startup = Startup::new();
+        startup.protocol_minor_version = 2;
+        client.startup(startup)?;
+        assert_eq!(client.protocol_version(), (3, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_token_follows_key_rotation() -> Result<(), Box<dyn Error>> {
+        let startup_key = BackendKeyData {
+            process_id: 42,
+            secret_key: 1111,
+        };
+        let rotated_key = BackendKeyData {
+            process_id: 42,
+            secret_key: 2222,
+        };
+        let mut responses = Authentication::Ok.encode();
+        startup_key.encode_into(&mut responses);
+        responses.extend(ready(TransactionStatus::Idle));
+        rotated_key.encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("SELECT 0")
+            .build()
+            .encode_into(&mut responses);
+        responses.extend(ready(TransactionStatus::Idle));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        client.startup(Startup::new())?;
+        assert_eq!(client.cancel_token()?.key_data(), &startup_key);
+
+        client.query("SELECT 1 WHERE false")?;
+        let token = client.cancel_token()?;
+        assert_eq!(token.key_data(), &rotated_key);
+        assert_eq!(token.address(), client.backend.peer_addr()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_timeout_cancels() -> Result<(), Box<dyn Error>> {
+        let key_data = BackendKeyData {
+            process_id: 42,
+            secret_key: 1234,
+        };
+        let query = SimpleQuery::new("SELECT pg_sleep(60)");
+
+        // Holds the query until a matching CancelRequest arrives on a second
+        // connection, the way a server would.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let expected_query = query.encode();
+        let server = thread::spawn(move || {
+            let (mut session, _) = listener.accept().unwrap();
+            let mut received = vec![0; expected_query.len()];
+            session.read_exact(&mut received).unwrap();
+            assert_eq!(received, expected_query);
+
+            let (mut cancel, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            cancel.read_to_end(&mut received).unwrap();
+            assert_eq!(
+                received,
+                CancelRequest {
+                    process_id: 42,
+                    secret_key: 1234,
+                }
+                .encode()
+            );
+
+            let mut responses = ErrorResponse::new(
+                Severity::Error,
+                "57014",
+                "canceling statement due to user request",
+            )
+            .encode();
+            responses.extend(ready(TransactionStatus::Idle));
+            session.write_all(&responses).unwrap();
+        });
+
+        let mut client = Client::new(Backend::new(TcpStream::connect(address)?));
+        client.key_data = Some(key_data);
+
+        let start = Instant::now();
+        let err = client
+            .query_timeout(query.query(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            err.downcast_ref::<ErrorResponse>()
+                .map(ErrorResponse::sqlstate),
+            Some("57014")
+        );
+        server.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_timeout_cancels_streaming_query() -> Result<(), Box<dyn Error>> {
+        let query = SimpleQuery::new("SELECT generate_series(1, 1000000000)");
+
+        // Keeps sending rows until the CancelRequest arrives, so that there
+        // is always something to read.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let query_length = query.encode().len();
+        let server = thread::spawn(move || {
+            let (mut session, _) = listener.accept().unwrap();
+            let mut received = vec![0; query_length];
+            session.read_exact(&mut received).unwrap();
+
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let rows = {
+                let mut session = session.try_clone().unwrap();
+                let cancelled = cancelled.clone();
+                thread::spawn(move || {
+                    let row = DataRow::builder().string_field("1").build().encode();
+                    while !cancelled.load(Ordering::SeqCst) {
+                        session.write_all(&row).unwrap();
+                    }
+                })
+            };
+
+            let (mut cancel, _) = listener.accept().unwrap();
+            cancel.read_to_end(&mut Vec::new()).unwrap();
+            cancelled.store(true, Ordering::SeqCst);
+            rows.join().unwrap();
+
+            let mut responses = ErrorResponse::new(
+                Severity::Error,
+                "57014",
+                "canceling statement due to user request",
+            )
+            .encode();
+            responses.extend(ready(TransactionStatus::Idle));
+            session.write_all(&responses).unwrap();
+        });
+
+        let mut client = Client::new(Backend::new(TcpStream::connect(address)?));
+        client.key_data = Some(BackendKeyData {
+            process_id: 42,
+            secret_key: 1234,
+        });
+
+        let err = client
+            .query_timeout(query.query(), Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ErrorResponse>()
+                .map(ErrorResponse::sqlstate),
+            Some("57014")
+        );
+        server.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_observer() -> Result<(), Box<dyn Error>> {
+        let mut responses = greetings(
+            RowDescription::builder()
+                .string_field("id")
+                .string_field("text")
+                .build(),
+        );
+        responses.extend(BindComplete.encode());
+        responses.extend(complete("UPDATE 3", TransactionStatus::Idle));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let statement = prepared(&mut client, "UPDATE t SET n = n + 1");
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = Arc::clone(&events);
+        client.set_query_observer(Box::new(move |event: &QueryEvent| {
+            observed.lock().unwrap().push(event.clone());
+        }));
+
+        client.query("SELECT id, text FROM greetings")?;
+        client.execute_count(&statement, &[])?;
+
+        let events = events.lock().unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| (event.query.as_str(), event.rows, event.sqlstate.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("SELECT id, text FROM greetings", 2, None),
+                ("UPDATE t SET n = n + 1", 3, None),
+            ]
+        );
+        assert!(events.iter().all(|event| event.start <= event.end));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_with_notices() -> Result<(), Box<dyn Error>> {
+        let notice = |message: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(NoticeMessage::builder()
+                .severity(Severity::Notice)
+                .message(message.to_string())
+                .build()?
+                .encode())
+        };
+        let mut responses = RowDescription::builder().string_field("n").build().encode();
+        responses.extend(notice("starting")?);
+        responses.extend(DataRow::builder().string_field("1").build().encode());
+        responses.extend(notice("halfway")?);
+        responses.extend(DataRow::builder().string_field("2").build().encode());
+        responses.extend(complete("SELECT 2", TransactionStatus::Idle));
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&handled);
+        client.set_notice_handler(Box::new(move |notice: &NoticeMessage| {
+            seen.lock().unwrap().push(notice.message.clone());
+        }));
+
+        let result = client.query_with_notices("SELECT noisy()")?;
+        assert_eq!(result.rows.len(), 2);
+        let notices: Vec<_> = result
+            .notices
+            .iter()
+            .map(|notice| notice.message.as_str())
+            .collect();
+        assert_eq!(notices, ["starting", "halfway"]);
+        assert_eq!(*handled.lock().unwrap(), ["starting", "halfway"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_version() {
+        let (_server, stream) = MockServer::start(vec![]);
+        let mut client = Client::new(Backend::new(stream));
+        assert_eq!(client.server_version(), None);
+
+        let mut version = |name: &str, value: &str| {
+            client.parameters.clear();
+            client.parameters.insert(name.to_string(), value.to_string());
+            client.server_version()
+        };
+        assert_eq!(version("server_version_num", "160002"), Some((16, 2)));
+        assert_eq!(version("server_version_num", "90624"), Some((9, 6)));
+        assert_eq!(version("server_version", "16.2"), Some((16, 2)));
+        assert_eq!(
+            version("server_version", "16.2 (Debian 16.2-1.pgdg120+2)"),
+            Some((16, 2))
+        );
+        assert_eq!(version("server_version", "9.6.24"), Some((9, 6)));
+        assert_eq!(version("server_version", "17beta1"), Some((17, 0)));
+
+        // server_version_num wins when both are reported.
+        client
+            .parameters
+            .insert("server_version".to_string(), "15.0".to_string());
+        client
+            .parameters
+            .insert("server_version_num".to_string(), "160002".to_string());
+        assert_eq!(client.server_version(), Some((16, 2)));
+    }
+
+    #[test]
+    fn test_empty_query_returns_no_rows() -> Result<(), Box<dyn Error>> {
+        let mut responses = EmptyQueryResponse.encode();
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        assert!(client.query("")?.is_empty());
+        drop(client);
+
+        assert_eq!(server.received(), vec![b'Q', 0x00, 0x00, 0x00, 5, 0x00]);
+
+        Ok(())
+    }
+}
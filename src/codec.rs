@@ -0,0 +1,182 @@
+use std::error::Error;
+
+use bytes::{Buf, BytesMut};
+
+use crate::{
+    backend::DEFAULT_MAX_MESSAGE_SIZE,
+    messages::{backend::BackendMessage, Message},
+    readers::body_length,
+    ProtocolError,
+};
+
+/// Frames backend messages without doing any IO. Bytes read from the server
+/// go into a buffer, and `decode` takes whole messages off the front of it,
+/// so any runtime can drive the protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct Codec {
+    max_message_size: u32,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Codec {
+    pub fn new() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// The largest message, in bytes including its tag and length, that
+    /// `decode` will accept. Anything longer is an error as soon as its
+    /// header arrives, before room is made for it.
+    pub fn max_message_size(&self) -> u32 {
+        self.max_message_size
+    }
+
+    pub fn set_max_message_size(&mut self, max_message_size: u32) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Removes the next message from `buf`, or returns None if it has not
+    /// all arrived yet. A partial message is left in place for the next call.
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<BackendMessage>, Box<dyn Error>> {
+        let Some(frame_length) = self.frame_length(buf)? else {
+            return Ok(None);
+        };
+
+        if buf.len() < frame_length {
+            buf.reserve(frame_length - buf.len());
+            return Ok(None);
+        }
+
+        let r#type = buf[0];
+        buf.advance(5);
+        let body = buf.split_to(frame_length - 5);
+        BackendMessage::decode(r#type, &mut body.reader()).map(Some)
+    }
+
+    /// How many more bytes `buf` needs before `decode` can return the next
+    /// message: the rest of its header, or once that has arrived, the rest
+    /// of its body. Reading no more than this leaves the following message
+    /// where it is.
+    pub fn missing(&self, buf: &BytesMut) -> Result<usize, ProtocolError> {
+        Ok(match self.frame_length(buf)? {
+            Some(frame_length) => frame_length.saturating_sub(buf.len()),
+            None => 5 - buf.len(),
+        })
+    }
+
+    pub fn encode(&mut self, message: impl Message, buf: &mut BytesMut) {
+        buf.extend_from_slice(&message.encode());
+    }
+
+    // The length of the next message, tag and length included, once its
+    // header has arrived.
+    fn frame_length(&self, buf: &BytesMut) -> Result<Option<usize>, ProtocolError> {
+        let Some(header) = buf.get(..5) else {
+            return Ok(None);
+        };
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        if u64::from(length) + 1 > u64::from(self.max_message_size) {
+            return Err(ProtocolError::MessageTooLong {
+                length,
+                max: self.max_message_size,
+            });
+        }
+        Ok(Some(5 + body_length(length)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, ReadyForQuery},
+            frontend::SimpleQuery,
+        },
+        state::TransactionStatus,
+    };
+
+    #[test]
+    fn test_decode_partial() -> Result<(), Box<dyn Error>> {
+        let command_complete = CommandComplete::builder().tag("SELECT 1").build();
+        let ready_for_query = ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        };
+        let first = command_complete.encode();
+        let second = ready_for_query.encode();
+
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        for (i, byte) in first.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            let decoded = codec.decode(&mut buf)?;
+            if i + 1 < first.len() {
+                assert_eq!(decoded, None);
+            } else {
+                assert_eq!(
+                    decoded,
+                    Some(BackendMessage::CommandComplete(command_complete.clone()))
+                );
+            }
+        }
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(&second[..3]);
+        assert_eq!(codec.decode(&mut buf)?, None);
+        buf.extend_from_slice(&second[3..]);
+        assert_eq!(
+            codec.decode(&mut buf)?,
+            Some(BackendMessage::ReadyForQuery(ready_for_query))
+        );
+        assert_eq!(codec.decode(&mut buf)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_length_too_short() {
+        let mut buf = BytesMut::from(&[b'Z', 0, 0, 0, 3][..]);
+        assert!(Codec::new().decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_decode_message_too_long() {
+        let mut codec = Codec::new();
+        codec.set_max_message_size(1024);
+        let mut buf = BytesMut::from(&[b'D', 0, 0, 4, 0][..]);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::MessageTooLong {
+                length: 1024,
+                max: 1024
+            })
+        );
+        assert!(buf.capacity() < 1024);
+    }
+
+    #[test]
+    fn test_missing() -> Result<(), Box<dyn Error>> {
+        let codec = Codec::new();
+        let mut buf = BytesMut::from(&[b'Z', 0][..]);
+        assert_eq!(codec.missing(&buf)?, 3);
+        buf.extend_from_slice(&[0, 0, 5]);
+        assert_eq!(codec.missing(&buf)?, 1);
+        buf.extend_from_slice(b"I");
+        assert_eq!(codec.missing(&buf)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = BytesMut::new();
+        Codec::new().encode(SimpleQuery::new("SELECT 1"), &mut buf);
+        assert_eq!(&buf[..], &SimpleQuery::new("SELECT 1").encode()[..]);
+    }
+}
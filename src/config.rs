@@ -0,0 +1,552 @@
+use std::{
+    error::Error,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::{
+    messages::startup::Startup, AsyncBackend, AsyncClient, Backend, Client, MaybeTlsStream,
+};
+
+/// Connection settings for a server, or for several servers to fail over
+/// between.
+///
+/// Debug masks the password.
+#[derive(Clone, Default)]
+pub struct Config {
+    hosts: Vec<(String, u16)>,
+    user: Option<String>,
+    password: Option<String>,
+    database: Option<String>,
+    target_session_attrs: TargetSessionAttrs,
+    replication: ReplicationMode,
+    allow_plaintext: bool,
+    max_message_size: Option<u32>,
+    tls: Option<Arc<ClientConfig>>,
+}
+
+/// Which sessions `Config::connect` will settle for, as in libpq's
+/// `target_session_attrs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetSessionAttrs {
+    #[default]
+    Any,
+    /// `transaction_read_only` is off.
+    ReadWrite,
+    /// `transaction_read_only` is on.
+    ReadOnly,
+    /// The server is not a hot standby.
+    Primary,
+    /// The server is a hot standby.
+    Standby,
+}
+
+/// The `replication` startup parameter, which starts a walsender session
+/// instead of a normal one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplicationMode {
+    #[default]
+    Off,
+    /// `replication=true`, for physical replication.
+    Physical,
+    /// `replication=database`, for logical replication from the database
+    /// being connected to.
+    Database,
+}
+
+impl ReplicationMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReplicationMode::Off => "false",
+            ReplicationMode::Physical => "true",
+            ReplicationMode::Database => "database",
+        }
+    }
+}
+
+impl TargetSessionAttrs {
+    fn matches(self, client: &mut Client) -> Result<bool, Box<dyn Error>> {
+        Ok(match self {
+            TargetSessionAttrs::Any => true,
+            TargetSessionAttrs::ReadWrite => !client.is_read_only()?,
+            TargetSessionAttrs::ReadOnly => client.is_read_only()?,
+            TargetSessionAttrs::Primary => !client.is_hot_standby()?,
+            TargetSessionAttrs::Standby => client.is_hot_standby()?,
+        })
+    }
+
+    async fn matches_async<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        self,
+        client: &mut AsyncClient<S>,
+    ) -> Result<bool, Box<dyn Error>> {
+        Ok(match self {
+            TargetSessionAttrs::Any => true,
+            TargetSessionAttrs::ReadWrite => !client.is_read_only().await?,
+            TargetSessionAttrs::ReadOnly => client.is_read_only().await?,
+            TargetSessionAttrs::Primary => !client.is_hot_standby().await?,
+            TargetSessionAttrs::Standby => client.is_hot_standby().await?,
+        })
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("hosts", &self.hosts)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("database", &self.database)
+            .field("target_session_attrs", &self.target_session_attrs)
+            .field("replication", &self.replication)
+            .field("allow_plaintext", &self.allow_plaintext)
+            .field("max_message_size", &self.max_message_size)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+// TLS settings are compared by identity, since ClientConfig has no
+// equality of its own.
+impl PartialEq for Config {
+    fn eq(&self, other: &Self) -> bool {
+        let tls_eq = match (&self.tls, &other.tls) {
+            (Some(tls), Some(other_tls)) => Arc::ptr_eq(tls, other_tls),
+            (tls, other_tls) => tls.is_none() && other_tls.is_none(),
+        };
+        self.hosts == other.hosts
+            && self.user == other.user
+            && self.password == other.password
+            && self.database == other.database
+            && self.target_session_attrs == other.target_session_attrs
+            && self.replication == other.replication
+            && self.allow_plaintext == other.allow_plaintext
+            && self.max_message_size == other.max_message_size
+            && tls_eq
+    }
+}
+
+impl Eq for Config {}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a host to try, after any added before it.
+    pub fn add_host(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.hosts.push((host.into(), port));
+        self
+    }
+
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// The password for cleartext, MD5 and, with [`AsyncClient::connect`]
+    /// only, SCRAM-SHA-256 authentication.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn database(mut self, database: impl Into<String>) -> Self {
+        self.database = Some(database.into());
+        self
+    }
+
+    pub fn target_session_attrs(mut self, target_session_attrs: TargetSessionAttrs) -> Self {
+        self.target_session_attrs = target_session_attrs;
+        self
+    }
+
+    /// Replication sessions take commands such as `IDENTIFY_SYSTEM` through
+    /// `Client::query`, which uses the simple query protocol they require.
+    /// A physical one accepts no SQL, so of the `TargetSessionAttrs` only
+    /// `Any`, `ReadWrite` and `ReadOnly`, which use `SHOW`, work with it.
+    pub fn replication(mut self, replication: ReplicationMode) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    /// Silences the warning printed when a connection to a host that is
+    /// not on the loopback interface is left in plaintext, where cleartext
+    /// and MD5 passwords cross the network unencrypted. `Config::connect`
+    /// has no TLS, so it always warns; `AsyncClient::connect` warns only
+    /// when the server turns down the TLS asked for with [`Config::tls`].
+    pub fn allow_plaintext(mut self, allow_plaintext: bool) -> Self {
+        self.allow_plaintext = allow_plaintext;
+        self
+    }
+
+    /// Has [`AsyncClient::connect`] ask each host for TLS first and fall
+    /// back to plaintext if the server does not support it, as libpq's
+    /// `sslmode=prefer` does. `Config::connect` ignores it.
+    pub fn tls(mut self, tls: Arc<ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// The largest message to send, for poolers that accept less than the
+    /// server's own [`DEFAULT_MAX_MESSAGE_SIZE`](crate::DEFAULT_MAX_MESSAGE_SIZE).
+    /// Larger messages fail with
+    /// [`ProtocolError::MessageTooLong`](crate::ProtocolError::MessageTooLong) before any of them is written,
+    /// except COPY data, which is split to fit.
+    pub fn max_message_size(mut self, max_message_size: u32) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    pub fn hosts(&self) -> &[(String, u16)] {
+        &self.hosts
+    }
+
+    /// Tries each host in order, and returns the first session that starts
+    /// up and matches `target_session_attrs`. If none do, the error from
+    /// the last host is returned.
+    pub fn connect(&self) -> Result<Client, Box<dyn Error>> {
+        let mut last_error = None;
+        for (host, port) in &self.hosts {
+            match self.connect_one(host, *port) {
+                Ok(client) => return Ok(client),
+                Err(err) => {
+                    eprintln!("could not use {host}:{port}: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "no hosts to connect to".into()))
+    }
+
+    fn connect_one(&self, host: &str, port: u16) -> Result<Client, Box<dyn Error>> {
+        let mut backend = Backend::connect(host, port)?;
+        if let Some(max_message_size) = self.max_message_size {
+            backend.set_max_message_size(max_message_size);
+        }
+        if let Some(warning) = self.plaintext_warning(backend.peer_addr()?) {
+            eprintln!("{warning}");
+        }
+        let mut client = Client::new(backend);
+        client.startup_with_password(self.startup_message(), self.password.as_deref())?;
+
+        if !self.target_session_attrs.matches(&mut client)? {
+            return Err(format!(
+                "{host}:{port} does not match target_session_attrs {:?}",
+                self.target_session_attrs
+            )
+            .into());
+        }
+        Ok(client)
+    }
+
+    pub(crate) async fn connect_async(
+        &self,
+    ) -> Result<AsyncClient<MaybeTlsStream>, Box<dyn Error>> {
+        let mut last_error = None;
+        for (host, port) in &self.hosts {
+            match self.connect_one_async(host, *port).await {
+                Ok(client) => return Ok(client),
+                Err(err) => {
+                    eprintln!("could not use {host}:{port}: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "no hosts to connect to".into()))
+    }
+
+    async fn connect_one_async(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<AsyncClient<MaybeTlsStream>, Box<dyn Error>> {
+        let stream = TcpStream::connect((host, port)).await?;
+        let address = stream.peer_addr()?;
+        let stream = match &self.tls {
+            Some(tls) => MaybeTlsStream::prefer_tls(stream, host, tls.clone()).await?,
+            None => MaybeTlsStream::Plain(stream),
+        };
+        if !stream.is_tls() {
+            if let Some(warning) = self.plaintext_warning(address) {
+                eprintln!("{warning}");
+            }
+        }
+        let mut backend = AsyncBackend::new(stream);
+        if let Some(max_message_size) = self.max_message_size {
+            backend.set_max_message_size(max_message_size);
+        }
+        let mut client = AsyncClient::new(backend);
+        client
+            .startup(self.startup_message(), self.password.as_deref())
+            .await?;
+
+        if !self.target_session_attrs.matches_async(&mut client).await? {
+            return Err(format!(
+                "{host}:{port} does not match target_session_attrs {:?}",
+                self.target_session_attrs
+            )
+            .into());
+        }
+        Ok(client)
+    }
+
+    fn startup_message(&self) -> Startup {
+        let mut startup = Startup::new();
+        if let Some(user) = &self.user {
+            startup.add_parameter("user", user);
+        }
+        if let Some(database) = &self.database {
+            startup.add_parameter("database", database);
+        }
+        if self.replication != ReplicationMode::Off {
+            startup.add_parameter("replication", self.replication.as_str());
+        }
+        startup
+    }
+
+    fn plaintext_warning(&self, address: SocketAddr) -> Option<String> {
+        let loopback = match address.ip() {
+            IpAddr::V4(ip) => ip.is_loopback(),
+            IpAddr::V6(ip) => {
+                ip.is_loopback() || ip.to_ipv4_mapped().is_some_and(|ip| ip.is_loopback())
+            }
+        };
+        (!loopback && !self.allow_plaintext).then(|| {
+            format!(
+                "warning: connecting to {address} without TLS, \
+                 passwords and queries are sent in plaintext"
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, DataRow, ReadyForQuery, RowDescription},
+            frontend::SimpleQuery,
+            ssl::{SSLRequest, SSLResponse},
+            Message,
+        },
+        mock::MockServer,
+        state::{Authentication, ParameterStatus, TransactionStatus},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::PrivateKeyDer, RootCertStore, ServerConfig},
+        TlsAcceptor,
+    };
+
+    // A server that names itself in application_name and answers one
+    // `SHOW transaction_read_only`.
+    fn server(name: &str, read_only: &str) -> (MockServer, SocketAddr) {
+        let ready = ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        };
+        let mut responses = Authentication::Ok.encode();
+        ParameterStatus {
+            name: "application_name".to_string(),
+            value: name.to_string(),
+        }
+        .encode_into(&mut responses);
+        ready.encode_into(&mut responses);
+        RowDescription::builder()
+            .string_field("transaction_read_only")
+            .build()
+            .encode_into(&mut responses);
+        DataRow::builder()
+            .string_field(read_only)
+            .build()
+            .encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("SHOW")
+            .build()
+            .encode_into(&mut responses);
+        ready.encode_into(&mut responses);
+        MockServer::listen(responses)
+    }
+
+    #[test]
+    fn test_read_write_skips_read_only_host() -> Result<(), Box<dyn Error>> {
+        let (standby, standby_address) = server("standby", "on");
+        let (primary, primary_address) = server("primary", "off");
+
+        let client = Config::new()
+            .add_host("127.0.0.1", standby_address.port())
+            .add_host("127.0.0.1", primary_address.port())
+            .user("postgres")
+            .target_session_attrs(TargetSessionAttrs::ReadWrite)
+            .connect()?;
+        assert_eq!(client.parameter("application_name"), Some("primary"));
+        drop(client);
+
+        let show = SimpleQuery::new("SHOW transaction_read_only").encode();
+        assert!(standby.received().ends_with(&show));
+        assert!(primary.received().ends_with(&show));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_skips_read_write_host() -> Result<(), Box<dyn Error>> {
+        let (primary, primary_address) = server("primary", "off");
+        let (standby, standby_address) = server("standby", "on");
+
+        let client = Config::new()
+            .add_host("127.0.0.1", primary_address.port())
+            .add_host("127.0.0.1", standby_address.port())
+            .user("postgres")
+            .target_session_attrs(TargetSessionAttrs::ReadOnly)
+            .connect()?;
+        assert_eq!(client.parameter("application_name"), Some("standby"));
+        drop(client);
+
+        let show = SimpleQuery::new("SHOW transaction_read_only").encode();
+        assert!(primary.received().ends_with(&show));
+        assert!(standby.received().ends_with(&show));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replication_parameter() {
+        let config = Config::new().user("replicator").database("app");
+        assert_eq!(config.startup_message().parameter("replication"), None);
+
+        let startup = config
+            .replication(ReplicationMode::Database)
+            .startup_message();
+        assert_eq!(startup.parameter("replication"), Some("database"));
+        assert_eq!(startup.parameter("user"), Some("replicator"));
+        assert_eq!(startup.parameter("database"), Some("app"));
+    }
+
+    #[test]
+    fn test_plaintext_warning() {
+        let remote: SocketAddr = "192.0.2.10:5432".parse().unwrap();
+        let warning = Config::new().plaintext_warning(remote).unwrap();
+        assert!(warning.contains("192.0.2.10:5432"));
+
+        assert_eq!(
+            Config::new()
+                .allow_plaintext(true)
+                .plaintext_warning(remote),
+            None
+        );
+        for loopback in ["127.0.0.1:5432", "[::1]:5432", "[::ffff:127.0.0.1]:5432"] {
+            assert_eq!(
+                Config::new().plaintext_warning(loopback.parse().unwrap()),
+                None
+            );
+        }
+    }
+
+    // A server that answers an SSLRequest with `response`, performing the
+    // handshake on S, and then lets any startup through. It panics if the
+    // client does not follow its answer.
+    async fn tls_server(
+        response: SSLResponse,
+    ) -> Result<(SocketAddr, Arc<ClientConfig>, JoinHandle<()>), Box<dyn Error>> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let certificate = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(certified.signing_key.serialize_der().into());
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], key)?;
+        let mut roots = RootCertStore::empty();
+        roots.add(certificate)?;
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut ssl_request = [0; 8];
+            stream.read_exact(&mut ssl_request).await.unwrap();
+            assert_eq!(ssl_request.to_vec(), SSLRequest.encode());
+            stream.write_all(&response.encode()).await.unwrap();
+
+            let mut stream: Box<dyn AsyncReadWrite> = match response {
+                SSLResponse::S => {
+                    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+                    Box::new(acceptor.accept(stream).await.unwrap())
+                }
+                SSLResponse::N => Box::new(stream),
+            };
+            let length = stream.read_u32().await.unwrap();
+            let mut startup = vec![0; length as usize - 4];
+            stream.read_exact(&mut startup).await.unwrap();
+            let mut responses = Authentication::Ok.encode();
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode_into(&mut responses);
+            stream.write_all(&responses).await.unwrap();
+            stream.flush().await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+        });
+        Ok((address, Arc::new(client_config), server))
+    }
+
+    trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
+    impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncReadWrite for S {}
+
+    #[tokio::test]
+    async fn test_connect_prefers_tls() -> Result<(), Box<dyn Error>> {
+        let (address, tls, server) = tls_server(SSLResponse::S).await?;
+
+        let client = Config::new()
+            .add_host("localhost", address.port())
+            .tls(tls)
+            .connect_async()
+            .await?;
+        client.shutdown().await?;
+        server.await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_falls_back_to_plaintext() -> Result<(), Box<dyn Error>> {
+        let (address, tls, server) = tls_server(SSLResponse::N).await?;
+
+        let client = Config::new()
+            .add_host("127.0.0.1", address.port())
+            .tls(tls)
+            .connect_async()
+            .await?;
+        client.shutdown().await?;
+        server.await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_host_matches() {
+        let (_standby, standby_address) = server("standby", "on");
+
+        let result = Config::new()
+            .add_host("127.0.0.1", standby_address.port())
+            .target_session_attrs(TargetSessionAttrs::ReadWrite)
+            .connect();
+        assert!(result.is_err());
+    }
+}
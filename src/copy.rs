@@ -0,0 +1,650 @@
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    backend::ReadTimeoutGuard,
+    messages::{
+        backend::{BackendMessage, ErrorResponse},
+        copy::{CopyData, CopyDone, CopyFail},
+        frontend::SimpleQuery,
+    },
+    state::ParameterStatus,
+    Client,
+};
+
+// The size of the CopyData frames sent by `copy_in_from_reader`. Small
+// enough to keep memory bounded, large enough that the per-message overhead
+// doesn't matter.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    Text,
+    Csv,
+    Binary,
+}
+
+impl CopyFormat {
+    fn option(&self) -> &'static str {
+        match self {
+            CopyFormat::Text => "text",
+            CopyFormat::Csv => "csv",
+            CopyFormat::Binary => "binary",
+        }
+    }
+
+    pub fn copy_out_query(&self, source: &str) -> String {
+        format!("COPY {source} TO STDOUT (FORMAT {})", self.option())
+    }
+
+    pub fn copy_in_query(&self, target: &str) -> String {
+        format!("COPY {target} FROM STDIN (FORMAT {})", self.option())
+    }
+}
+
+impl Client {
+    /// Starts a `COPY ... TO STDOUT` and yields the raw contents of each
+    /// CopyData message.
+    pub fn copy_out(&mut self, query: &str) -> Result<CopyOutReader<'_>, Box<dyn Error>> {
+        let timeout = self.operation_timeout()?;
+        match self.start_copy(query)? {
+            BackendMessage::CopyOutResponse(_) => Ok(CopyOutReader::new(self, timeout)),
+            message => {
+                self.abort_copy(&message);
+                Err(format!("expected CopyOutResponse, got {message:?}").into())
+            }
+        }
+    }
+
+    /// Copies `source` (a table name or a parenthesised query) out as CSV,
+    /// one record per row.
+    pub fn copy_out_csv(&mut self, source: &str) -> Result<CsvRecords<'_>, Box<dyn Error>> {
+        let reader = self.copy_out(&CopyFormat::Csv.copy_out_query(source))?;
+        Ok(CsvRecords { reader })
+    }
+
+    pub fn copy_out_binary(&mut self, source: &str) -> Result<CopyOutReader<'_>, Box<dyn Error>> {
+        self.copy_out(&CopyFormat::Binary.copy_out_query(source))
+    }
+
+    /// Starts a `COPY ... FROM STDIN`. Everything written is sent as
+    /// CopyData; the copy must be ended with `finish` or `fail`.
+    pub fn copy_in(&mut self, query: &str) -> Result<CopyInWriter<'_>, Box<dyn Error>> {
+        let timeout = self.operation_timeout()?;
+        match self.start_copy(query)? {
+            BackendMessage::CopyInResponse(_) => Ok(CopyInWriter {
+                client: self,
+                finished: false,
+                _timeout: timeout,
+            }),
+            message => {
+                self.abort_copy(&message);
+                Err(format!("expected CopyInResponse, got {message:?}").into())
+            }
+        }
+    }
+
+    pub fn copy_in_csv(&mut self, target: &str) -> Result<CopyInWriter<'_>, Box<dyn Error>> {
+        self.copy_in(&CopyFormat::Csv.copy_in_query(target))
+    }
+
+    /// Runs a `COPY ... FROM STDIN` fed from `reader`, like psql's `\copy`,
+    /// and returns the number of rows copied. The copy is aborted with
+    /// CopyFail if the reader fails.
+    pub fn copy_in_from_reader(
+        &mut self,
+        query: &str,
+        mut reader: impl Read,
+    ) -> Result<u64, Box<dyn Error>> {
+        let mut writer = self.copy_in(query)?;
+        let mut buffer = vec![0; COPY_CHUNK_SIZE];
+
+        loop {
+            // Fill the whole chunk so that short reads don't turn into
+            // lots of tiny CopyData messages.
+            let mut filled = 0;
+            while filled < buffer.len() {
+                match reader.read(&mut buffer[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        writer.fail(&format!("error reading COPY data: {err}"))?;
+                        return Err(err.into());
+                    }
+                }
+            }
+
+            if filled == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..filled])?;
+            if filled < buffer.len() {
+                break;
+            }
+        }
+
+        writer.finish()
+    }
+
+    fn start_copy(&mut self, query: &str) -> Result<BackendMessage, Box<dyn Error>> {
+        self.close_dropped_statements()?;
+        self.backend.send_message(SimpleQuery::new_trusted(query))?;
+
+        loop {
+            match self.read_message()? {
+                BackendMessage::ParameterStatus(status) => self.record_parameter(status),
+                BackendMessage::ErrorResponse(error) => {
+                    self.read_until_ready()?;
+                    return Err(error.into());
+                }
+                BackendMessage::ReadyForQuery(_) => {
+                    return Err("query did not start a COPY".into());
+                }
+                BackendMessage::NoticeMessage(_) => {}
+                message => return Ok(message),
+            }
+        }
+    }
+
+    // Gets the connection back to ReadyForQuery after a COPY in the
+    // unexpected direction.
+    fn abort_copy(&mut self, response: &BackendMessage) {
+        match response {
+            BackendMessage::CopyInResponse(_) => {
+                let _ = self
+                    .backend
+                    .send_message(CopyFail::new("unexpected COPY FROM STDIN"));
+                let _ = self.read_until_ready();
+            }
+            BackendMessage::CopyOutResponse(_) => CopyOutReader::new(self, None).drain(),
+            _ => {
+                let _ = self.read_until_ready();
+            }
+        }
+    }
+
+    // Reads the rest of the response, returning the row count from the
+    // CommandComplete and the first ErrorResponse as the error.
+    fn read_until_ready(&mut self) -> Result<Option<u64>, Box<dyn Error>> {
+        let mut rows = None;
+        let mut error: Option<ErrorResponse> = None;
+
+        loop {
+            match self.read_message()? {
+                BackendMessage::CommandComplete(command_complete) => {
+                    rows = command_complete.rows_affected();
+                }
+                BackendMessage::ErrorResponse(error_response) => {
+                    error.get_or_insert(error_response);
+                }
+                BackendMessage::ParameterStatus(status) => self.record_parameter(status),
+                BackendMessage::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+
+        match error {
+            Some(error) => Err(error.into()),
+            None => Ok(rows),
+        }
+    }
+
+    fn record_parameter(&mut self, ParameterStatus { name, value }: ParameterStatus) {
+        self.parameters.insert(name, value);
+    }
+}
+
+pub struct CopyOutReader<'a> {
+    client: &'a mut Client,
+    rows: Option<u64>,
+    finished: bool,
+    // Keeps the client's read timeout for as long as the copy runs.
+    _timeout: Option<ReadTimeoutGuard>,
+}
+
+impl<'a> CopyOutReader<'a> {
+    fn new(client: &'a mut Client, timeout: Option<ReadTimeoutGuard>) -> Self {
+        Self {
+            client,
+            rows: None,
+            finished: false,
+            _timeout: timeout,
+        }
+    }
+
+    /// The row count reported by the server, once the copy has finished.
+    pub fn rows(&self) -> Option<u64> {
+        self.rows
+    }
+
+    fn drain(&mut self) {
+        while self.next().is_some() {}
+    }
+}
+
+impl Iterator for CopyOutReader<'_> {
+    type Item = Result<Vec<u8>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            let message = match self.client.read_message() {
+                Ok(message) => message,
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            };
+
+            match message {
+                BackendMessage::CopyData(CopyData { data }) => return Some(Ok(data)),
+                BackendMessage::CopyDone(_) => {
+                    self.finished = true;
+                    return match self.client.read_until_ready() {
+                        Ok(rows) => {
+                            self.rows = rows;
+                            None
+                        }
+                        Err(err) => Some(Err(err)),
+                    };
+                }
+                BackendMessage::ErrorResponse(error) => {
+                    self.finished = true;
+                    return Some(self.client.read_until_ready().and(Err(error.into())));
+                }
+                BackendMessage::ParameterStatus(status) => self.client.record_parameter(status),
+                BackendMessage::ReadyForQuery(_) => {
+                    self.finished = true;
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for CopyOutReader<'_> {
+    fn drop(&mut self) {
+        // Leave the connection ready for the next query.
+        self.drain();
+    }
+}
+
+pub struct CsvRecords<'a> {
+    reader: CopyOutReader<'a>,
+}
+
+impl CsvRecords<'_> {
+    pub fn rows(&self) -> Option<u64> {
+        self.reader.rows()
+    }
+}
+
+impl Iterator for CsvRecords<'_> {
+    type Item = Result<Vec<Option<String>>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Postgres sends each row in its own CopyData, so a record never
+        // spans messages even when a quoted field contains a newline.
+        self.reader
+            .next()
+            .map(|data| data.and_then(|data| parse_csv_record(&data)))
+    }
+}
+
+// Parses one record of postgres' CSV output. An unquoted empty field is
+// NULL, a quoted one is the empty string.
+pub(crate) fn parse_csv_record(data: &[u8]) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    let line = std::str::from_utf8(data)?;
+    let line = line
+        .strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix('\n'))
+        .unwrap_or(line);
+
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let field = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        value.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated quoted field in CSV record".into()),
+                }
+            }
+            Some(value)
+        } else {
+            let mut value = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ',' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+            (!value.is_empty()).then_some(value)
+        };
+        fields.push(field);
+
+        match chars.next() {
+            Some(',') => continue,
+            None => break,
+            Some(c) => return Err(format!("unexpected {c:?} after quoted CSV field").into()),
+        }
+    }
+
+    Ok(fields)
+}
+
+pub struct CopyInWriter<'a> {
+    client: &'a mut Client,
+    finished: bool,
+    // Keeps the client's read timeout for as long as the copy runs.
+    _timeout: Option<ReadTimeoutGuard>,
+}
+
+impl CopyInWriter<'_> {
+    /// Ends the copy and returns the number of rows copied.
+    pub fn finish(mut self) -> Result<u64, Box<dyn Error>> {
+        if self.finished {
+            return Err("the server has already ended the copy".into());
+        }
+        self.finished = true;
+        self.client.backend.send_message(CopyDone)?;
+        Ok(self.client.read_until_ready()?.unwrap_or_default())
+    }
+
+    /// Aborts the copy. The server rolls back whatever was sent so far.
+    pub fn fail(mut self, message: &str) -> Result<(), Box<dyn Error>> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.client.backend.send_message(CopyFail::new(message))?;
+
+        // The server always answers a CopyFail with an ErrorResponse.
+        match self.client.read_until_ready() {
+            Ok(_) => Err("server accepted a failed COPY".into()),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl CopyInWriter<'_> {
+    /// Flushes the data written so far and checks, without waiting, whether
+    /// the server has already rejected the copy, so that a long copy can
+    /// stop early rather than only find out at `finish`. Once an error has
+    /// been returned the copy is over, and `finish` fails.
+    pub fn flush_and_poll(&mut self) -> Result<Option<ErrorResponse>, Box<dyn Error>> {
+        if self.finished {
+            return Err("the server has already ended the copy".into());
+        }
+        self.client.backend.flush()?;
+
+        while self.client.backend.wait_readable(None)? {
+            match self.client.read_message()? {
+                BackendMessage::ErrorResponse(error_response) => {
+                    // The server ends the copy with the error, so the
+                    // ReadyForQuery that follows is already on its way.
+                    self.finished = true;
+                    self.client.read_until_ready()?;
+                    return Ok(Some(error_response));
+                }
+                BackendMessage::NoticeMessage(notice) => self.client.handle_notice(&notice),
+                BackendMessage::ParameterStatus(status) => self.client.record_parameter(status),
+                message => {
+                    return Err(format!("unexpected {} during COPY", message.name()).into());
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Write for CopyInWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The server doesn't care where CopyData frames break, so data too
+        // big for one message is sent in several.
+        let chunk_size = (self.client.backend.max_message_size() as usize)
+            .checked_sub(5)
+            .filter(|&size| size > 0)
+            .ok_or_else(|| io::Error::other("max_message_size leaves no room for COPY data"))?;
+        for chunk in buf.chunks(chunk_size) {
+            self.client
+                .backend
+                .send_message(CopyData::new(chunk))
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CopyInWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            eprintln!("CopyInWriter dropped without finish or fail");
+            let _ = self
+                .client
+                .backend
+                .send_message(CopyFail::new("copy abandoned"));
+            let _ = self.client.read_until_ready();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, ReadyForQuery},
+            copy::{CopyInResponse, CopyOutResponse},
+            Message,
+        },
+        mock::MockServer,
+        state::TransactionStatus,
+        Backend,
+    };
+
+    fn ready() -> Vec<u8> {
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode()
+    }
+
+    fn copy_in_response() -> Vec<u8> {
+        CopyInResponse {
+            format: 0,
+            column_formats: vec![0, 0],
+        }
+        .encode()
+    }
+
+    #[test]
+    fn test_copy_in_from_reader() -> Result<(), Box<dyn Error>> {
+        let mut responses = copy_in_response();
+        responses.extend(CommandComplete::builder().tag("COPY 2").build().encode());
+        responses.extend(ready());
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let data = b"1,one\n2,two\n";
+        let rows = client.copy_in_from_reader("COPY pairs FROM STDIN (FORMAT csv)", &data[..])?;
+        assert_eq!(rows, 2);
+        drop(client);
+
+        let mut expected = SimpleQuery::new("COPY pairs FROM STDIN (FORMAT csv)").encode();
+        expected.extend(CopyData::new(&data[..]).encode());
+        expected.extend(CopyDone.encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_in_splits_oversized_data() -> Result<(), Box<dyn Error>> {
+        let mut responses = copy_in_response();
+        responses.extend(CommandComplete::builder().tag("COPY 2").build().encode());
+        responses.extend(ready());
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut writer = client.copy_in("COPY pairs FROM STDIN (FORMAT csv)")?;
+        writer.client.backend.set_max_message_size(10);
+        writer.write_all(b"1,one\n2,two\n")?;
+        assert_eq!(writer.finish()?, 2);
+        drop(client);
+
+        let mut expected = SimpleQuery::new("COPY pairs FROM STDIN (FORMAT csv)").encode();
+        expected.extend(CopyData::new(&b"1,one"[..]).encode());
+        expected.extend(CopyData::new(&b"\n2,tw"[..]).encode());
+        expected.extend(CopyData::new(&b"o\n"[..]).encode());
+        expected.extend(CopyDone.encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_in_flush_and_poll() -> Result<(), Box<dyn Error>> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let stream = std::net::TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        server.write_all(&copy_in_response())?;
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut writer = client.copy_in("COPY pairs FROM STDIN (FORMAT csv)")?;
+        writer.write_all(b"1,one\n")?;
+        assert_eq!(writer.flush_and_poll()?, None);
+
+        let error = ErrorResponse::new(
+            crate::messages::backend::Severity::Error,
+            "22P02",
+            "invalid input syntax for type integer",
+        );
+        let mut responses = error.encode();
+        responses.extend(ready());
+        server.write_all(&responses)?;
+        assert!(writer
+            .client
+            .backend
+            .wait_readable(Some(std::time::Duration::from_secs(5)))?);
+
+        assert_eq!(writer.flush_and_poll()?, Some(error));
+        assert!(writer.finish().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_in_from_failing_reader() -> Result<(), Box<dyn Error>> {
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+        }
+
+        let mut responses = copy_in_response();
+        responses.extend(
+            ErrorResponse::new(
+                crate::messages::backend::Severity::Error,
+                "57014",
+                "COPY from stdin failed",
+            )
+            .encode(),
+        );
+        responses.extend(ready());
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let err = client
+            .copy_in_from_reader("COPY pairs FROM STDIN", FailingReader)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "disk on fire");
+        drop(client);
+
+        let mut expected = SimpleQuery::new("COPY pairs FROM STDIN").encode();
+        expected.extend(CopyFail::new("error reading COPY data: disk on fire").encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_out_csv() -> Result<(), Box<dyn Error>> {
+        let mut responses = CopyOutResponse {
+            format: 0,
+            column_formats: vec![0, 0],
+        }
+        .encode();
+        responses.extend(CopyData::new("1,one\n").encode());
+        responses.extend(CopyData::new("2,\"two, \"\"2\"\"\nlines\"\n").encode());
+        responses.extend(CopyData::new("3,\n").encode());
+        responses.extend(CopyDone.encode());
+        responses.extend(CommandComplete::builder().tag("COPY 3").build().encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut records = client.copy_out_csv("pairs")?;
+        let rows = records.by_ref().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                vec![Some("1".to_string()), Some("one".to_string())],
+                vec![Some("2".to_string()), Some("two, \"2\"\nlines".to_string())],
+                vec![Some("3".to_string()), None],
+            ]
+        );
+        assert_eq!(records.rows(), Some(3));
+        drop(records);
+        drop(client);
+
+        assert_eq!(
+            server.received(),
+            SimpleQuery::new("COPY pairs TO STDOUT (FORMAT csv)").encode()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_record() -> Result<(), Box<dyn Error>> {
+        assert_eq!(parse_csv_record(b"\n")?, vec![None]);
+        assert_eq!(
+            parse_csv_record(b"\"\",\r\n")?,
+            vec![Some(String::new()), None]
+        );
+        assert_eq!(
+            parse_csv_record(b"a,\"b,c\"\n")?,
+            vec![Some("a".to_string()), Some("b,c".to_string())]
+        );
+        assert!(parse_csv_record(b"\"open\n").is_err());
+        assert!(parse_csv_record(b"\"a\"b\n").is_err());
+
+        Ok(())
+    }
+}
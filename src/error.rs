@@ -0,0 +1,53 @@
+use core::fmt;
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    LengthTooShort { length: u32 },
+    ColumnCountMismatch { expected: usize, got: usize },
+    UnsupportedProtocolVersion { major: u16, minor: u16 },
+    FieldTooLong { length: u32, remaining: usize },
+    MessageTooLong { length: u32, max: u32 },
+    LengthOverflow { length: usize },
+    CountOverflow { count: usize },
+    ScramParameterOutOfRange { name: &'static str, value: String },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::LengthTooShort { length } => {
+                write!(f, "message length {length} is shorter than its length field")
+            }
+            ProtocolError::ColumnCountMismatch { expected, got } => {
+                write!(f, "expected {expected} columns in DataRow, got {got}")
+            }
+            ProtocolError::UnsupportedProtocolVersion { major, minor } => {
+                write!(f, "unsupported protocol version: {major}.{minor}")
+            }
+            ProtocolError::MessageTooLong { length, max } => {
+                write!(
+                    f,
+                    "message length {length} is longer than the maximum of {max}"
+                )
+            }
+            ProtocolError::ScramParameterOutOfRange { name, value } => {
+                write!(f, "SCRAM parameter {name}={value:?} is out of range")
+            }
+            ProtocolError::LengthOverflow { length } => {
+                write!(f, "message length {length} does not fit in a length field")
+            }
+            ProtocolError::CountOverflow { count } => {
+                write!(f, "count {count} does not fit in a 16-bit count field")
+            }
+            ProtocolError::FieldTooLong { length, remaining } => {
+                write!(
+                    f,
+                    "field length {length} is longer than the {remaining} bytes left in DataRow"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ProtocolError {}
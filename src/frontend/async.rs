@@ -0,0 +1,223 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_core::stream::Stream;
+use std::{error::Error, sync::Arc};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+use crate::messages::{frontend::FrontendMessage, startup::StartupRequest, Message};
+
+#[derive(Debug)]
+pub struct AsyncFrontend<S = TcpStream> {
+    reader: Arc<Mutex<BufReader<ReadHalf<S>>>>,
+    writer: Arc<Mutex<WriteHalf<S>>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncFrontend<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = io::split(stream);
+        Self {
+            reader: Arc::new(Mutex::new(BufReader::new(reader))),
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    pub async fn send_message(
+        &mut self,
+        message: impl Message + std::fmt::Debug,
+    ) -> Result<(), Box<dyn Error>> {
+        println!("Frontend send_message: {message:?}");
+
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&message.try_encode()?).await?;
+        if message.requires_flush() {
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Reads startup requests until a Startup or CancelRequest. An
+    /// SSLRequest does not end the stream, since the client follows it up
+    /// once it has an SSLResponse.
+    pub fn read_startup_messages(&mut self) -> impl Stream<Item = StartupRequest> {
+        MessageStream::new(
+            self.reader.clone(),
+            |reader| {
+                Box::pin(async move {
+                    let mut reader = reader.lock().await;
+                    StartupRequest::read_next_message_async(&mut *reader).await
+                })
+            },
+            |message| !matches!(message, StartupRequest::SSLRequest(_)),
+        )
+    }
+
+    /// Reads frontend messages until Termination or the connection closes.
+    pub fn read_messages(&mut self) -> impl Stream<Item = FrontendMessage> {
+        MessageStream::new(
+            self.reader.clone(),
+            |reader| {
+                Box::pin(async move {
+                    let mut reader = reader.lock().await;
+                    FrontendMessage::read_next_message_async(&mut *reader).await
+                })
+            },
+            |message| matches!(message, FrontendMessage::Termination(_)),
+        )
+    }
+}
+
+type Reader<S> = Arc<Mutex<BufReader<ReadHalf<S>>>>;
+type ReadFuture<T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + Send>>;
+
+// Clients pipeline their messages, so a read is often left waiting halfway
+// through one. The stream keeps that read alive between polls rather than
+// starting over, which would lose whatever it had already consumed.
+struct MessageStream<S, T> {
+    reader: Reader<S>,
+    read: fn(Reader<S>) -> ReadFuture<T>,
+    is_last: fn(&T) -> bool,
+    future: Option<ReadFuture<T>>,
+    finished: bool,
+}
+
+impl<S, T> MessageStream<S, T> {
+    fn new(
+        reader: Reader<S>,
+        read: fn(Reader<S>) -> ReadFuture<T>,
+        is_last: fn(&T) -> bool,
+    ) -> Self {
+        Self {
+            reader,
+            read,
+            is_last,
+            future: None,
+            finished: false,
+        }
+    }
+}
+
+impl<S, T> Stream for MessageStream<S, T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
+        let future = this
+            .future
+            .get_or_insert_with(|| (this.read)(this.reader.clone()));
+        let Poll::Ready(result) = future.as_mut().poll(cx) else {
+            return Poll::Pending;
+        };
+        this.future = None;
+
+        match result {
+            Ok(item) => {
+                this.finished = (this.is_last)(&item);
+                Poll::Ready(Some(item))
+            }
+            Err(err) => {
+                this.finished = true;
+                eprintln!("error reading frontend message: {err}");
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            frontend::{SimpleQuery, Termination},
+            ssl::{SSLRequest, SSLResponse},
+            startup::Startup,
+        },
+        state::{Authentication, ReadyForQuery, TransactionStatus},
+    };
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_startup() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream.write_all(&SSLRequest.encode()).await.unwrap();
+            assert_eq!(stream.read_u8().await.unwrap(), b'N');
+
+            let mut startup = Startup::new();
+            startup.add_parameter("user", "postgres");
+            stream.write_all(&startup.encode()).await.unwrap();
+            stream
+                .write_all(&SimpleQuery::new("SELECT 1").encode())
+                .await
+                .unwrap();
+            stream.write_all(&Termination.encode()).await.unwrap();
+
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let (stream, _) = listener.accept().await?;
+        let mut frontend = AsyncFrontend::new(stream);
+
+        let mut requests = vec![];
+        let mut messages = frontend.read_startup_messages();
+        while let Some(message) = messages.next().await {
+            match message {
+                StartupRequest::SSLRequest(_) => frontend.send_message(SSLResponse::N).await?,
+                StartupRequest::Startup(_) => {
+                    frontend.send_message(Authentication::Ok).await?;
+                    frontend
+                        .send_message(ReadyForQuery {
+                            transaction_status: TransactionStatus::Idle,
+                        })
+                        .await?;
+                }
+                StartupRequest::CancelRequest(_) => {}
+            }
+            requests.push(message);
+        }
+        drop(messages);
+        assert_eq!(requests.len(), 2);
+        let StartupRequest::Startup(startup) = &requests[1] else {
+            panic!("expected Startup, got {:?}", requests[1]);
+        };
+        assert_eq!(startup.parameter("user"), Some("postgres"));
+
+        let messages: Vec<FrontendMessage> = frontend.read_messages().collect().await;
+        assert_eq!(
+            messages,
+            vec![
+                FrontendMessage::SimpleQuery(SimpleQuery::new("SELECT 1")),
+                FrontendMessage::Termination(Termination),
+            ]
+        );
+        drop(frontend);
+
+        let mut expected = Authentication::Ok.encode();
+        expected.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        assert_eq!(client.await?, expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+mod r#async;
+mod sync;
+
+pub use r#async::AsyncFrontend;
+pub use sync::Frontend;
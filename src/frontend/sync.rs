@@ -12,6 +12,12 @@ impl Frontend {
         Self { stream }
     }
 
+    /// Another handle to the same connection, such as for writing from
+    /// another thread.
+    pub fn try_clone(&self) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(self.stream.try_clone()?))
+    }
+
     pub fn read_startup_messages(
         &mut self,
     ) -> Result<impl Iterator<Item = StartupRequest>, Box<dyn Error>> {
@@ -64,8 +70,10 @@ impl Frontend {
         message: impl Message + core::fmt::Debug,
     ) -> Result<(), Box<dyn Error>> {
         println!("Frontend send_message: {message:?}");
-        self.stream.write_all(&message.encode())?;
-        //self.stream.flush()?;
+        self.stream.write_all(&message.try_encode()?)?;
+        if message.requires_flush() {
+            self.stream.flush()?;
+        }
         Ok(())
     }
 }
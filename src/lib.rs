@@ -1,9 +1,57 @@
+pub mod auth;
+mod cancel;
+pub use cancel::CancelToken;
+mod codec;
+pub use codec::Codec;
+mod error;
+pub use error::ProtocolError;
+
 pub mod messages;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod test_support;
 mod readers;
+mod row;
+pub use row::{FromRow, FromRowError, Row};
+#[cfg(feature = "derive")]
+pub use rpsql_derive::FromRow;
+mod row_set;
+pub use row_set::{Column, RowSet};
+mod spans;
+pub mod sql;
 pub mod state;
+pub mod types;
+
+mod client;
+pub use client::{Client, QueryEvent, QueryResult};
+
+mod async_client;
+pub use async_client::AsyncClient;
+
+mod config;
+pub use config::{Config, ReplicationMode, TargetSessionAttrs};
+
+mod copy;
+pub use copy::{CopyFormat, CopyInWriter, CopyOutReader, CsvRecords};
+
+mod pool;
+pub use pool::{Pool, PooledClient};
+
+mod portal;
+pub use portal::PortalReader;
+
+mod statement;
+pub use statement::{ResultFormat, Statement, StatementKey};
+
+mod transaction;
+pub use transaction::Transaction;
 
 mod frontend;
-pub use frontend::Frontend;
+pub use frontend::{AsyncFrontend, Frontend};
 
 mod backend;
-pub use backend::{AsyncBackend, Backend};
+pub use backend::{
+    AsyncBackend, AsyncTlsBackend, AsyncTransaction, Backend, Direction, MaybeTlsStream,
+    RecordedFrame, RecordingBackend, ReplayBackend, DEFAULT_MAX_MESSAGE_SIZE,
+};
@@ -1,58 +1,170 @@
-use crate::readers::*;
-use std::{error::Error, io::Read, str};
+use crate::{readers::*, ProtocolError};
+use std::{error::Error, ops::Range};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// The length sent in place of a NULL field's.
+const NULL_LENGTH: u32 = 0xFFFFFFFF;
+
+// The field values live in one buffer rather than one allocation per field,
+// and are handed out as borrowed slices. A row read off the wire keeps the
+// message body as it arrived, length prefixes and all, and its fields point
+// into it; a built row stores its fields back to back.
+#[derive(Debug, Default, Clone)]
 pub struct DataRow {
-    pub fields: Vec<Option<String>>,
+    body: Vec<u8>,
+    fields: Vec<Option<Range<usize>>>,
 }
 
 impl DataRow {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let field_count = read_u16(stream)? as usize;
-        let mut fields: Vec<Option<String>> = vec![None; field_count as usize];
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        // Field lengths are checked against what is left of the message, so
+        // a bad length cannot ask for more memory than the message itself.
+        let body = stream.read_remaining()?;
+        let mut remaining = body.as_slice();
 
-        for index in 0..field_count {
-            let field_length = read_u32(stream)? as usize;
+        let field_count = read_u16(&mut remaining)? as usize;
+        let mut fields = Vec::with_capacity(field_count);
+        for _ in 0..field_count {
+            let field_length = read_u32(&mut remaining)?;
+            if field_length == NULL_LENGTH {
+                fields.push(None);
+                continue;
+            }
 
-            match field_length {
-                0xFFFFFFFF => {
-                    continue;
-                }
-                size => {
-                    let field_value = read_bytes(size, stream)?;
-                    fields[index] = Some(str::from_utf8(&field_value)?.to_string());
+            let size = usize::try_from(field_length)?;
+            if size > remaining.len() {
+                return Err(ProtocolError::FieldTooLong {
+                    length: field_length,
+                    remaining: remaining.len(),
                 }
+                .into());
             }
+
+            let start = body.len() - remaining.len();
+            fields.push(Some(start..start + size));
+            remaining = &remaining[size..];
         }
 
-        Ok(DataRow { fields })
+        Ok(DataRow { body, fields })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The raw bytes of a field, or None if it is NULL or out of range.
+    pub fn field(&self, index: usize) -> Option<&[u8]> {
+        let range = self.fields.get(index)?.clone()?;
+        Some(&self.body[range])
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = Option<&[u8]>> {
+        self.fields
+            .iter()
+            .map(|range| range.clone().map(|range| &self.body[range]))
+    }
+
+    pub fn is_null(&self, index: usize) -> bool {
+        matches!(self.fields.get(index), Some(None))
+    }
+}
+
+// Rows are equal when their fields are, however their buffers are laid out.
+impl PartialEq for DataRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields().eq(other.fields())
     }
 }
 
+impl Eq for DataRow {}
+
 impl DataRow {
     pub fn builder() -> DataRowBuilder {
-        DataRowBuilder { fields: Vec::new() }
+        DataRowBuilder {
+            data_row: DataRow::default(),
+        }
     }
 }
 
 pub struct DataRowBuilder {
-    fields: Vec<Option<String>>,
+    data_row: DataRow,
 }
 
 impl DataRowBuilder {
-    pub fn string_field(mut self, field: impl Into<String>) -> Self {
-        self.fields.push(Some(field.into()));
+    pub fn string_field(self, field: impl Into<String>) -> Self {
+        self.binary_field(field.into())
+    }
+
+    pub fn binary_field(mut self, field: impl AsRef<[u8]>) -> Self {
+        let start = self.data_row.body.len();
+        self.data_row.body.extend_from_slice(field.as_ref());
+        self.data_row
+            .fields
+            .push(Some(start..self.data_row.body.len()));
         self
     }
 
     pub fn null_field(mut self) -> Self {
-        self.fields.push(None);
+        self.data_row.fields.push(None);
         self
     }
 
     pub fn build(self) -> DataRow {
-        DataRow {
-            fields: self.fields,
-        }
+        self.data_row
     }
 }
+
+#[test]
+fn test_data_row_fields() -> Result<(), Box<dyn Error>> {
+    let data_row = DataRow::builder()
+        .string_field("1")
+        .null_field()
+        .binary_field([0xde, 0xad])
+        .build();
+
+    assert_eq!(data_row.len(), 3);
+    assert_eq!(data_row.field(0), Some(&b"1"[..]));
+    assert_eq!(data_row.field(1), None);
+    assert!(data_row.is_null(1));
+    assert_eq!(data_row.field(2), Some(&[0xde, 0xad][..]));
+    assert_eq!(data_row.field(3), None);
+    assert!(!data_row.is_null(3));
+
+    Ok(())
+}
+
+#[test]
+fn test_data_row_null_marker() -> Result<(), Box<dyn Error>> {
+    let mut body = vec![0, 2];
+    body.extend_from_slice(&NULL_LENGTH.to_be_bytes());
+    body.extend_from_slice(&[0, 0, 0, 1, b'x']);
+
+    let data_row = DataRow::read_next_message(&mut body.as_slice())?;
+    assert!(data_row.is_null(0));
+    assert_eq!(data_row.field(1), Some(&b"x"[..]));
+    assert_eq!(
+        data_row,
+        DataRow::builder().null_field().string_field("x").build()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_data_row_field_too_long() {
+    let mut body = vec![0, 1];
+    body.extend_from_slice(&0xFFFFFFFEu32.to_be_bytes());
+    body.extend_from_slice(b"short");
+
+    let err = DataRow::read_next_message(&mut body.as_slice()).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ProtocolError>(),
+        Some(&ProtocolError::FieldTooLong {
+            length: 0xFFFFFFFE,
+            remaining: 5
+        })
+    );
+}
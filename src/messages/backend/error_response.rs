@@ -0,0 +1,348 @@
+use crate::messages::{
+    backend::{
+        notice_message::{encode_extra_fields, encode_optional_fields, parse_position, Severity},
+        NoticeMessage,
+    },
+    Message,
+};
+use crate::readers::ByteReader;
+use core::fmt;
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    pub position: Option<String>,
+    pub internal_position: Option<String>,
+    pub internal_query: Option<String>,
+    /// Fields of types this crate doesn't know, as in [`NoticeMessage`].
+    pub extra: Vec<(u8, String)>,
+}
+
+impl ErrorResponse {
+    pub fn new(severity: Severity, code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: code.into(),
+            message: message.into(),
+            detail: None,
+            hint: None,
+            position: None,
+            internal_position: None,
+            internal_query: None,
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn builder() -> ErrorResponseBuilder {
+        ErrorResponseBuilder::new()
+    }
+
+    /// The 1-based character offset into the query, if the server sent a
+    /// valid one. The raw value is in the `position` field.
+    pub fn position(&self) -> Option<u32> {
+        parse_position(&self.position)
+    }
+
+    pub fn internal_position(&self) -> Option<u32> {
+        parse_position(&self.internal_position)
+    }
+
+    /// Whether the server is ending the session, as it does after a FATAL
+    /// or PANIC error instead of sending ReadyForQuery.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self.severity, Severity::Fatal | Severity::Panic)
+    }
+
+    pub fn sqlstate(&self) -> &str {
+        &self.code
+    }
+
+    /// Whether the SQLSTATE belongs to a class, given by its first two
+    /// characters, e.g. "23" for integrity constraint violations.
+    pub fn is_class(&self, class: &str) -> bool {
+        class.len() == 2 && self.code.get(..2) == Some(class)
+    }
+
+    pub fn is_unique_violation(&self) -> bool {
+        self.code == "23505"
+    }
+
+    pub fn is_serialization_failure(&self) -> bool {
+        self.code == "40001"
+    }
+
+    pub fn is_deadlock(&self) -> bool {
+        self.code == "40P01"
+    }
+
+    pub fn is_undefined_table(&self) -> bool {
+        self.code == "42P01"
+    }
+
+    /// Whether a cursor or portal of the same name is already open.
+    pub fn is_duplicate_cursor(&self) -> bool {
+        self.code == "42P03"
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        // Errors and notices share the same field layout.
+        let NoticeMessage {
+            severity,
+            code,
+            message,
+            detail,
+            hint,
+            position,
+            internal_position,
+            internal_query,
+            extra,
+        } = NoticeMessage::read_next_message(stream)?;
+
+        Ok(Self {
+            severity,
+            code,
+            message,
+            detail,
+            hint,
+            position,
+            internal_position,
+            internal_query,
+            extra,
+        })
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.severity, self.message, self.code)
+    }
+}
+
+impl Error for ErrorResponse {}
+
+impl Message for ErrorResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        let mut inner = Vec::new();
+
+        // Severity
+        self.severity.encode_into(&mut inner);
+
+        // Code
+        inner.push(b'C');
+        inner.extend_from_slice(self.code.as_bytes());
+        inner.push(0);
+
+        // Message
+        inner.push(b'M');
+        inner.extend_from_slice(self.message.as_bytes());
+        inner.push(0);
+
+        encode_optional_fields(
+            &mut inner,
+            [
+                (b'D', &self.detail),
+                (b'H', &self.hint),
+                (b'P', &self.position),
+                (b'p', &self.internal_position),
+                (b'q', &self.internal_query),
+            ],
+        );
+        encode_extra_fields(&mut inner, &self.extra);
+
+        buffer.push(b'E');
+        buffer.extend_from_slice(&(inner.len() as u32 + 4 + 1).to_be_bytes());
+        buffer.extend_from_slice(&inner);
+
+        // terminator
+        buffer.push(0);
+    }
+}
+
+pub struct ErrorResponseBuilder {
+    severity: Option<Severity>,
+    code: Option<String>,
+    message: Option<String>,
+    detail: Option<String>,
+    hint: Option<String>,
+    position: Option<String>,
+}
+
+impl ErrorResponseBuilder {
+    pub fn new() -> Self {
+        Self {
+            severity: None,
+            code: None,
+            message: None,
+            detail: None,
+            hint: None,
+            position: None,
+        }
+    }
+
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.severity = Some(severity);
+        self
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn position(mut self, position: u32) -> Self {
+        self.position = Some(position.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<ErrorResponse, Box<dyn Error>> {
+        Ok(ErrorResponse {
+            severity: self.severity.unwrap_or(Severity::Error),
+            code: self.code.ok_or("ErrorResponse requires a code")?,
+            message: self.message.ok_or("ErrorResponse requires a message")?,
+            detail: self.detail,
+            hint: self.hint,
+            position: self.position,
+            internal_position: None,
+            internal_query: None,
+            extra: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::backend::BackendMessage;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_error_response() -> Result<(), Box<dyn Error>> {
+        let error_response = ErrorResponse::new(
+            Severity::Fatal,
+            "28P01",
+            "password authentication failed for user \"postgres\"",
+        );
+
+        let encoded = error_response.encode();
+        assert_eq!(&encoded[..1], b"E");
+        assert_eq!(&encoded[5..12], b"SFATAL\0");
+
+        let mut cursor = Cursor::new(encoded);
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::ErrorResponse(error_response));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_response_keeps_unknown_fields() -> Result<(), Box<dyn Error>> {
+        let mut error_response = ErrorResponse::new(Severity::Error, "42P01", "no such table");
+        error_response.extra.push((b'Z', "from a newer server".to_string()));
+
+        let encoded = error_response.encode();
+        assert!(encoded
+            .windows(21)
+            .any(|field| field == b"Zfrom a newer server\0"));
+
+        let mut cursor = Cursor::new(encoded);
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::ErrorResponse(error_response));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_response_builder() -> Result<(), Box<dyn Error>> {
+        let error_response = ErrorResponse::builder()
+            .code("23505")
+            .message("duplicate key value violates unique constraint \"users_pkey\"")
+            .detail("Key (id)=(1) already exists.")
+            .position(15)
+            .build()?;
+        assert_eq!(error_response.severity, Severity::Error);
+        assert_eq!(error_response.hint, None);
+        assert_eq!(error_response.position(), Some(15));
+
+        let mut cursor = Cursor::new(error_response.encode());
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::ErrorResponse(error_response));
+
+        assert!(ErrorResponse::builder().code("23505").build().is_err());
+        assert!(ErrorResponse::builder().message("oops").build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_response_position() {
+        let mut error_response = ErrorResponse::new(Severity::Error, "42601", "syntax error");
+        assert_eq!(error_response.position(), None);
+
+        error_response.position = Some("15".to_string());
+        assert_eq!(error_response.position(), Some(15));
+
+        error_response.position = Some("fifteen".to_string());
+        assert_eq!(error_response.position(), None);
+        assert_eq!(error_response.position.as_deref(), Some("fifteen"));
+    }
+
+    #[test]
+    fn test_sqlstate_predicates() {
+        let error = |code| ErrorResponse::new(Severity::Error, code, "oops");
+
+        let unique_violation = error("23505");
+        assert_eq!(unique_violation.sqlstate(), "23505");
+        assert!(unique_violation.is_class("23"));
+        assert!(!unique_violation.is_class("2"));
+        assert!(!unique_violation.is_class("40"));
+        assert!(unique_violation.is_unique_violation());
+        assert!(!error("23503").is_unique_violation());
+
+        let serialization_failure = error("40001");
+        assert!(serialization_failure.is_class("40"));
+        assert!(serialization_failure.is_serialization_failure());
+        assert!(!serialization_failure.is_deadlock());
+
+        let deadlock = error("40P01");
+        assert!(deadlock.is_class("40"));
+        assert!(deadlock.is_deadlock());
+        assert!(!deadlock.is_serialization_failure());
+
+        let undefined_table = error("42P01");
+        assert!(undefined_table.is_class("42"));
+        assert!(undefined_table.is_undefined_table());
+        assert!(!error("42703").is_undefined_table());
+
+        assert!(!error("").is_class(""));
+    }
+
+    #[test]
+    fn test_is_fatal() {
+        let error = |severity| ErrorResponse::new(severity, "57P01", "oops");
+
+        assert!(error(Severity::Fatal).is_fatal());
+        assert!(error(Severity::Panic).is_fatal());
+        assert!(!error(Severity::Error).is_fatal());
+        assert!(!error(Severity::Localized("FATAL".to_string())).is_fatal());
+    }
+}
@@ -1,24 +1,46 @@
 use std::{
     error::Error,
-    io::{Cursor, Read},
-    str,
+    io::{self, Read},
 };
 
-use crate::{messages::Message, readers::*};
-use tokio::io::{AsyncRead, BufReader};
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    codec::Codec,
+    messages::{
+        copy::{CopyData, CopyDone, CopyInResponse, CopyOutResponse},
+        expect_encoded,
+        extended::{
+            BindComplete, CloseComplete, ParameterDescription, ParseComplete, PortalSuspended,
+        },
+        message_count, message_length, Message,
+    },
+    readers::*,
+    state::{BackendKeyData, ParameterStatus},
+    types::Oid,
+    ProtocolError,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+#[cfg(test)]
+use std::io::Cursor;
 
 mod data_row;
 mod empty_query_response;
+mod error_response;
 mod no_data;
 mod notice_message;
+mod notification_response;
 mod ready_for_query;
 mod row_description;
 pub use data_row::DataRow;
 pub use empty_query_response::EmptyQueryResponse;
+pub use error_response::ErrorResponse;
 pub use no_data::NoData;
-pub use notice_message::NoticeMessage;
+pub use notice_message::{NoticeMessage, Severity};
+pub use notification_response::NotificationResponse;
 pub use ready_for_query::ReadyForQuery;
-pub use row_description::RowDescription;
+pub use row_description::{ColumnMeta, RowDescription};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BackendMessage {
@@ -29,7 +51,20 @@ pub enum BackendMessage {
     CommandComplete(CommandComplete),
     EmptyQueryResponse(EmptyQueryResponse),
     NoticeMessage(NoticeMessage),
-    Error { length: u32 },
+    ParameterStatus(ParameterStatus),
+    ErrorResponse(ErrorResponse),
+    CopyInResponse(CopyInResponse),
+    CopyOutResponse(CopyOutResponse),
+    CopyData(CopyData),
+    CopyDone(CopyDone),
+    ParseComplete(ParseComplete),
+    BindComplete(BindComplete),
+    CloseComplete(CloseComplete),
+    PortalSuspended(PortalSuspended),
+    ParameterDescription(ParameterDescription),
+    NotificationResponse(NotificationResponse),
+    /// A new cancellation key, from a pooler that hands out one per query.
+    BackendKeyData(BackendKeyData),
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CommandComplete {
@@ -41,10 +76,64 @@ impl CommandComplete {
         CommandCompleteBuilder { tag: None }
     }
 
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let tag = read_string(stream)?;
         Ok(Self { tag })
     }
+
+    pub fn kind(&self) -> CommandKind {
+        let mut words = self.tag.split(' ');
+        let command = words.next().unwrap_or_default();
+        let rows = words.next_back().and_then(|rows| rows.parse().ok());
+
+        match (command, rows) {
+            ("SELECT", Some(rows)) => CommandKind::Select(rows),
+            // The OID is only non-zero for a single row inserted into a
+            // table created WITH OIDS, which servers before 12 allowed.
+            ("INSERT", Some(rows)) => CommandKind::Insert {
+                oid: words
+                    .next()
+                    .and_then(|oid| oid.parse().ok())
+                    .map(Oid)
+                    .unwrap_or_default(),
+                rows,
+            },
+            ("UPDATE", Some(rows)) => CommandKind::Update(rows),
+            ("DELETE", Some(rows)) => CommandKind::Delete(rows),
+            ("MERGE", Some(rows)) => CommandKind::Merge(rows),
+            ("MOVE", Some(rows)) => CommandKind::Move(rows),
+            ("FETCH", Some(rows)) => CommandKind::Fetch(rows),
+            ("COPY", Some(rows)) => CommandKind::Copy(rows),
+            _ => CommandKind::Other(self.tag.clone()),
+        }
+    }
+
+    pub fn rows_affected(&self) -> Option<u64> {
+        match self.kind() {
+            CommandKind::Select(rows)
+            | CommandKind::Insert { rows, .. }
+            | CommandKind::Update(rows)
+            | CommandKind::Delete(rows)
+            | CommandKind::Merge(rows)
+            | CommandKind::Move(rows)
+            | CommandKind::Fetch(rows)
+            | CommandKind::Copy(rows) => Some(rows),
+            CommandKind::Other(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandKind {
+    Select(u64),
+    Insert { oid: Oid, rows: u64 },
+    Update(u64),
+    Delete(u64),
+    Merge(u64),
+    Move(u64),
+    Fetch(u64),
+    Copy(u64),
+    Other(String),
 }
 
 pub struct CommandCompleteBuilder {
@@ -68,57 +157,73 @@ impl BackendMessage {
     pub async fn read_next_message_async<R: AsyncRead + Unpin>(
         stream: &mut BufReader<R>,
     ) -> Result<Self, Box<dyn Error>> {
-        let r#type = read_u8_async(stream).await?;
-
-        let length = read_u32_async(stream).await? as usize;
-        let buffer = read_bytes_async(length - 4, stream).await?;
-        let mut buffer = Cursor::new(buffer);
-
-        let message = match r#type {
-            b'T' => BackendMessage::RowDescription(RowDescription::read_next_message(&mut buffer)?),
-            b'D' => BackendMessage::DataRow(DataRow::read_next_message(&mut buffer)?),
-            b'C' => BackendMessage::CommandComplete(CommandComplete::read_next_message(&mut buffer)?),
-            b'Z' => BackendMessage::ReadyForQuery(ReadyForQuery::read_next_message(&mut buffer)?),
-            b'I' => BackendMessage::EmptyQueryResponse(EmptyQueryResponse::read_next_message(&mut buffer)?),
-            _ => {
-                eprintln!("unhandled message type: {}", str::from_utf8(&[r#type])?);
-                eprintln!("backend message length: {}", length);
-                return Err("not implemented".into());
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        loop {
+            if let Some(message) = codec.decode(&mut buf)? {
+                return Ok(message);
             }
-        };
-
-        Ok(message)
+            let missing = codec.missing(&buf)?;
+            let read = (&mut *stream)
+                .take(missing as u64)
+                .read_buf(&mut buf)
+                .await?;
+            if read == 0 {
+                return Err(end_of_message(&buf).into());
+            }
+        }
     }
 
     pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let mut header: Vec<u8> = vec![0; 5];
-        let bytes_read = stream.read(&mut header)?;
-        if bytes_read != 5 {
-            return Err("expected 5 bytes for message type".into());
+        // The codec checks the length against its limit as soon as the
+        // header is in, and only then is room made for the body, so that a
+        // bogus length cannot make us allocate up to 4GB before finding out.
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        loop {
+            if let Some(message) = codec.decode(&mut buf)? {
+                return Ok(message);
+            }
+            // Read no further than this message; the rest is the next one's.
+            let missing = codec.missing(&buf)?;
+            let read = io::copy(&mut stream.take(missing as u64), &mut (&mut buf).writer())?;
+            if read < missing as u64 {
+                return Err(end_of_message(&buf).into());
+            }
         }
+    }
 
-        let r#type: u8 = header[0];
-        let length: u32 = u32::from_be_bytes(header[1..5].try_into()?);
-        let mut buffer = Cursor::new(read_bytes(length as usize - 4, stream)?);
-
+    // Decodes a message body, once its tag and length have been read.
+    pub(crate) fn decode(r#type: u8, buffer: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let message: BackendMessage = match r#type {
-            b'Z' => BackendMessage::ReadyForQuery(ReadyForQuery::read_next_message(&mut buffer)?),
-            b'T' => BackendMessage::RowDescription(RowDescription::read_next_message(&mut buffer)?),
-            b'D' => BackendMessage::DataRow(DataRow::read_next_message(&mut buffer)?),
-            b'n' => BackendMessage::NoData(NoData::read_next_message(&mut buffer)?),
-            b'C' => {
-                BackendMessage::CommandComplete(CommandComplete::read_next_message(&mut buffer)?)
-            }
-            b'I' => BackendMessage::EmptyQueryResponse(EmptyQueryResponse::read_next_message(&mut buffer)?),
-            b'N' => BackendMessage::NoticeMessage(NoticeMessage::read_next_message(&mut buffer)?),
-            b'E' => {
-                let _ = read_bytes(length as usize - 4, stream)?;
-                BackendMessage::Error { length }
+            b'Z' => BackendMessage::ReadyForQuery(ReadyForQuery::read_next_message(buffer)?),
+            b'T' => BackendMessage::RowDescription(RowDescription::read_next_message(buffer)?),
+            b'D' => BackendMessage::DataRow(DataRow::read_next_message(buffer)?),
+            b'n' => BackendMessage::NoData(NoData::read_next_message(buffer)?),
+            b'C' => BackendMessage::CommandComplete(CommandComplete::read_next_message(buffer)?),
+            b'I' => {
+                BackendMessage::EmptyQueryResponse(EmptyQueryResponse::read_next_message(buffer)?)
             }
+            b'N' => BackendMessage::NoticeMessage(NoticeMessage::read_next_message(buffer)?),
+            b'S' => BackendMessage::ParameterStatus(ParameterStatus::read_next_message(buffer)?),
+            b'E' => BackendMessage::ErrorResponse(ErrorResponse::read_next_message(buffer)?),
+            b'G' => BackendMessage::CopyInResponse(CopyInResponse::read_next_message(buffer)?),
+            b'H' => BackendMessage::CopyOutResponse(CopyOutResponse::read_next_message(buffer)?),
+            b'd' => BackendMessage::CopyData(CopyData::read_next_message(buffer)?),
+            b'c' => BackendMessage::CopyDone(CopyDone),
+            b'1' => BackendMessage::ParseComplete(ParseComplete),
+            b'2' => BackendMessage::BindComplete(BindComplete),
+            b'3' => BackendMessage::CloseComplete(CloseComplete),
+            b's' => BackendMessage::PortalSuspended(PortalSuspended),
+            b't' => BackendMessage::ParameterDescription(ParameterDescription::read_next_message(
+                buffer,
+            )?),
+            b'A' => BackendMessage::NotificationResponse(NotificationResponse::read_next_message(
+                buffer,
+            )?),
+            b'K' => BackendMessage::BackendKeyData(BackendKeyData::read_next_message(buffer)?),
             _ => {
-                return Err(
-                    format!("unhandled message type: {:?}", str::from_utf8(&[r#type])?).into(),
-                );
+                return Err(format!("unhandled message type: {:?}", char::from(r#type)).into());
             }
         };
 
@@ -127,13 +232,18 @@ impl BackendMessage {
 }
 
 impl Message for DataRow {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let count = message_count(self.len())?;
         let mut field_buffer = Vec::new();
-        for field in &self.fields {
+        for field in self.fields() {
             match field {
                 Some(value) => {
                     field_buffer.extend_from_slice(&(value.len() as u32).to_be_bytes());
-                    field_buffer.extend_from_slice(&value.as_bytes());
+                    field_buffer.extend_from_slice(value);
                 }
                 None => {
                     // NULL or no value
@@ -142,13 +252,38 @@ impl Message for DataRow {
             }
         }
 
-        let mut buffer = Vec::new();
+        let length = message_length(field_buffer.len() + 2)?;
         buffer.push(b'D');
-        buffer.extend_from_slice(&(field_buffer.len() as u32 + 4 + 2).to_be_bytes());
-        buffer.extend_from_slice(&(self.fields.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&length.to_be_bytes());
+        buffer.extend_from_slice(&count.to_be_bytes());
         buffer.extend_from_slice(&field_buffer);
+        Ok(())
+    }
+}
 
-        buffer
+// Why a stream ended part way through a message.
+fn end_of_message(buf: &BytesMut) -> &'static str {
+    if buf.len() < 5 {
+        "expected 5 bytes for message type"
+    } else {
+        "unexpected end of message"
+    }
+}
+
+#[test]
+fn test_malformed_messages() {
+    // Inputs that used to panic or exhaust memory; fuzz/corpus has them too.
+    for input in [
+        // A length shorter than the length field itself.
+        &b"Z\x00\x00\x00\x03I"[..],
+        // A ReadyForQuery with an unknown transaction status.
+        &b"Z\x00\x00\x00\x05X"[..],
+        // A 4GB length with one byte of body behind it.
+        &b"D\xff\xff\xff\xff\x00"[..],
+        // An unknown, non-ASCII tag.
+        &b"\xff\x00\x00\x00\x04"[..],
+    ] {
+        assert!(BackendMessage::read_next_message(&mut Cursor::new(input)).is_err());
     }
 }
 
@@ -175,8 +310,7 @@ fn test_empty_data_row() -> Result<(), Box<dyn Error>> {
 }
 
 impl Message for CommandComplete {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'C');
 
         let mut tag_bytes: Vec<u8> = self.tag.as_bytes().to_vec();
@@ -185,7 +319,6 @@ impl Message for CommandComplete {
 
         buffer.extend_from_slice(&length.to_be_bytes());
         buffer.extend_from_slice(&tag_bytes);
-        buffer
     }
 }
 
@@ -235,21 +368,148 @@ fn test_select1_command_complete() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_command_kind() {
+    let kind = |tag: &str| CommandComplete::builder().tag(tag).build().kind();
+
+    assert_eq!(kind("SELECT 1"), CommandKind::Select(1));
+    assert_eq!(
+        kind("INSERT 0 1"),
+        CommandKind::Insert {
+            oid: Oid(0),
+            rows: 1
+        }
+    );
+    assert_eq!(
+        kind("INSERT 12345 1"),
+        CommandKind::Insert {
+            oid: Oid(12345),
+            rows: 1
+        }
+    );
+    assert_eq!(kind("UPDATE 42"), CommandKind::Update(42));
+    assert_eq!(kind("BEGIN"), CommandKind::Other("BEGIN".to_string()));
+    assert_eq!(kind("GREETING"), CommandKind::Other("GREETING".to_string()));
+}
+
+// Gives each variant a name, and lets it be taken out of a BackendMessage
+// with TryFrom, which hands back any other message unchanged.
+macro_rules! variants {
+    ($($variant:ident),* $(,)?) => {
+        impl BackendMessage {
+            /// The name of the message type, such as `"DataRow"`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(BackendMessage::$variant(_) => stringify!($variant),)*
+                }
+            }
+        }
+
+        $(
+            impl TryFrom<BackendMessage> for $variant {
+                type Error = BackendMessage;
+
+                fn try_from(message: BackendMessage) -> Result<Self, Self::Error> {
+                    match message {
+                        BackendMessage::$variant(inner) => Ok(inner),
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+variants!(
+    ReadyForQuery,
+    RowDescription,
+    DataRow,
+    NoData,
+    CommandComplete,
+    EmptyQueryResponse,
+    NoticeMessage,
+    ParameterStatus,
+    ErrorResponse,
+    CopyInResponse,
+    CopyOutResponse,
+    CopyData,
+    CopyDone,
+    ParseComplete,
+    BindComplete,
+    CloseComplete,
+    PortalSuspended,
+    ParameterDescription,
+    NotificationResponse,
+    BackendKeyData,
+);
+
 impl Message for BackendMessage {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            BackendMessage::ReadyForQuery(ready_for_query) => ready_for_query.encode_into(buffer),
+            BackendMessage::RowDescription(row_description) => row_description.encode_into(buffer),
+            BackendMessage::DataRow(data_row) => data_row.encode_into(buffer),
+            BackendMessage::NoData(no_data) => no_data.encode_into(buffer),
+            BackendMessage::CommandComplete(command_complete) => {
+                command_complete.encode_into(buffer)
+            }
+            BackendMessage::EmptyQueryResponse(empty_query_response) => {
+                empty_query_response.encode_into(buffer)
+            }
+            BackendMessage::NoticeMessage(notice_message) => notice_message.encode_into(buffer),
+            BackendMessage::ParameterStatus(parameter_status) => {
+                parameter_status.encode_into(buffer)
+            }
+            BackendMessage::ErrorResponse(error_response) => error_response.encode_into(buffer),
+            BackendMessage::CopyInResponse(copy_in_response) => {
+                copy_in_response.encode_into(buffer)
+            }
+            BackendMessage::CopyOutResponse(copy_out_response) => {
+                copy_out_response.encode_into(buffer)
+            }
+            BackendMessage::CopyData(copy_data) => copy_data.encode_into(buffer),
+            BackendMessage::CopyDone(copy_done) => copy_done.encode_into(buffer),
+            BackendMessage::ParseComplete(parse_complete) => parse_complete.encode_into(buffer),
+            BackendMessage::BindComplete(bind_complete) => bind_complete.encode_into(buffer),
+            BackendMessage::CloseComplete(close_complete) => close_complete.encode_into(buffer),
+            BackendMessage::PortalSuspended(portal_suspended) => {
+                portal_suspended.encode_into(buffer)
+            }
+            BackendMessage::ParameterDescription(parameter_description) => {
+                parameter_description.encode_into(buffer)
+            }
+            BackendMessage::NotificationResponse(notification_response) => {
+                notification_response.encode_into(buffer)
+            }
+            BackendMessage::BackendKeyData(key_data) => key_data.encode_into(buffer),
+        }
+    }
+
+    fn requires_flush(&self) -> bool {
+        match self {
+            BackendMessage::CopyData(copy_data) => copy_data.requires_flush(),
+            _ => true,
+        }
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
         match self {
-            BackendMessage::ReadyForQuery(ready_for_query) => ready_for_query.encode(),
-            BackendMessage::RowDescription(row_description) => row_description.encode(),
-            BackendMessage::DataRow(data_row) => data_row.encode(),
-            BackendMessage::NoData(no_data) => no_data.encode(),
-            BackendMessage::CommandComplete(command_complete) => command_complete.encode(),
-            BackendMessage::EmptyQueryResponse(empty_query_response) => empty_query_response.encode(),
-            BackendMessage::NoticeMessage(notice_message) => notice_message.encode(),
-            BackendMessage::Error { length } => {
-                let mut buffer = Vec::new();
-                buffer.push(b'E');
-                buffer.extend_from_slice(&length.to_be_bytes());
-                buffer
+            BackendMessage::RowDescription(row_description) => {
+                row_description.try_encode_into(buffer)
+            }
+            BackendMessage::DataRow(data_row) => data_row.try_encode_into(buffer),
+            BackendMessage::ParameterDescription(parameter_description) => {
+                parameter_description.try_encode_into(buffer)
+            }
+            BackendMessage::CopyInResponse(copy_in_response) => {
+                copy_in_response.try_encode_into(buffer)
+            }
+            BackendMessage::CopyOutResponse(copy_out_response) => {
+                copy_out_response.try_encode_into(buffer)
+            }
+            message => {
+                message.encode_into(buffer);
+                Ok(())
             }
         }
     }
@@ -1,12 +1,20 @@
 use crate::{messages::Message, readers::*};
 use core::fmt;
-use std::{error::Error, io::Read};
+use std::error::Error;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NoticeMessage {
     pub severity: Severity,
     pub code: String,
     pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    pub position: Option<String>,
+    pub internal_position: Option<String>,
+    pub internal_query: Option<String>,
+    /// Fields of types this crate doesn't know, such as ones added by newer
+    /// servers, kept so that they survive being encoded again.
+    pub extra: Vec<(u8, String)>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -17,11 +25,14 @@ pub enum Severity {
     Debug,
     Info,
     Log,
+    Error,
+    Fatal,
+    Panic,
     Localized(String),
 }
 
 impl NoticeMessage {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let mut builder = NoticeMessage::builder();
         loop {
             match read_u8(stream)? {
@@ -36,6 +47,9 @@ impl NoticeMessage {
                         "DEBUG" => Severity::Debug,
                         "INFO" => Severity::Info,
                         "LOG" => Severity::Log,
+                        "ERROR" => Severity::Error,
+                        "FATAL" => Severity::Fatal,
+                        "PANIC" => Severity::Panic,
                         other => Severity::Localized(other.to_string()),
                     };
                     builder = builder.severity(severity);
@@ -48,6 +62,26 @@ impl NoticeMessage {
                     let message = read_string(stream)?;
                     builder = builder.message(message);
                 }
+                b'D' => {
+                    let detail = read_string(stream)?;
+                    builder = builder.detail(detail);
+                }
+                b'H' => {
+                    let hint = read_string(stream)?;
+                    builder = builder.hint(hint);
+                }
+                b'P' => {
+                    let position = read_string(stream)?;
+                    builder = builder.position(position);
+                }
+                b'p' => {
+                    let internal_position = read_string(stream)?;
+                    builder = builder.internal_position(internal_position);
+                }
+                b'q' => {
+                    let internal_query = read_string(stream)?;
+                    builder = builder.internal_query(internal_query);
+                }
                 b'F' => {
                     let _file_name = read_string(stream)?;
                 }
@@ -60,26 +94,39 @@ impl NoticeMessage {
                 0 => break,
 
                 field_type => {
-                    let field_type = String::from_utf8(vec![field_type])?;
-                    let field_value = read_string(stream)?;
-                    eprintln!("Unknown field type: {field_type}");
-                    eprintln!("  : {field_value}");
-
-                    continue;
+                    let value = read_string(stream)?;
+                    builder = builder.extra(field_type, value);
                 }
             }
         }
 
-        Ok(builder.build()?)
+        builder.build()
     }
 
     pub fn builder() -> NoticeMessageBuilder {
         NoticeMessageBuilder::new()
     }
+
+    /// The 1-based character offset into the query, if the server sent a
+    /// valid one. The raw value is in the `position` field.
+    pub fn position(&self) -> Option<u32> {
+        parse_position(&self.position)
+    }
+
+    /// The 1-based character offset into the internally generated query,
+    /// such as one run by a PL/pgSQL function, if the server sent a valid
+    /// one. The query itself is in `internal_query`.
+    pub fn internal_position(&self) -> Option<u32> {
+        parse_position(&self.internal_position)
+    }
+}
+
+pub(crate) fn parse_position(position: &Option<String>) -> Option<u32> {
+    position.as_deref()?.parse().ok()
 }
 
 impl Severity {
-    fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub(crate) fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let value = read_string(stream)?;
         Ok(match value.as_str() {
             "WARNING" => Severity::Warning,
@@ -87,6 +134,9 @@ impl Severity {
             "DEBUG" => Severity::Debug,
             "INFO" => Severity::Info,
             "LOG" => Severity::Log,
+            "ERROR" => Severity::Error,
+            "FATAL" => Severity::Fatal,
+            "PANIC" => Severity::Panic,
             other => Severity::Localized(other.to_string()),
         })
     }
@@ -100,17 +150,20 @@ impl fmt::Display for Severity {
             Severity::Debug => write!(f, "DEBUG"),
             Severity::Info => write!(f, "INFO"),
             Severity::Log => write!(f, "LOG"),
+            Severity::Error => write!(f, "ERROR"),
+            Severity::Fatal => write!(f, "FATAL"),
+            Severity::Panic => write!(f, "PANIC"),
             Severity::Localized(value) => write!(f, "{}", value),
         }
     }
 }
 
 impl Message for NoticeMessage {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         let mut inner = Vec::new();
 
         // Severity
-        inner.extend_from_slice(&self.severity.encode());
+        self.severity.encode_into(&mut inner);
 
         // Code
         inner.push(b'C');
@@ -122,7 +175,18 @@ impl Message for NoticeMessage {
         inner.extend_from_slice(self.message.as_bytes());
         inner.push(0);
 
-        let mut buffer = Vec::new();
+        encode_optional_fields(
+            &mut inner,
+            [
+                (b'D', &self.detail),
+                (b'H', &self.hint),
+                (b'P', &self.position),
+                (b'p', &self.internal_position),
+                (b'q', &self.internal_query),
+            ],
+        );
+        encode_extra_fields(&mut inner, &self.extra);
+
         buffer.push(b'N');
 
         buffer.extend_from_slice(&(inner.len() as u32 + 4 + 1).to_be_bytes());
@@ -130,13 +194,11 @@ impl Message for NoticeMessage {
 
         // terminator
         buffer.push(0);
-        buffer
     }
 }
 
 impl Message for Severity {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'S');
 
         match self {
@@ -145,10 +207,33 @@ impl Message for Severity {
             Severity::Debug => buffer.extend_from_slice(b"DEBUG"),
             Severity::Info => buffer.extend_from_slice(b"INFO"),
             Severity::Log => buffer.extend_from_slice(b"LOG"),
+            Severity::Error => buffer.extend_from_slice(b"ERROR"),
+            Severity::Fatal => buffer.extend_from_slice(b"FATAL"),
+            Severity::Panic => buffer.extend_from_slice(b"PANIC"),
             Severity::Localized(value) => buffer.extend_from_slice(value.as_bytes()),
         }
         buffer.push(0);
-        buffer
+    }
+}
+
+pub(crate) fn encode_optional_fields<const N: usize>(
+    buffer: &mut Vec<u8>,
+    fields: [(u8, &Option<String>); N],
+) {
+    for (field_type, value) in fields {
+        if let Some(value) = value {
+            buffer.push(field_type);
+            buffer.extend_from_slice(value.as_bytes());
+            buffer.push(0);
+        }
+    }
+}
+
+pub(crate) fn encode_extra_fields(buffer: &mut Vec<u8>, extra: &[(u8, String)]) {
+    for (field_type, value) in extra {
+        buffer.push(*field_type);
+        buffer.extend_from_slice(value.as_bytes());
+        buffer.push(0);
     }
 }
 
@@ -156,6 +241,12 @@ pub struct NoticeMessageBuilder {
     severity: Option<Severity>,
     code: Option<String>,
     message: Option<String>,
+    detail: Option<String>,
+    hint: Option<String>,
+    position: Option<String>,
+    internal_position: Option<String>,
+    internal_query: Option<String>,
+    extra: Vec<(u8, String)>,
 }
 
 impl NoticeMessageBuilder {
@@ -164,6 +255,12 @@ impl NoticeMessageBuilder {
             severity: None,
             code: None,
             message: None,
+            detail: None,
+            hint: None,
+            position: None,
+            internal_position: None,
+            internal_query: None,
+            extra: Vec::new(),
         }
     }
 
@@ -182,6 +279,37 @@ impl NoticeMessageBuilder {
         self
     }
 
+    pub fn detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+
+    pub fn hint(mut self, hint: String) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn position(mut self, position: String) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    pub fn internal_position(mut self, internal_position: String) -> Self {
+        self.internal_position = Some(internal_position);
+        self
+    }
+
+    pub fn internal_query(mut self, internal_query: String) -> Self {
+        self.internal_query = Some(internal_query);
+        self
+    }
+
+    /// Adds a field of a type with no setter of its own.
+    pub fn extra(mut self, field_type: u8, value: String) -> Self {
+        self.extra.push((field_type, value));
+        self
+    }
+
     pub fn build(self) -> Result<NoticeMessage, Box<dyn Error>> {
         let severity = self.severity.unwrap_or_default(); //.ok_or("Severity is required")?;
         let code = self.code.unwrap_or_default(); //ok_or("Code is required")?;
@@ -191,6 +319,12 @@ impl NoticeMessageBuilder {
             severity,
             code,
             message,
+            detail: self.detail,
+            hint: self.hint,
+            position: self.position,
+            internal_position: self.internal_position,
+            internal_query: self.internal_query,
+            extra: self.extra,
         })
     }
 }
@@ -275,4 +409,34 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_notice_keeps_unknown_fields() -> Result<(), Box<dyn Error>> {
+        let notice_message = NoticeMessage::builder()
+            .message("hello".to_string())
+            .extra(b'Z', "from a newer server".to_string())
+            .build()?;
+
+        let mut cursor = Cursor::new(notice_message.encode());
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::NoticeMessage(notice_message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_notice_position() -> Result<(), Box<dyn Error>> {
+        let notice_message = NoticeMessage::builder()
+            .position("15".to_string())
+            .internal_position("x".to_string())
+            .build()?;
+        assert_eq!(notice_message.position(), Some(15));
+        assert_eq!(notice_message.internal_position(), None);
+        assert_eq!(notice_message.internal_position.as_deref(), Some("x"));
+
+        let mut cursor = Cursor::new(notice_message.encode());
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::NoticeMessage(notice_message));
+
+        Ok(())
+    }
 }
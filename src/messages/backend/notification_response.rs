@@ -0,0 +1,38 @@
+use crate::{messages::Message, readers::*};
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationResponse {
+    pub process_id: u32,
+    pub channel: String,
+    pub payload: String,
+}
+
+impl NotificationResponse {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let process_id = read_u32(stream)?;
+        let channel = read_string(stream)?;
+        let payload = read_string(stream)?;
+
+        Ok(Self {
+            process_id,
+            channel,
+            payload,
+        })
+    }
+}
+
+impl Message for NotificationResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'A');
+
+        let length = 4 + 4 + self.channel.len() as u32 + 1 + self.payload.len() as u32 + 1;
+        buffer.extend_from_slice(&length.to_be_bytes());
+
+        buffer.extend_from_slice(&self.process_id.to_be_bytes());
+        buffer.extend_from_slice(self.channel.as_bytes());
+        buffer.push(0);
+        buffer.extend_from_slice(self.payload.as_bytes());
+        buffer.push(0);
+    }
+}
@@ -1,35 +1,18 @@
-use std::{error::Error, io::Read};
+use crate::messages::Message;
 
-use crate::{messages::Message, readers::*, state::TransactionStatus};
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ReadyForQuery {
-    pub transaction_status: TransactionStatus,
-}
-
-impl ReadyForQuery {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let transaction_status = TransactionStatus::from_u8(read_u8(stream)?);
-
-        Ok(Self { transaction_status })
-    }
-}
+pub use rpsql_wire::ReadyForQuery;
 
 impl Message for ReadyForQuery {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        buffer.push(b'Z');
-        buffer.extend_from_slice(&5u32.to_be_bytes());
-        buffer.extend_from_slice(&[self.transaction_status.to_u8()]);
-        buffer
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        ReadyForQuery::encode_into(self, buffer)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::messages::backend::BackendMessage;
-    use std::io::Cursor;
+    use crate::{messages::backend::BackendMessage, state::TransactionStatus};
+    use std::{error::Error, io::Cursor};
 
     #[test]
     fn test_ready_for_query() -> Result<(), Box<dyn Error>> {
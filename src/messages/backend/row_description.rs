@@ -1,10 +1,15 @@
-use std::{error::Error, io::Read};
+use std::{error::Error, slice};
 
-use crate::{messages::Message, readers::*};
+use crate::{
+    messages::{expect_encoded, message_count, message_length, Message},
+    readers::*,
+    types::{Format, Oid},
+    ProtocolError,
+};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct RowDescription {
-    fields: Vec<Field>,
+    fields: Vec<ColumnMeta>,
 }
 
 impl RowDescription {
@@ -14,33 +19,43 @@ impl RowDescription {
 }
 
 pub struct RowDescriptionBuilder {
-    fields: Vec<Field>,
+    fields: Vec<ColumnMeta>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Field {
-    name: String,
-    table_oid: u32,
-    column_index: u16,
-    data_type_oid: u32,
-    data_type_size: u16,
-    type_modifier: u32,
-    format_code: u16,
+/// One column of a RowDescription.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ColumnMeta {
+    pub name: String,
+    pub table_oid: Oid,
+    pub column_index: u16,
+    pub data_type_oid: Oid,
+    pub data_type_size: u16,
+    pub type_modifier: u32,
+    pub format_code: u16,
 }
 
-impl RowDescriptionBuilder {
-    pub fn string_field(mut self, name: impl Into<String>) -> Self {
-        let field = Field {
+impl ColumnMeta {
+    pub fn new(name: impl Into<String>, data_type_oid: impl Into<Oid>, format: Format) -> Self {
+        Self {
             name: name.into(),
-            table_oid: 0,
-            column_index: 0,
-            data_type_oid: 0,
-            data_type_size: 0,
-            type_modifier: 0,
-            format_code: 0,
-        };
+            data_type_oid: data_type_oid.into(),
+            format_code: format.code(),
+            ..Self::default()
+        }
+    }
+}
+
+impl RowDescriptionBuilder {
+    pub fn string_field(self, name: impl Into<String>) -> Self {
+        self.field(ColumnMeta::new(name, 0, Format::Text))
+    }
 
-        self.fields.push(field);
+    pub fn binary_field(self, name: impl Into<String>, data_type_oid: impl Into<Oid>) -> Self {
+        self.field(ColumnMeta::new(name, data_type_oid, Format::Binary))
+    }
+
+    pub fn field(mut self, column: ColumnMeta) -> Self {
+        self.fields.push(column);
         self
     }
 
@@ -52,15 +67,15 @@ impl RowDescriptionBuilder {
 }
 
 impl RowDescription {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let field_count = read_u16(stream)? as usize;
-        let mut fields: Vec<Field> = Vec::with_capacity(field_count);
+        let mut fields: Vec<ColumnMeta> = Vec::with_capacity(field_count);
         for _ in 0..field_count {
-            let field = Field {
+            let field = ColumnMeta {
                 name: read_string(stream)?,
-                table_oid: read_u32(stream)?,
+                table_oid: Oid(read_u32(stream)?),
                 column_index: read_u16(stream)?,
-                data_type_oid: read_u32(stream)?,
+                data_type_oid: Oid(read_u32(stream)?),
                 data_type_size: read_u16(stream)?,
                 type_modifier: read_u32(stream)?,
                 format_code: read_u16(stream)?,
@@ -75,24 +90,62 @@ impl RowDescription {
     pub fn field_names(&self) -> Vec<String> {
         self.fields.iter().map(|f| f.name.to_string()).collect()
     }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn column(&self, index: usize) -> Option<&ColumnMeta> {
+        self.fields.get(index)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, ColumnMeta> {
+        self.fields.iter()
+    }
+
+    pub(crate) fn data_type_oid(&self, index: usize) -> Option<Oid> {
+        self.fields.get(index).map(|f| f.data_type_oid)
+    }
+
+    pub(crate) fn format_code(&self, index: usize) -> Option<u16> {
+        self.fields.get(index).map(|f| f.format_code)
+    }
+}
+
+impl<'a> IntoIterator for &'a RowDescription {
+    type Item = &'a ColumnMeta;
+    type IntoIter = slice::Iter<'a, ColumnMeta>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
 impl Message for RowDescription {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let count = message_count(self.fields.len())?;
         let mut field_buffer = Vec::new();
         for field in &self.fields {
             // Field Name
-            field_buffer.extend_from_slice(&field.name.as_bytes());
+            field_buffer.extend_from_slice(field.name.as_bytes());
             field_buffer.push(0);
 
             // Table OID (u32) or zero
-            field_buffer.extend_from_slice(&field.table_oid.to_be_bytes());
+            field_buffer.extend_from_slice(&field.table_oid.0.to_be_bytes());
 
             // Column Index (u16) or zero
             field_buffer.extend_from_slice(&field.column_index.to_be_bytes());
 
             // Data Type OID (u32)
-            field_buffer.extend_from_slice(&field.data_type_oid.to_be_bytes());
+            field_buffer.extend_from_slice(&field.data_type_oid.0.to_be_bytes());
 
             // Data Type Size (i16). Negative values denote variable length types.
             field_buffer.extend_from_slice(&field.data_type_size.to_be_bytes());
@@ -104,24 +157,22 @@ impl Message for RowDescription {
             field_buffer.extend_from_slice(&field.format_code.to_be_bytes());
         }
 
-        let mut buffer = Vec::new();
-        buffer.push(b'T');
-
         // Length of message contents in bytes, including self.
-        buffer.extend_from_slice(&(field_buffer.len() as u32 + 4 + 2).to_be_bytes());
+        let length = message_length(field_buffer.len() + 2)?;
+        buffer.push(b'T');
+        buffer.extend_from_slice(&length.to_be_bytes());
         // Number of fields in the row.
-        buffer.extend_from_slice(&(self.fields.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&count.to_be_bytes());
         // The fields serialized
         buffer.extend_from_slice(&field_buffer);
-
-        buffer
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::messages::backend::BackendMessage;
+    use crate::{messages::backend::BackendMessage, types::TypeOid};
     use std::io::Cursor;
 
     #[test]
@@ -147,6 +198,21 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_iterate_columns() {
+        let row_description = RowDescription::builder()
+            .string_field("id")
+            .binary_field("n", 20)
+            .build();
+
+        let mut names = Vec::new();
+        for column in &row_description {
+            names.push((column.name.as_str(), column.data_type_oid));
+        }
+        assert_eq!(names, [("id", Oid(0)), ("n", Oid(20))]);
+        assert_eq!(row_description.iter().len(), row_description.len());
+    }
+
     #[test]
     fn test_single_row_description() -> Result<(), Box<dyn Error>> {
         let row_description = RowDescription::builder().string_field("id").build();
@@ -218,4 +284,60 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_mixed_format_row_description() -> Result<(), Box<dyn Error>> {
+        let row_description = RowDescription::builder()
+            .string_field("name")
+            .binary_field("id", 23)
+            .field(ColumnMeta {
+                table_oid: Oid(16384),
+                column_index: 3,
+                data_type_size: 8,
+                ..ColumnMeta::new("created", 1184, Format::Binary)
+            })
+            .build();
+
+        assert_eq!(row_description.format_code(0), Some(0));
+        assert_eq!(row_description.format_code(1), Some(1));
+        assert_eq!(row_description.data_type_oid(1), Some(Oid(23)));
+        assert_eq!(
+            row_description.column(2).map(|c| c.table_oid),
+            Some(Oid(16384))
+        );
+
+        let mut cursor = Cursor::new(row_description.encode());
+        let decoded = BackendMessage::read_next_message(&mut cursor)?;
+        assert_eq!(decoded, BackendMessage::RowDescription(row_description));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oid_roundtrip() -> Result<(), Box<dyn Error>> {
+        let row_description = RowDescription::builder()
+            .field(ColumnMeta {
+                table_oid: Oid(0x0102_0304),
+                ..ColumnMeta::new("id", TypeOid::Int8, Format::Binary)
+            })
+            .build();
+
+        let encoded = row_description.encode();
+        // Table OID follows the tag, length, field count and name.
+        assert_eq!(&encoded[10..14], &[0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(&encoded[16..20], &TypeOid::Int8.as_u32().to_be_bytes());
+
+        let mut cursor = Cursor::new(encoded);
+        let BackendMessage::RowDescription(decoded) =
+            BackendMessage::read_next_message(&mut cursor)?
+        else {
+            panic!("expected a RowDescription");
+        };
+        let column = decoded.column(0).unwrap();
+        assert_eq!(column.table_oid, Oid(0x0102_0304));
+        assert_eq!(column.data_type_oid, Oid::from(TypeOid::Int8));
+        assert_eq!(decoded, row_description);
+
+        Ok(())
+    }
 }
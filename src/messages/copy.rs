@@ -0,0 +1,211 @@
+use std::error::Error;
+
+use crate::{
+    messages::{expect_encoded, message_count, message_length, Message},
+    readers::*,
+    ProtocolError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyInResponse {
+    pub format: u8,
+    pub column_formats: Vec<u16>,
+}
+
+impl CopyInResponse {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let (format, column_formats) = read_copy_response(stream)?;
+        Ok(Self {
+            format,
+            column_formats,
+        })
+    }
+}
+
+impl Message for CopyInResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        encode_copy_response(buffer, b'G', self.format, &self.column_formats)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyOutResponse {
+    pub format: u8,
+    pub column_formats: Vec<u16>,
+}
+
+impl CopyOutResponse {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let (format, column_formats) = read_copy_response(stream)?;
+        Ok(Self {
+            format,
+            column_formats,
+        })
+    }
+}
+
+impl Message for CopyOutResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        encode_copy_response(buffer, b'H', self.format, &self.column_formats)
+    }
+}
+
+fn read_copy_response(stream: &mut impl ByteReader) -> Result<(u8, Vec<u16>), Box<dyn Error>> {
+    let format = read_u8(stream)?;
+    let column_count = read_u16(stream)?;
+
+    let mut column_formats = Vec::with_capacity(column_count as usize);
+    for _ in 0..column_count {
+        column_formats.push(read_u16(stream)?);
+    }
+
+    Ok((format, column_formats))
+}
+
+fn encode_copy_response(
+    buffer: &mut Vec<u8>,
+    tag: u8,
+    format: u8,
+    column_formats: &[u16],
+) -> Result<(), ProtocolError> {
+    let count = message_count(column_formats.len())?;
+    // 1 byte for the overall format
+    // 2 bytes for the column count
+    let length = message_length(1 + 2 + 2 * column_formats.len())?;
+
+    buffer.push(tag);
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.push(format);
+    buffer.extend_from_slice(&count.to_be_bytes());
+    for column_format in column_formats {
+        buffer.extend_from_slice(&column_format.to_be_bytes());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyData {
+    pub data: Vec<u8>,
+}
+
+impl CopyData {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Self { data: data.into() }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            data: stream.read_remaining()?,
+        })
+    }
+}
+
+impl Message for CopyData {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'd');
+        buffer.extend_from_slice(&(self.data.len() as u32 + 4).to_be_bytes());
+        buffer.extend_from_slice(&self.data);
+    }
+
+    fn requires_flush(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyDone;
+
+impl Message for CopyDone {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'c');
+        buffer.extend_from_slice(&4u32.to_be_bytes());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyFail {
+    pub message: String,
+}
+
+impl CopyFail {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(read_string(stream)?))
+    }
+}
+
+impl Message for CopyFail {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'f');
+        // 4 bytes for length
+        // 1 byte for null terminator
+        buffer.extend_from_slice(&(self.message.len() as u32 + 4 + 1).to_be_bytes());
+        buffer.extend_from_slice(self.message.as_bytes());
+        buffer.push(0);
+    }
+}
+
+#[test]
+fn test_copy_out_response() -> Result<(), Box<dyn Error>> {
+    let copy_out_response = CopyOutResponse {
+        format: 0,
+        column_formats: vec![0, 0],
+    };
+    let encoded = copy_out_response.encode();
+    assert_eq!(
+        encoded,
+        vec![
+            b'H', // message tag
+            0x00, 0x00, 0x00, 11,   // length
+            0x00, // text format
+            0x00, 0x02, // column count
+            0x00, 0x00, // column formats
+            0x00, 0x00,
+        ]
+    );
+
+    let mut cursor = std::io::Cursor::new(&encoded[5..]);
+    assert_eq!(
+        CopyOutResponse::read_next_message(&mut cursor)?,
+        copy_out_response
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_data() -> Result<(), Box<dyn Error>> {
+    let copy_data = CopyData::new("1,a\n");
+    let encoded = copy_data.encode();
+    assert_eq!(
+        encoded,
+        vec![b'd', 0x00, 0x00, 0x00, 8, b'1', b',', b'a', b'\n']
+    );
+
+    let mut cursor = std::io::Cursor::new(&encoded[5..]);
+    assert_eq!(CopyData::read_next_message(&mut cursor)?, copy_data);
+
+    Ok(())
+}
+
+#[test]
+fn test_requires_flush() {
+    use crate::messages::ssl::SSLRequest;
+
+    assert!(SSLRequest.requires_flush());
+    assert!(CopyDone.requires_flush());
+    assert!(!CopyData::new("1,a\n").requires_flush());
+}
@@ -0,0 +1,495 @@
+use std::error::Error;
+
+use crate::{
+    messages::{expect_encoded, message_count, message_length, Message},
+    readers::*,
+    types::{Format, Oid},
+    ProtocolError,
+};
+
+// Wraps a message body with its tag and length.
+fn frame(buffer: &mut Vec<u8>, tag: u8, body: &[u8]) -> Result<(), ProtocolError> {
+    let length = message_length(body.len())?;
+    buffer.push(tag);
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(body);
+    Ok(())
+}
+
+fn push_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(0);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parse {
+    pub name: String,
+    pub query: String,
+    pub parameter_types: Vec<Oid>,
+}
+
+impl Parse {
+    pub fn new(name: impl Into<String>, query: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            query: query.into(),
+            parameter_types: vec![],
+        }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let name = read_string(stream)?;
+        let query = read_string(stream)?;
+
+        let count = read_u16(stream)?;
+        let mut parameter_types = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            parameter_types.push(Oid(read_u32(stream)?));
+        }
+
+        Ok(Self {
+            name,
+            query,
+            parameter_types,
+        })
+    }
+}
+
+impl Message for Parse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let mut body = Vec::new();
+        push_string(&mut body, &self.name);
+        push_string(&mut body, &self.query);
+        body.extend_from_slice(&message_count(self.parameter_types.len())?.to_be_bytes());
+        for parameter_type in &self.parameter_types {
+            body.extend_from_slice(&parameter_type.0.to_be_bytes());
+        }
+        frame(buffer, b'P', &body)
+    }
+}
+
+/// How a Bind's parameters are formatted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum BindFormat {
+    /// Every parameter is text, sent as no format codes at all.
+    #[default]
+    Text,
+    /// Every parameter has this format, sent as a single code.
+    All(Format),
+    /// One format per parameter.
+    ///
+    /// On the wire, a single format applies to every parameter, so
+    /// `PerParameter(vec![format])` is sent the same as `All(format)` and
+    /// reads back as `All`. Compare formats with [`BindFormat::format`]
+    /// rather than by variant.
+    PerParameter(Vec<Format>),
+}
+
+impl BindFormat {
+    /// The format of the parameter at `index`.
+    pub fn format(&self, index: usize) -> Option<Format> {
+        match self {
+            BindFormat::Text => Some(Format::Text),
+            BindFormat::All(format) => Some(*format),
+            BindFormat::PerParameter(formats) => formats.get(index).copied(),
+        }
+    }
+
+    fn codes(&self) -> Vec<u16> {
+        match self {
+            BindFormat::Text => vec![],
+            BindFormat::All(format) => vec![format.code()],
+            BindFormat::PerParameter(formats) => formats.iter().map(Format::code).collect(),
+        }
+    }
+
+    fn read_next(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let count = read_u16(stream)?;
+        let mut formats = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            formats.push(Format::from_code(read_u16(stream)?)?);
+        }
+
+        Ok(match formats.len() {
+            0 => BindFormat::Text,
+            1 => BindFormat::All(formats[0]),
+            _ => BindFormat::PerParameter(formats),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Bind {
+    pub portal: String,
+    pub statement: String,
+    pub parameter_formats: BindFormat,
+    pub parameters: Vec<Option<Vec<u8>>>,
+    pub result_formats: Vec<u16>,
+}
+
+impl Bind {
+    pub fn new(portal: impl Into<String>, statement: impl Into<String>) -> Self {
+        Self {
+            portal: portal.into(),
+            statement: statement.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let portal = read_string(stream)?;
+        let statement = read_string(stream)?;
+
+        let parameter_formats = BindFormat::read_next(stream)?;
+
+        let count = read_u16(stream)?;
+        let mut parameters = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            match read_u32(stream)? {
+                0xFFFFFFFF => parameters.push(None),
+                length => parameters.push(Some(read_bytes(length as usize, stream)?)),
+            }
+        }
+
+        let count = read_u16(stream)?;
+        let mut result_formats = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            result_formats.push(read_u16(stream)?);
+        }
+
+        Ok(Self {
+            portal,
+            statement,
+            parameter_formats,
+            parameters,
+            result_formats,
+        })
+    }
+}
+
+impl Message for Bind {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let mut body = Vec::new();
+        push_string(&mut body, &self.portal);
+        push_string(&mut body, &self.statement);
+
+        let parameter_formats = self.parameter_formats.codes();
+        body.extend_from_slice(&message_count(parameter_formats.len())?.to_be_bytes());
+        for format in parameter_formats {
+            body.extend_from_slice(&format.to_be_bytes());
+        }
+
+        body.extend_from_slice(&message_count(self.parameters.len())?.to_be_bytes());
+        for parameter in &self.parameters {
+            match parameter {
+                Some(value) => {
+                    body.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                    body.extend_from_slice(value);
+                }
+                // NULL
+                None => body.extend_from_slice(&0xFFFFFFFFu32.to_be_bytes()),
+            }
+        }
+
+        body.extend_from_slice(&message_count(self.result_formats.len())?.to_be_bytes());
+        for format in &self.result_formats {
+            body.extend_from_slice(&format.to_be_bytes());
+        }
+
+        frame(buffer, b'B', &body)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execute {
+    pub portal: String,
+    /// Zero means no limit.
+    pub max_rows: u32,
+}
+
+impl Execute {
+    pub fn new(portal: impl Into<String>, max_rows: u32) -> Self {
+        Self {
+            portal: portal.into(),
+            max_rows,
+        }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let portal = read_string(stream)?;
+        let max_rows = read_u32(stream)?;
+        Ok(Self { portal, max_rows })
+    }
+}
+
+impl Message for Execute {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        let mut body = Vec::new();
+        push_string(&mut body, &self.portal);
+        body.extend_from_slice(&self.max_rows.to_be_bytes());
+        expect_encoded(frame(buffer, b'E', &body))
+    }
+}
+
+/// What a Describe or Close refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Statement(String),
+    Portal(String),
+}
+
+impl Target {
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Statement(name) | Target::Portal(name) => name,
+        }
+    }
+
+    fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        match read_u8(stream)? {
+            b'S' => Ok(Target::Statement(read_string(stream)?)),
+            b'P' => Ok(Target::Portal(read_string(stream)?)),
+            other => Err(format!("invalid target type: {other:#04x}").into()),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            Target::Statement(name) => {
+                body.push(b'S');
+                push_string(&mut body, name);
+            }
+            Target::Portal(name) => {
+                body.push(b'P');
+                push_string(&mut body, name);
+            }
+        }
+        body
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Describe {
+    pub target: Target,
+}
+
+impl Describe {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let target = Target::read_next_message(stream)?;
+        Ok(Self { target })
+    }
+}
+
+impl Message for Describe {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'D', &self.target.encode()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Close {
+    pub target: Target,
+}
+
+impl Close {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let target = Target::read_next_message(stream)?;
+        Ok(Self { target })
+    }
+}
+
+impl Message for Close {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'C', &self.target.encode()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flush;
+
+impl Message for Flush {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'H', &[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseComplete;
+
+impl Message for ParseComplete {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'1', &[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindComplete;
+
+impl Message for BindComplete {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'2', &[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseComplete;
+
+impl Message for CloseComplete {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b'3', &[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortalSuspended;
+
+impl Message for PortalSuspended {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(frame(buffer, b's', &[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterDescription {
+    pub types: Vec<Oid>,
+}
+
+impl ParameterDescription {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let count = read_u16(stream)?;
+        let mut types = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            types.push(Oid(read_u32(stream)?));
+        }
+        Ok(Self { types })
+    }
+}
+
+impl Message for ParameterDescription {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&message_count(self.types.len())?.to_be_bytes());
+        for oid in &self.types {
+            body.extend_from_slice(&oid.0.to_be_bytes());
+        }
+        frame(buffer, b't', &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_roundtrip, ReadBack};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse() {
+        let mut parse = Parse::new("s1", "SELECT $1");
+        parse.parameter_types.push(Oid(23));
+
+        assert_eq!(&parse.encode()[..5], &[b'P', 0x00, 0x00, 0x00, 23]);
+        assert_roundtrip(parse);
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut bind = Bind::new("p1", "s1");
+        bind.parameter_formats = BindFormat::All(Format::Binary);
+        bind.parameters.push(Some(42i32.to_be_bytes().to_vec()));
+        bind.parameters.push(None);
+        bind.result_formats.push(1);
+
+        assert_roundtrip(bind);
+    }
+
+    #[test]
+    fn test_bind_too_many_parameters() {
+        let mut bind = Bind::new("", "s1");
+        bind.parameters = vec![None; u16::MAX as usize + 1];
+
+        let mut buffer = Vec::new();
+        assert_eq!(
+            bind.try_encode_into(&mut buffer),
+            Err(ProtocolError::CountOverflow {
+                count: u16::MAX as usize + 1
+            })
+        );
+        assert!(buffer.is_empty());
+    }
+
+    // The parameter format codes start after the portal and statement names.
+    fn parameter_format_codes(bind: &Bind) -> Vec<u8> {
+        let start = 5 + bind.portal.len() + 1 + bind.statement.len() + 1;
+        let encoded = bind.encode();
+        let count = u16::from_be_bytes([encoded[start], encoded[start + 1]]) as usize;
+        encoded[start..start + 2 + count * 2].to_vec()
+    }
+
+    #[test]
+    fn test_bind_text_format() {
+        let mut bind = Bind::new("", "s1");
+        bind.parameters.push(Some(b"42".to_vec()));
+        bind.parameters.push(Some(b"43".to_vec()));
+
+        assert_eq!(parameter_format_codes(&bind), [0, 0]);
+        assert_eq!(bind.parameter_formats.format(1), Some(Format::Text));
+        assert_roundtrip(bind);
+    }
+
+    #[test]
+    fn test_bind_all_format() {
+        let mut bind = Bind::new("", "s1");
+        bind.parameter_formats = BindFormat::All(Format::Binary);
+        bind.parameters.push(Some(42i32.to_be_bytes().to_vec()));
+        bind.parameters.push(Some(43i32.to_be_bytes().to_vec()));
+        bind.parameters.push(Some(44i32.to_be_bytes().to_vec()));
+
+        assert_eq!(parameter_format_codes(&bind), [0, 1, 0, 1]);
+        assert_eq!(bind.parameter_formats.format(2), Some(Format::Binary));
+        assert_roundtrip(bind);
+    }
+
+    #[test]
+    fn test_bind_per_parameter_format() {
+        let mut bind = Bind::new("", "s1");
+        bind.parameter_formats = BindFormat::PerParameter(vec![Format::Binary, Format::Text]);
+        bind.parameters.push(Some(42i32.to_be_bytes().to_vec()));
+        bind.parameters.push(Some(b"43".to_vec()));
+
+        assert_eq!(parameter_format_codes(&bind), [0, 2, 0, 1, 0, 0]);
+        assert_eq!(bind.parameter_formats.format(0), Some(Format::Binary));
+        assert_eq!(bind.parameter_formats.format(2), None);
+        assert_roundtrip(bind);
+    }
+
+    #[test]
+    fn test_bind_single_per_parameter_format() -> Result<(), Box<dyn Error>> {
+        let mut bind = Bind::new("", "s1");
+        bind.parameter_formats = BindFormat::PerParameter(vec![Format::Binary]);
+        bind.parameters.push(Some(42i32.to_be_bytes().to_vec()));
+
+        assert_eq!(parameter_format_codes(&bind), [0, 1, 0, 1]);
+        let encoded = bind.encode();
+        let decoded = Bind::read_back(&mut Cursor::new(encoded.as_slice()))?;
+        assert_eq!(decoded.parameter_formats, BindFormat::All(Format::Binary));
+        assert_eq!(
+            decoded.parameter_formats.format(0),
+            bind.parameter_formats.format(0)
+        );
+
+        Ok(())
+    }
+}
@@ -1,14 +1,37 @@
 use std::{
     error::Error,
-    io::{Cursor, Read},
+    fmt,
+    io::{self, Cursor, Read, Write},
     str,
 };
 
-use crate::{messages::Message, readers::*};
+use crate::{
+    auth,
+    messages::{
+        copy::{CopyData, CopyDone, CopyFail},
+        expect_encoded,
+        extended::{Bind, Close, Describe, Execute, Flush, Parse},
+        message_length, Message,
+    },
+    readers::*,
+    ProtocolError,
+};
+use tokio::io::{AsyncRead, BufReader};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FrontendMessage {
     SimpleQuery(SimpleQuery),
+    PasswordMessage(PasswordMessage),
+    Sync(Sync),
+    CopyData(CopyData),
+    CopyDone(CopyDone),
+    CopyFail(CopyFail),
+    Parse(Parse),
+    Bind(Bind),
+    Describe(Describe),
+    Execute(Execute),
+    Close(Close),
+    Flush(Flush),
     Termination(Termination),
 }
 
@@ -22,10 +45,37 @@ impl FrontendMessage {
 
         let r#type: u8 = header[0];
         let length: u32 = u32::from_be_bytes(header[1..5].try_into()?);
-        let mut buffer = Cursor::new(read_bytes(length as usize - 4, stream)?);
+        let mut buffer = Cursor::new(read_bytes(body_length(length)?, stream)?);
+        Self::decode(r#type, length, &mut buffer)
+    }
 
+    pub async fn read_next_message_async<R: AsyncRead + Unpin>(
+        stream: &mut BufReader<R>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let r#type = read_u8_async(stream).await?;
+        let length = read_u32_async(stream).await?;
+        let mut buffer = Cursor::new(read_bytes_async(body_length(length)?, stream).await?);
+        Self::decode(r#type, length, &mut buffer)
+    }
+
+    fn decode(
+        r#type: u8,
+        length: u32,
+        buffer: &mut Cursor<Vec<u8>>,
+    ) -> Result<Self, Box<dyn Error>> {
         let message: FrontendMessage = match r#type {
-            b'Q' => FrontendMessage::SimpleQuery(SimpleQuery::read_next_message(&mut buffer)?),
+            b'Q' => FrontendMessage::SimpleQuery(SimpleQuery::read_next_message(buffer)?),
+            b'p' => FrontendMessage::PasswordMessage(PasswordMessage::read_next_message(buffer)?),
+            b'S' => FrontendMessage::Sync(Sync),
+            b'd' => FrontendMessage::CopyData(CopyData::read_next_message(buffer)?),
+            b'c' => FrontendMessage::CopyDone(CopyDone),
+            b'f' => FrontendMessage::CopyFail(CopyFail::read_next_message(buffer)?),
+            b'P' => FrontendMessage::Parse(Parse::read_next_message(buffer)?),
+            b'B' => FrontendMessage::Bind(Bind::read_next_message(buffer)?),
+            b'D' => FrontendMessage::Describe(Describe::read_next_message(buffer)?),
+            b'E' => FrontendMessage::Execute(Execute::read_next_message(buffer)?),
+            b'C' => FrontendMessage::Close(Close::read_next_message(buffer)?),
+            b'H' => FrontendMessage::Flush(Flush),
             b'X' => {
                 assert_eq!(length, 4);
                 FrontendMessage::Termination(Termination)
@@ -44,10 +94,54 @@ impl FrontendMessage {
 }
 
 impl Message for FrontendMessage {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            FrontendMessage::SimpleQuery(query) => query.encode_into(buffer),
+            FrontendMessage::PasswordMessage(password) => password.encode_into(buffer),
+            FrontendMessage::Sync(sync) => sync.encode_into(buffer),
+            FrontendMessage::CopyData(copy_data) => copy_data.encode_into(buffer),
+            FrontendMessage::CopyDone(copy_done) => copy_done.encode_into(buffer),
+            FrontendMessage::CopyFail(copy_fail) => copy_fail.encode_into(buffer),
+            FrontendMessage::Parse(parse) => parse.encode_into(buffer),
+            FrontendMessage::Bind(bind) => bind.encode_into(buffer),
+            FrontendMessage::Describe(describe) => describe.encode_into(buffer),
+            FrontendMessage::Execute(execute) => execute.encode_into(buffer),
+            FrontendMessage::Close(close) => close.encode_into(buffer),
+            FrontendMessage::Flush(flush) => flush.encode_into(buffer),
+            FrontendMessage::Termination(terminationa) => terminationa.encode_into(buffer),
+        }
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        match self {
+            FrontendMessage::SimpleQuery(query) => query.try_encode_into(buffer),
+            FrontendMessage::Parse(parse) => parse.try_encode_into(buffer),
+            FrontendMessage::Bind(bind) => bind.try_encode_into(buffer),
+            message => {
+                message.encode_into(buffer);
+                Ok(())
+            }
+        }
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match self {
+            FrontendMessage::SimpleQuery(query) => query.encode_to(writer),
+            message => writer.write_all(&message.encode()),
+        }
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
         match self {
-            FrontendMessage::SimpleQuery(query) => query.encode(),
-            FrontendMessage::Termination(terminationa) => terminationa.encode(),
+            FrontendMessage::SimpleQuery(query) => query.encoded_len(),
+            _ => None,
+        }
+    }
+
+    fn requires_flush(&self) -> bool {
+        match self {
+            FrontendMessage::CopyData(copy_data) => copy_data.requires_flush(),
+            _ => true,
         }
     }
 }
@@ -58,29 +152,325 @@ pub struct SimpleQuery {
 }
 
 impl SimpleQuery {
-    pub fn new(query: impl Into<String>) -> Self {
+    /// A query fixed at compile time. Dynamic values belong in the
+    /// parameters of a prepared statement, not spliced into the query text.
+    pub fn new(query: &'static str) -> Self {
+        Self::new_trusted(query)
+    }
+
+    /// A query built at runtime. The caller vouches that anything
+    /// interpolated into it has been escaped.
+    pub fn new_trusted(query: impl Into<String>) -> Self {
         Self {
             query: query.into(),
         }
     }
 
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        Ok(SimpleQuery::new(read_string(stream)?))
+    /// Builds a query from static SQL and quoted values, for the places a
+    /// prepared statement cannot go, such as `SET` or `COPY`.
+    pub fn builder() -> SimpleQueryBuilder {
+        SimpleQueryBuilder::default()
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.query.trim().is_empty()
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        Ok(SimpleQuery::new_trusted(read_string(stream)?))
+    }
+}
+
+/// Appends to a query only SQL fixed at compile time and values quoted as
+/// literals or identifiers, so that nothing dynamic reaches the server
+/// unescaped.
+#[derive(Debug, Default, Clone)]
+pub struct SimpleQueryBuilder {
+    query: String,
+}
+
+impl SimpleQueryBuilder {
+    pub fn sql(mut self, sql: &'static str) -> Self {
+        self.query.push_str(sql);
+        self
+    }
+
+    /// Appends `value` as a string literal, the way `quote_literal` does:
+    /// quotes are doubled, and a value with backslashes becomes an `E''`
+    /// string with them doubled too.
+    pub fn literal(mut self, value: &str) -> Self {
+        if value.contains('\\') {
+            self.query.push('E');
+        }
+        self.query.push('\'');
+        for c in value.chars() {
+            if matches!(c, '\'' | '\\') {
+                self.query.push(c);
+            }
+            self.query.push(c);
+        }
+        self.query.push('\'');
+        self
+    }
+
+    /// Appends `name` as a quoted identifier, doubling any quotes in it.
+    pub fn identifier(mut self, name: &str) -> Self {
+        self.query.push('"');
+        self.query.push_str(&name.replace('"', "\"\""));
+        self.query.push('"');
+        self
+    }
+
+    pub fn build(self) -> SimpleQuery {
+        SimpleQuery::new_trusted(self.query)
     }
 }
 
 impl Message for SimpleQuery {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = vec![];
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
 
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        // 1 byte for null terminator
+        let length = message_length(self.query.len() + 1)?;
         buffer.push(b'Q');
+        buffer.extend_from_slice(&length.to_be_bytes());
+        buffer.extend_from_slice(self.query.as_bytes());
+        buffer.push(0);
+        Ok(())
+    }
+
+    // Bulk INSERTs can run to many megabytes, so the query is written
+    // straight from the String rather than copied into a buffer first.
+    fn encode_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let length = message_length(self.query.len() + 1)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut header = [b'Q', 0, 0, 0, 0];
+        header[1..].copy_from_slice(&length.to_be_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(self.query.as_bytes())?;
+        writer.write_all(&[0])
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        let length = message_length(self.query.len() + 1).ok()?;
+        Some(1 + length as usize)
+    }
+}
+
+/// Debug masks the password, which may be in cleartext.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PasswordMessage {
+    pub password: String,
+}
+
+impl fmt::Debug for PasswordMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordMessage")
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+impl PasswordMessage {
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            password: password.into(),
+        }
+    }
+
+    pub fn md5(user: &str, password: &str, salt: [u8; 4]) -> Self {
+        Self::new(auth::md5_salted(&auth::md5_hash(user, password), salt))
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        Ok(PasswordMessage::new(read_string(stream)?))
+    }
+}
+
+impl Message for PasswordMessage {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'p');
         // 4 bytes for length
         // 1 byte for null terminator
-        buffer.extend_from_slice(&(self.query.len() as u32 + 4 + 1).to_be_bytes());
-        buffer.extend_from_slice(&self.query.as_bytes());
+        buffer.extend_from_slice(&(self.password.len() as u32 + 4 + 1).to_be_bytes());
+        buffer.extend_from_slice(self.password.as_bytes());
+        buffer.push(0);
+    }
+}
+
+/// The first SASL message, choosing a mechanism from those the server
+/// offered in AuthenticationSASL. Like PasswordMessage, its tag is `p`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SASLInitialResponse {
+    pub mechanism: String,
+    /// None is sent as a length of -1, for mechanisms where the client
+    /// does not speak first.
+    pub data: Option<Vec<u8>>,
+}
+
+impl SASLInitialResponse {
+    pub fn new(mechanism: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            mechanism: mechanism.into(),
+            data: Some(data.into()),
+        }
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let mechanism = read_string(stream)?;
+        let data = match read_u32(stream)? {
+            0xFFFFFFFF => None,
+            length => Some(read_bytes(length as usize, stream)?),
+        };
+        Ok(Self { mechanism, data })
+    }
+}
+
+impl Message for SASLInitialResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let data = self.data.as_deref().unwrap_or_default();
+        // 1 byte for null terminator
+        // 4 bytes for data length
+        let length = message_length(self.mechanism.len() + 1 + 4 + data.len())?;
+        buffer.push(b'p');
+        buffer.extend_from_slice(&length.to_be_bytes());
+        buffer.extend_from_slice(self.mechanism.as_bytes());
         buffer.push(0);
+        match &self.data {
+            Some(data) => {
+                // Shorter than the whole message, whose length fit.
+                let data_length = u32::try_from(data.len()).expect("data fits in the message");
+                buffer.extend_from_slice(&data_length.to_be_bytes());
+            }
+            None => buffer.extend_from_slice(&0xFFFFFFFFu32.to_be_bytes()),
+        }
+        buffer.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A later SASL message, answering AuthenticationSASLContinue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SASLResponse {
+    pub data: Vec<u8>,
+}
+
+impl SASLResponse {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Self { data: data.into() }
+    }
 
-        buffer
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(stream.read_remaining()?))
+    }
+}
+
+impl Message for SASLResponse {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let length = message_length(self.data.len())?;
+        buffer.push(b'p');
+        buffer.extend_from_slice(&length.to_be_bytes());
+        buffer.extend_from_slice(&self.data);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sasl_messages() {
+    let initial = SASLInitialResponse::new("SCRAM-SHA-256", "n,,n=,r=abc");
+    assert_eq!(&initial.encode()[..5], &[b'p', 0, 0, 0, 33]);
+    crate::test_support::assert_roundtrip(initial);
+    crate::test_support::assert_roundtrip(SASLInitialResponse {
+        mechanism: "OAUTHBEARER".to_string(),
+        data: None,
+    });
+    crate::test_support::assert_roundtrip(SASLResponse::new("c=biws,r=abc,p=xyz"));
+}
+
+#[test]
+fn test_simple_query_constructors_agree() {
+    let dynamic = String::from("SELECT 1");
+    assert_eq!(
+        SimpleQuery::new("SELECT 1").encode(),
+        SimpleQuery::new_trusted(dynamic).encode()
+    );
+}
+
+#[test]
+fn test_simple_query_builder() {
+    let query = SimpleQuery::builder()
+        .sql("SET search_path = ")
+        .identifier("my \"schema\"")
+        .sql("; SELECT ")
+        .literal("it's")
+        .sql(", ")
+        .literal("C:\\temp")
+        .build();
+    assert_eq!(
+        query.query(),
+        r#"SET search_path = "my ""schema"""; SELECT 'it''s', E'C:\\temp'"#
+    );
+}
+
+#[test]
+fn test_simple_query_encode_to() -> Result<(), Box<dyn Error>> {
+    let mut query = String::from("INSERT INTO t VALUES ");
+    while query.len() < 1024 * 1024 {
+        query.push_str("(1, 'one'), ");
+    }
+    query.push_str("(2, 'two')");
+    let simple_query = SimpleQuery::new_trusted(query);
+
+    let mut streamed = Vec::new();
+    simple_query.encode_to(&mut streamed)?;
+    assert_eq!(streamed, simple_query.encode());
+    assert_eq!(simple_query.encoded_len(), Some(streamed.len()));
+
+    Ok(())
+}
+
+#[test]
+fn test_password_message() -> Result<(), Box<dyn Error>> {
+    let password = PasswordMessage::new("secret");
+    let encoded = password.encode();
+    assert_eq!(
+        encoded,
+        vec![
+            b'p',                 // message tag
+            0x00, 0x00, 0x00, 11, // length
+            b's', b'e', b'c', b'r', b'e', b't', 0x00,
+        ]
+    );
+
+    let mut cursor = Cursor::new(encoded);
+    let decoded = FrontendMessage::read_next_message(&mut cursor)?;
+    assert_eq!(decoded, FrontendMessage::PasswordMessage(password));
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sync;
+
+impl Message for Sync {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.push(b'S');
+        buffer.extend_from_slice(&4u32.to_be_bytes());
     }
 }
 
@@ -88,12 +478,28 @@ impl Message for SimpleQuery {
 pub struct Termination;
 
 impl Message for Termination {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = vec![];
-
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'X');
         buffer.extend_from_slice(&4u32.to_be_bytes());
-
-        buffer
     }
 }
+
+#[test]
+fn test_empty_simple_query() -> Result<(), Box<dyn Error>> {
+    let query = SimpleQuery::new("");
+    let encoded = query.encode();
+    assert_eq!(
+        encoded,
+        vec![
+            b'Q',                // message tag
+            0x00, 0x00, 0x00, 5, // length
+            0x00,                // empty query string
+        ]
+    );
+
+    let mut cursor = Cursor::new(encoded);
+    let decoded = FrontendMessage::read_next_message(&mut cursor)?;
+    assert_eq!(decoded, FrontendMessage::SimpleQuery(query));
+
+    Ok(())
+}
@@ -1,8 +1,148 @@
 pub mod backend;
+pub mod copy;
+pub mod extended;
 pub mod frontend;
 pub mod ssl;
 pub mod startup;
 
+use std::io::{self, Write};
+
+pub use rpsql_wire::ByteReader;
+
+use crate::ProtocolError;
+
+/// The length field for a message with `body_length` bytes after it, which
+/// counts its own 4 bytes too.
+pub(crate) fn message_length(body_length: usize) -> Result<u32, ProtocolError> {
+    body_length
+        .checked_add(4)
+        .and_then(|length| u32::try_from(length).ok())
+        .ok_or(ProtocolError::LengthOverflow {
+            length: body_length.saturating_add(4),
+        })
+}
+
+/// The 16-bit count that precedes `count` fields, parameters or formats.
+pub(crate) fn message_count(count: usize) -> Result<u16, ProtocolError> {
+    u16::try_from(count).map_err(|_| ProtocolError::CountOverflow { count })
+}
+
+// For encode_into, which has no way to fail. A wrapped length would corrupt
+// the stream, so this panics instead.
+pub(crate) fn expect_encoded(result: Result<(), ProtocolError>) {
+    if let Err(err) = result {
+        panic!("{err}");
+    }
+}
+
+// Kept object safe, so that messages can be held as `Box<dyn Message>`.
 pub trait Message {
-    fn encode(&self) -> Vec<u8>;
+    /// Appends the encoded message to `buffer`, so that one allocation can
+    /// be reused across many messages.
+    ///
+    /// Panics if the message is too long for its length field.
+    fn encode_into(&self, buffer: &mut Vec<u8>);
+
+    /// Like `encode_into`, but fails rather than panicking if the message
+    /// is too long for its length field, appending nothing. Messages whose
+    /// length depends on their contents override it.
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        self.encode_into(buffer);
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode_into(&mut buffer);
+        buffer
+    }
+
+    fn try_encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let mut buffer = Vec::new();
+        self.try_encode_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Writes the encoded message to `writer`. The default encodes into a
+    /// buffer first; messages that can be very large override it to write
+    /// their bodies straight from where they are.
+    fn encode_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+
+    /// The length of the encoded message, tag included, worked out from
+    /// its declared length without encoding it. Messages that override
+    /// `encode_to` give it, so that a sender can check the size before
+    /// writing anything; None means the message has to be encoded to know,
+    /// or is too long for its length field.
+    fn encoded_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the peer may be waiting on this message. Senders that buffer
+    /// writes must flush these immediately; bulk data can stay buffered.
+    fn requires_flush(&self) -> bool {
+        true
+    }
+}
+
+impl<M: Message + ?Sized> Message for Box<M> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        (**self).encode_into(buffer)
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        (**self).try_encode_into(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (**self).encode_to(writer)
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        (**self).encoded_len()
+    }
+
+    fn requires_flush(&self) -> bool {
+        (**self).requires_flush()
+    }
+}
+
+#[test]
+fn test_message_is_object_safe() {
+    let messages: Vec<Box<dyn Message>> = vec![Box::new(frontend::Sync), Box::new(copy::CopyDone)];
+    let encoded: Vec<u8> = messages.iter().flat_map(Message::encode).collect();
+    assert_eq!(encoded, [b'S', 0, 0, 0, 4, b'c', 0, 0, 0, 4]);
+}
+
+#[test]
+fn test_message_length_overflow() {
+    assert_eq!(message_length(0), Ok(4));
+    assert_eq!(message_length(u32::MAX as usize - 4), Ok(u32::MAX));
+    assert_eq!(
+        message_length(u32::MAX as usize - 3),
+        Err(ProtocolError::LengthOverflow {
+            length: u32::MAX as usize + 1
+        })
+    );
+    assert!(message_length(usize::MAX).is_err());
+}
+
+#[test]
+fn test_message_count_overflow() {
+    assert_eq!(message_count(u16::MAX as usize), Ok(u16::MAX));
+    assert_eq!(
+        message_count(u16::MAX as usize + 1),
+        Err(ProtocolError::CountOverflow {
+            count: u16::MAX as usize + 1
+        })
+    );
+}
+
+#[test]
+fn test_encode_into_appends() {
+    let mut buffer = vec![0xAA];
+    frontend::Sync.encode_into(&mut buffer);
+    copy::CopyDone.encode_into(&mut buffer);
+    assert_eq!(buffer, [0xAA, b'S', 0, 0, 0, 4, b'c', 0, 0, 0, 4]);
 }
@@ -1,23 +1,44 @@
 use std::{
     error::Error,
-    io::{Cursor, Read},
+    io::Cursor,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 
-use crate::{messages::Message, readers::*};
+use crate::{messages::Message, readers::*, ProtocolError};
+
+// The "protocol versions" that mark an SSLRequest and a GSSENCRequest.
+const SSL_REQUEST_CODE: (u16, u16) = (1234, 5679);
+const GSSENC_REQUEST_CODE: (u16, u16) = (1234, 5680);
+
+fn read_request_code(buffer: &mut impl ByteReader) -> Result<(u16, u16), Box<dyn Error>> {
+    Ok((read_u16(buffer)?, read_u16(buffer)?))
+}
+
+fn expect_request_code(code: (u16, u16), expected: (u16, u16)) -> Result<(), ProtocolError> {
+    if code != expected {
+        return Err(ProtocolError::UnsupportedProtocolVersion {
+            major: code.0,
+            minor: code.1,
+        });
+    }
+    Ok(())
+}
+
+fn encode_request_code(buffer: &mut Vec<u8>, (major, minor): (u16, u16)) {
+    let length: u32 = 8;
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(&major.to_be_bytes());
+    buffer.extend_from_slice(&minor.to_be_bytes());
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SSLRequest;
 
 impl SSLRequest {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let length = read_u32(stream)?;
-        let mut buffer = Cursor::new(read_bytes(length as usize - 4, stream)?);
-
-        let protocol_major_version = read_u16(&mut buffer)?;
-        let protocol_minor_version = read_u16(&mut buffer)?;
-        assert_eq!(protocol_major_version, 1234);
-        assert_eq!(protocol_minor_version, 5679);
+        let mut buffer = Cursor::new(read_bytes(body_length(length)?, stream)?);
+        expect_request_code(read_request_code(&mut buffer)?, SSL_REQUEST_CODE)?;
         Ok(SSLRequest)
     }
 
@@ -25,28 +46,35 @@ impl SSLRequest {
         stream: &mut BufReader<R>,
     ) -> Result<Self, Box<dyn Error>> {
         let length = stream.read_u32().await?;
-        let mut buffer = Cursor::new(read_bytes_async(length as usize - 4, stream).await?);
-
-        let protocol_major_version = read_u16(&mut buffer)?;
-        let protocol_minor_version = read_u16(&mut buffer)?;
-        assert_eq!(protocol_major_version, 1234);
-        assert_eq!(protocol_minor_version, 5679);
+        let mut buffer = Cursor::new(read_bytes_async(body_length(length)?, stream).await?);
+        expect_request_code(read_request_code(&mut buffer)?, SSL_REQUEST_CODE)?;
         Ok(SSLRequest)
     }
 }
 
 impl Message for SSLRequest {
-    fn encode(&self) -> Vec<u8> {
-        let length: u32 = 8;
-        let protocol_major_version: u16 = 1234;
-        let protocol_minor_version: u16 = 5679;
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        encode_request_code(buffer, SSL_REQUEST_CODE);
+    }
+}
 
-        let mut buffer = vec![];
-        buffer.extend_from_slice(&length.to_be_bytes());
-        buffer.extend_from_slice(&protocol_major_version.to_be_bytes());
-        buffer.extend_from_slice(&protocol_minor_version.to_be_bytes());
+/// Asks the server to encrypt the connection with GSSAPI. It is answered
+/// with a single `G` or `N` byte, like an SSLRequest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GSSENCRequest;
 
-        buffer
+impl GSSENCRequest {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let length = read_u32(stream)?;
+        let mut buffer = Cursor::new(read_bytes(body_length(length)?, stream)?);
+        expect_request_code(read_request_code(&mut buffer)?, GSSENC_REQUEST_CODE)?;
+        Ok(GSSENCRequest)
+    }
+}
+
+impl Message for GSSENCRequest {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        encode_request_code(buffer, GSSENC_REQUEST_CODE);
     }
 }
 
@@ -71,16 +99,16 @@ pub enum SSLResponse {
 }
 
 impl Message for SSLResponse {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         match self {
-            SSLResponse::S => vec![b'S'],
-            SSLResponse::N => vec![b'N'],
+            SSLResponse::S => buffer.push(b'S'),
+            SSLResponse::N => buffer.push(b'N'),
         }
     }
 }
 
 impl SSLResponse {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let message_type = read_u8(stream)?;
         match message_type {
             b'S' => Ok(SSLResponse::S),
@@ -129,14 +157,16 @@ fn test_ssl_response_n() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Debug, Clone)]
+/// Either side of the encryption handshake that may precede a Startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SSLMessage {
     SSLRequest(SSLRequest),
+    GSSENCRequest(GSSENCRequest),
     SSLResponse(SSLResponse),
 }
 
 impl SSLMessage {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let message_type = read_u8(stream)?;
         match message_type {
             b'S' => Ok(SSLMessage::SSLResponse(SSLResponse::S)),
@@ -144,15 +174,56 @@ impl SSLMessage {
             0 => {
                 let bytes = [0, read_u8(stream)?, read_u8(stream)?, read_u8(stream)?];
                 let length: u32 = u32::from_be_bytes(bytes);
-                let mut buffer = Cursor::new(read_bytes(length as usize - 4, stream)?);
-
-                let protocol_major_version = read_u16(&mut buffer)?;
-                let protocol_minor_version = read_u16(&mut buffer)?;
-                assert_eq!(protocol_major_version, 1234);
-                assert_eq!(protocol_minor_version, 5679);
-                Ok(SSLMessage::SSLRequest(SSLRequest))
+                let mut buffer = Cursor::new(read_bytes(body_length(length)?, stream)?);
+
+                match read_request_code(&mut buffer)? {
+                    SSL_REQUEST_CODE => Ok(SSLMessage::SSLRequest(SSLRequest)),
+                    GSSENC_REQUEST_CODE => Ok(SSLMessage::GSSENCRequest(GSSENCRequest)),
+                    (major, minor) => {
+                        Err(ProtocolError::UnsupportedProtocolVersion { major, minor }.into())
+                    }
+                }
             }
             _ => Err("Unknown ssl message type".into()),
         }
     }
 }
+
+impl Message for SSLMessage {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            SSLMessage::SSLRequest(ssl_request) => ssl_request.encode_into(buffer),
+            SSLMessage::GSSENCRequest(gssenc_request) => gssenc_request.encode_into(buffer),
+            SSLMessage::SSLResponse(ssl_response) => ssl_response.encode_into(buffer),
+        }
+    }
+}
+
+#[test]
+fn test_ssl_message_roundtrip() {
+    use crate::test_support::assert_roundtrip;
+
+    assert_roundtrip(SSLMessage::SSLRequest(SSLRequest));
+    assert_roundtrip(SSLMessage::GSSENCRequest(GSSENCRequest));
+    assert_roundtrip(SSLMessage::SSLResponse(SSLResponse::N));
+    assert_eq!(
+        GSSENCRequest.encode(),
+        vec![0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x30]
+    );
+}
+
+#[test]
+fn test_ssl_message_unknown_request_code() {
+    let mut encoded = 8u32.to_be_bytes().to_vec();
+    encoded.extend_from_slice(&1234u16.to_be_bytes());
+    encoded.extend_from_slice(&5678u16.to_be_bytes());
+
+    let err = SSLMessage::read_next_message(&mut Cursor::new(encoded)).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ProtocolError>(),
+        Some(&ProtocolError::UnsupportedProtocolVersion {
+            major: 1234,
+            minor: 5678
+        })
+    );
+}
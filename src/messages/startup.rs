@@ -1,16 +1,40 @@
 use crate::{
-    messages::{ssl::SSLRequest, Message},
+    messages::{
+        backend::{ErrorResponse, NoticeMessage},
+        expect_encoded, message_length,
+        ssl::SSLRequest,
+        Message,
+    },
     readers::*,
-    state::{Authentication, BackendKeyData, ParameterStatus, ReadyForQuery},
+    state::{
+        Authentication, BackendKeyData, NegotiateProtocolVersion, ParameterStatus, ReadyForQuery,
+    },
+    ProtocolError,
 };
 use std::{
     error::Error,
-    io::{Cursor, Read},
+    fmt,
+    io::Cursor,
     str,
 };
 use tokio::io::{AsyncRead, BufReader};
 
-#[derive(Debug, Clone)]
+// Startup requests come from clients that have not authenticated yet, so
+// their length is capped before anything is allocated for them. The server
+// rejects anything longer than this too.
+pub const MAX_STARTUP_LENGTH: u32 = 10_000;
+
+fn startup_body_length(length: u32) -> Result<usize, ProtocolError> {
+    if length > MAX_STARTUP_LENGTH {
+        return Err(ProtocolError::MessageTooLong {
+            length,
+            max: MAX_STARTUP_LENGTH,
+        });
+    }
+    body_length(length)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StartupRequest {
     SSLRequest(SSLRequest),
     Startup(Startup),
@@ -18,17 +42,32 @@ pub enum StartupRequest {
 }
 
 impl StartupRequest {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let length = read_u32(stream)? as usize;
-        let protocol_major_version = read_u16(stream)?;
-        let protocol_minor_version = read_u16(stream)?;
+    pub async fn read_next_message_async<R: AsyncRead + Unpin>(
+        stream: &mut BufReader<R>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let length = read_u32_async(stream).await?;
+        let body_length = startup_body_length(length)?;
+        let mut buffer = Cursor::new(read_bytes_async(body_length, stream).await?);
+        Self::decode(length, &mut buffer)
+    }
+
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let length = read_u32(stream)?;
+        let mut buffer = Cursor::new(read_bytes(startup_body_length(length)?, stream)?);
+        Self::decode(length, &mut buffer)
+    }
+
+    // Startup requests are untagged, so the protocol version that follows
+    // the length tells them apart.
+    fn decode(length: u32, buffer: &mut Cursor<Vec<u8>>) -> Result<Self, Box<dyn Error>> {
+        let protocol_major_version = read_u16(buffer)?;
+        let protocol_minor_version = read_u16(buffer)?;
 
-        let mut buffer = Cursor::new(read_bytes(length - 8, stream)?);
         match (length, protocol_major_version, protocol_minor_version) {
             (8, 1234, 5679) => Ok(Self::SSLRequest(SSLRequest)),
             (16, 1234, 5678) => {
-                let process_id = read_u32(&mut buffer)?;
-                let secret_key = read_u32(&mut buffer)?;
+                let process_id = read_u32(buffer)?;
+                let secret_key = read_u32(buffer)?;
                 Ok(Self::CancelRequest(CancelRequest {
                     process_id,
                     secret_key,
@@ -38,29 +77,32 @@ impl StartupRequest {
                 let mut startup = Startup::new();
 
                 loop {
-                    let key = read_string(&mut buffer)?;
+                    let key = read_string(buffer)?;
                     if key.is_empty() {
                         break;
                     }
 
-                    let value = read_string(&mut buffer)?;
+                    let value = read_string(buffer)?;
                     startup.add_parameter(&key, &value);
                 }
                 Ok(Self::Startup(startup))
             }
-            (_, _, _) => panic!(
-                "Unsupported protocol version: {protocol_major_version}.{protocol_minor_version}"
-            ),
+            (_, major, minor) => {
+                Err(ProtocolError::UnsupportedProtocolVersion { major, minor }.into())
+            }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StartupResponse {
     Authentication(Authentication),
     ParameterStatus(ParameterStatus),
     BackendKeyData(BackendKeyData),
     ReadyForQuery(ReadyForQuery),
+    ErrorResponse(ErrorResponse),
+    Notice(NoticeMessage),
+    NegotiateProtocolVersion(NegotiateProtocolVersion),
 }
 
 impl StartupResponse {
@@ -69,21 +111,25 @@ impl StartupResponse {
     ) -> Result<Option<Self>, Box<dyn Error>> {
         let r#type = read_u8_async(stream).await?;
 
-        let length = read_u32_async(stream).await? as usize;
-        let mut buffer = Cursor::new(read_bytes_async(length - 4, stream).await?);
+        let length = read_u32_async(stream).await?;
+        let mut buffer = Cursor::new(read_bytes_async(body_length(length)?, stream).await?);
 
         let message = match r#type {
             b'R' => Some(Self::Authentication(Authentication::read_next_message(&mut buffer)?)),
             b'S' => Some(Self::ParameterStatus(ParameterStatus::read_next_message(&mut buffer)?)),
             b'K' => Some(Self::BackendKeyData(BackendKeyData::read_next_message(&mut buffer)?)),
             b'Z' => Some(Self::ReadyForQuery(ReadyForQuery::read_next_message(&mut buffer)?)),
+            b'E' => Some(Self::ErrorResponse(ErrorResponse::read_next_message(&mut buffer)?)),
+            b'N' => Some(Self::Notice(NoticeMessage::read_next_message(&mut buffer)?)),
+            b'v' => Some(Self::NegotiateProtocolVersion(
+                NegotiateProtocolVersion::read_next_message(&mut buffer)?,
+            )),
             _ => {
                 eprintln!("unsupported message type: {}", str::from_utf8(&[r#type])?);
                 eprintln!("startup response length: {}", length);
-                match str::from_utf8(buffer.get_ref()) {
-                    Ok(s) => eprintln!("buffer: {s}"),
-                    Err(_) => {}
-                };
+                if let Ok(s) = str::from_utf8(buffer.get_ref()) {
+                    eprintln!("buffer: {s}");
+                }
                 return Err("unsupported message type".into());
             }
         };
@@ -91,24 +137,28 @@ impl StartupResponse {
         Ok(message)
     }
 
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Option<Self>, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Option<Self>, Box<dyn Error>> {
         let r#type = read_u8(stream)?;
 
-        let length = read_u32(stream)? as usize;
-        let mut buffer = Cursor::new(read_bytes(length - 4, stream)?);
+        let length = read_u32(stream)?;
+        let mut buffer = Cursor::new(read_bytes(body_length(length)?, stream)?);
 
         let message = match r#type {
             b'R' =>  Some(Self::Authentication(Authentication::read_next_message(&mut buffer)?)),
             b'S' => Some(Self::ParameterStatus(ParameterStatus::read_next_message(&mut buffer)?)),
             b'K' => Some(Self::BackendKeyData(BackendKeyData::read_next_message(&mut buffer)?)),
             b'Z' => Some(Self::ReadyForQuery(ReadyForQuery::read_next_message(&mut buffer)?)),
+            b'E' => Some(Self::ErrorResponse(ErrorResponse::read_next_message(&mut buffer)?)),
+            b'N' => Some(Self::Notice(NoticeMessage::read_next_message(&mut buffer)?)),
+            b'v' => Some(Self::NegotiateProtocolVersion(
+                NegotiateProtocolVersion::read_next_message(&mut buffer)?,
+            )),
             _ => {
                 eprintln!("unsupported message type: {}", str::from_utf8(&[r#type])?);
                 eprintln!("startup response length: {}", length);
-                match str::from_utf8(buffer.get_ref()) {
-                    Ok(s) => eprintln!("buffer: {s}"),
-                    Err(_) => {}
-                };
+                if let Ok(s) = str::from_utf8(buffer.get_ref()) {
+                    eprintln!("buffer: {s}");
+                }
                 None
             }
         };
@@ -118,27 +168,41 @@ impl StartupResponse {
 }
 
 impl Message for StartupRequest {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Self::SSLRequest(ssl_request) => ssl_request.encode_into(buffer),
+            Self::Startup(startup) => startup.encode_into(buffer),
+            Self::CancelRequest(cancel_request) => cancel_request.encode_into(buffer),
+        }
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
         match self {
-            Self::SSLRequest(ssl_request) => ssl_request.encode(),
-            Self::Startup(startup) => startup.encode(),
-            Self::CancelRequest(cancel_request) => cancel_request.encode(),
+            Self::Startup(startup) => startup.try_encode_into(buffer),
+            message => {
+                message.encode_into(buffer);
+                Ok(())
+            }
         }
     }
 }
 
 impl Message for StartupResponse {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         match self {
-            Self::Authentication(authentication) => authentication.encode(),
-            Self::ParameterStatus(parameter_status) => parameter_status.encode(),
-            Self::BackendKeyData(backend_key_data) => backend_key_data.encode(),
-            Self::ReadyForQuery(ready_for_query) => ready_for_query.encode(),
+            Self::Authentication(authentication) => authentication.encode_into(buffer),
+            Self::ParameterStatus(parameter_status) => parameter_status.encode_into(buffer),
+            Self::BackendKeyData(backend_key_data) => backend_key_data.encode_into(buffer),
+            Self::ReadyForQuery(ready_for_query) => ready_for_query.encode_into(buffer),
+            Self::ErrorResponse(error_response) => error_response.encode_into(buffer),
+            Self::Notice(notice) => notice.encode_into(buffer),
+            Self::NegotiateProtocolVersion(negotiate) => negotiate.encode_into(buffer),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Debug masks the value of any `password` parameter.
+#[derive(Clone, PartialEq, Eq)]
 pub struct Startup {
     length: u32,
     pub protocol_major_version: u16,
@@ -161,27 +225,62 @@ impl Default for Startup {
     }
 }
 
+impl fmt::Debug for Startup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parameters: Vec<(&str, &str)> = self
+            .parameters
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                "password" => (key.as_str(), "***"),
+                _ => (key.as_str(), value.as_str()),
+            })
+            .collect();
+        f.debug_struct("Startup")
+            .field("length", &self.length)
+            .field("protocol_major_version", &self.protocol_major_version)
+            .field("protocol_minor_version", &self.protocol_minor_version)
+            .field("parameters", &parameters)
+            .finish()
+    }
+}
+
 impl Startup {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn parameter(&self, key: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
     pub fn add_parameter(&mut self, key: &str, value: &str) {
         self.parameters.push((key.to_string(), value.to_string()));
-        self.length += key.len() as u32 + 1;
-        self.length += value.len() as u32 + 1;
+        // Saturates rather than wraps; encode_into checks the real length.
+        let added = u32::try_from(key.len() + 1 + value.len() + 1).unwrap_or(u32::MAX);
+        self.length = self.length.saturating_add(added);
     }
 
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let length = read_u32(stream)? as usize;
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let length = read_u32(stream)?;
+        let parameters_length = startup_body_length(length)?
+            .checked_sub(4)
+            .ok_or(ProtocolError::LengthTooShort { length })?;
         let protocol_major_version = read_u16(stream)?;
         let protocol_minor_version = read_u16(stream)?;
 
-        assert_eq!(protocol_major_version, 3);
-        assert_eq!(protocol_minor_version, 0);
+        if (protocol_major_version, protocol_minor_version) != (3, 0) {
+            return Err(ProtocolError::UnsupportedProtocolVersion {
+                major: protocol_major_version,
+                minor: protocol_minor_version,
+            }
+            .into());
+        }
 
         let mut startup = Startup::new();
-        let mut buffer = Cursor::new(read_bytes(length - 8, stream)?);
+        let mut buffer = Cursor::new(read_bytes(parameters_length, stream)?);
         loop {
             let key = read_string(&mut buffer)?;
             if key.is_empty() {
@@ -196,7 +295,11 @@ impl Startup {
 }
 
 impl Message for Startup {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        expect_encoded(self.try_encode_into(buffer))
+    }
+
+    fn try_encode_into(&self, buffer: &mut Vec<u8>) -> Result<(), ProtocolError> {
         let mut parameter_buffer: Vec<u8> = vec![];
         for (key, value) in &self.parameters {
             parameter_buffer.extend_from_slice(key.as_bytes());
@@ -206,34 +309,182 @@ impl Message for Startup {
             parameter_buffer.push(0);
         }
 
-        let mut buffer: Vec<u8> = vec![];
-
-        buffer.extend_from_slice(&self.length.to_be_bytes());
+        // 2 bytes each for the protocol versions
+        // 1 byte for the final null terminator
+        let length = message_length(2 + 2 + parameter_buffer.len() + 1)?;
+        buffer.extend_from_slice(&length.to_be_bytes());
         buffer.extend_from_slice(&self.protocol_major_version.to_be_bytes());
         buffer.extend_from_slice(&self.protocol_minor_version.to_be_bytes());
         buffer.extend_from_slice(&parameter_buffer);
         buffer.push(0);
-
-        buffer
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+/// Debug masks `secret_key`, as for BackendKeyData.
+#[derive(Clone, PartialEq, Eq)]
 pub struct CancelRequest {
     pub process_id: u32,
     pub secret_key: u32,
 }
 
-impl Message for CancelRequest {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = vec![];
+impl fmt::Debug for CancelRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelRequest")
+            .field("process_id", &self.process_id)
+            .field("secret_key", &"***")
+            .finish()
+    }
+}
 
+impl Message for CancelRequest {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.extend_from_slice(&16u32.to_be_bytes());
         buffer.extend_from_slice(&1234u16.to_be_bytes());
         buffer.extend_from_slice(&5678u16.to_be_bytes());
         buffer.extend_from_slice(&self.process_id.to_be_bytes());
         buffer.extend_from_slice(&self.secret_key.to_be_bytes());
+    }
+}
+
+#[test]
+fn test_debug_redacts_secrets() {
+    let mut startup = Startup::new();
+    startup.add_parameter("user", "postgres");
+    startup.add_parameter("password", "hunter2");
+    let debug = format!("{startup:?}");
+    assert!(debug.contains("postgres"));
+    assert!(!debug.contains("hunter2"));
+    assert_eq!(startup.parameter("password"), Some("hunter2"));
+
+    let cancel_request = CancelRequest {
+        process_id: 4321,
+        secret_key: 98_765_432,
+    };
+    assert!(!format!("{cancel_request:?}").contains("98765432"));
+}
+
+#[test]
+fn test_startup_equality() -> Result<(), Box<dyn Error>> {
+    let mut startup = Startup::new();
+    startup.add_parameter("user", "postgres");
+    startup.add_parameter("database", "postgres");
+
+    let mut other = Startup::new();
+    other.add_parameter("user", "postgres");
+    other.add_parameter("database", "postgres");
+    assert_eq!(startup, other);
+
+    let mut cursor = Cursor::new(startup.encode());
+    let decoded = StartupRequest::read_next_message(&mut cursor)?;
+    assert_eq!(decoded, StartupRequest::Startup(other));
+
+    Ok(())
+}
+
+#[test]
+fn test_startup_response_length_too_short() {
+    let mut cursor = Cursor::new(vec![b'R', 0x00, 0x00, 0x00, 3]);
+    let err = StartupResponse::read_next_message(&mut cursor).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ProtocolError>(),
+        Some(&ProtocolError::LengthTooShort { length: 3 })
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use std::io::Read;
+    use tokio::io::ReadBuf;
+
+    // Hands out at most three bytes per read, waking itself in between, so
+    // that every field has to be reassembled across reads.
+    struct ChunkedReader(Cursor<Vec<u8>>, bool);
+
+    impl AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.1 = !self.1;
+            if self.1 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let mut chunk = [0; 3];
+            let length = buf.remaining().min(chunk.len());
+            let read = self.0.read(&mut chunk[..length])?;
+            buf.put_slice(&chunk[..read]);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn chunked(bytes: Vec<u8>) -> BufReader<ChunkedReader> {
+        BufReader::new(ChunkedReader(Cursor::new(bytes), false))
+    }
+
+    #[tokio::test]
+    async fn test_read_next_message_async() -> Result<(), Box<dyn Error>> {
+        let mut startup = Startup::new();
+        startup.add_parameter("user", "postgres");
+
+        let mut bytes = SSLRequest.encode();
+        bytes.extend(startup.encode());
+        let mut stream = chunked(bytes);
+
+        assert_eq!(
+            StartupRequest::read_next_message_async(&mut stream).await?,
+            StartupRequest::SSLRequest(SSLRequest)
+        );
+        assert_eq!(
+            StartupRequest::read_next_message_async(&mut stream).await?,
+            StartupRequest::Startup(startup)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_protocol_version() {
+        // A protocol 2.0 startup packet with no parameters.
+        let mut stream = chunked(vec![0, 0, 0, 9, 0, 2, 0, 0, 0]);
+
+        let err = StartupRequest::read_next_message_async(&mut stream)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::UnsupportedProtocolVersion { major: 2, minor: 0 })
+        );
+
+        let packet = [0, 0, 0, 9, 0, 2, 0, 0, 0];
+        let err = Startup::read_next_message(&mut &packet[..]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::UnsupportedProtocolVersion { major: 2, minor: 0 })
+        );
+    }
+
+    #[test]
+    fn test_oversized_startup_rejected() {
+        // A protocol 3.0 startup packet claiming to be 1GB long.
+        let packet = [0x40, 0, 0, 0, 0, 3, 0, 0];
+        let too_long = ProtocolError::MessageTooLong {
+            length: 0x40000000,
+            max: MAX_STARTUP_LENGTH,
+        };
+
+        let err = StartupRequest::read_next_message(&mut &packet[..]).unwrap_err();
+        assert_eq!(err.downcast_ref::<ProtocolError>(), Some(&too_long));
 
-        buffer
+        let err = Startup::read_next_message(&mut &packet[..]).unwrap_err();
+        assert_eq!(err.downcast_ref::<ProtocolError>(), Some(&too_long));
     }
 }
@@ -0,0 +1,42 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+// A scripted server on a loopback socket. It writes `responses` as soon as
+// the client connects and closes its side, then records everything the
+// client sends until the client hangs up.
+pub(crate) struct MockServer {
+    handle: JoinHandle<Vec<u8>>,
+}
+
+impl MockServer {
+    pub(crate) fn start(responses: Vec<u8>) -> (Self, TcpStream) {
+        let (server, address) = Self::listen(responses);
+        let stream = TcpStream::connect(address).unwrap();
+        (server, stream)
+    }
+
+    // Like `start`, but leaves connecting to the caller.
+    pub(crate) fn listen(responses: Vec<u8>) -> (Self, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&responses).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        (Self { handle }, address)
+    }
+
+    pub(crate) fn received(self) -> Vec<u8> {
+        self.handle.join().unwrap()
+    }
+}
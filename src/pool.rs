@@ -0,0 +1,249 @@
+use std::{
+    error::Error,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{Client, Config};
+
+type Connect = Box<dyn Fn() -> Result<Client, Box<dyn Error>> + Send + Sync>;
+
+/// Connections to reuse, handed out by `get` and kept again when the
+/// [`PooledClient`] is dropped.
+///
+/// An idle connection is reset with [`Client::reset`] as it is checked out,
+/// so that no prepared statements, temporary tables or settings carry over
+/// from its last user. A connection that fails to reset is closed and the
+/// next one tried, and one the server has closed, as after a FATAL error,
+/// is not kept at all.
+pub struct Pool {
+    connect: Connect,
+    idle: Mutex<Vec<Client>>,
+    reset: bool,
+}
+
+impl Pool {
+    /// A pool whose connections come from `config.connect()`.
+    pub fn new(config: Config) -> Self {
+        Self::with_connect(move || config.connect())
+    }
+
+    /// A pool whose connections come from `connect`.
+    pub fn with_connect(
+        connect: impl Fn() -> Result<Client, Box<dyn Error>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            connect: Box::new(connect),
+            idle: Mutex::default(),
+            reset: true,
+        }
+    }
+
+    /// Whether to reset idle connections on checkout, which is the default.
+    pub fn reset(mut self, reset: bool) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    /// An idle connection if there is one, or else a new one.
+    pub fn get(&self) -> Result<PooledClient<'_>, Box<dyn Error>> {
+        loop {
+            let idle = self.idle_clients().pop();
+            let Some(mut client) = idle else {
+                return Ok(PooledClient::new(self, (self.connect)()?));
+            };
+            if client.is_closed() {
+                continue;
+            }
+            if !self.reset {
+                return Ok(PooledClient::new(self, client));
+            }
+            match client.reset() {
+                Ok(()) => return Ok(PooledClient::new(self, client)),
+                Err(err) => eprintln!("closing pooled connection that failed to reset: {err}"),
+            }
+        }
+    }
+
+    /// How many connections are waiting to be checked out.
+    pub fn idle(&self) -> usize {
+        self.idle_clients().len()
+    }
+
+    fn idle_clients(&self) -> MutexGuard<'_, Vec<Client>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("idle", &self.idle())
+            .field("reset", &self.reset)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A connection checked out of a [`Pool`], which goes back to it on drop.
+#[derive(Debug)]
+pub struct PooledClient<'a> {
+    pool: &'a Pool,
+    client: Option<Client>,
+}
+
+impl<'a> PooledClient<'a> {
+    fn new(pool: &'a Pool, client: Client) -> Self {
+        Self {
+            pool,
+            client: Some(client),
+        }
+    }
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("client is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("client is only taken on drop")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take().filter(|client| !client.is_closed()) {
+            self.pool.idle_clients().push(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, ErrorResponse, ReadyForQuery, Severity},
+            frontend::SimpleQuery,
+            Message,
+        },
+        mock::MockServer,
+        state::TransactionStatus,
+        Backend,
+    };
+    use std::{
+        net::TcpStream,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    fn complete(tag: &str) -> Vec<u8> {
+        let mut responses = CommandComplete::builder().tag(tag).build().encode();
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        responses
+    }
+
+    // A pool over connections to the mock servers in `servers`, one each,
+    // that counts how many it opens.
+    fn pool(servers: Vec<Vec<u8>>) -> (Pool, Vec<MockServer>, Arc<AtomicUsize>) {
+        let (servers, addresses): (Vec<_>, Vec<_>) =
+            servers.into_iter().map(MockServer::listen).unzip();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = connects.clone();
+        let pool = Pool::with_connect(move || {
+            let address = addresses[counter.fetch_add(1, Ordering::Relaxed)];
+            Ok(Client::new(Backend::new(TcpStream::connect(address)?)))
+        });
+        (pool, servers, connects)
+    }
+
+    #[test]
+    fn test_checkout_resets_idle_connection() -> Result<(), Box<dyn Error>> {
+        let (pool, mut servers, connects) = pool(vec![complete("DISCARD ALL")]);
+
+        drop(pool.get()?);
+        assert_eq!(pool.idle(), 1);
+        drop(pool.get()?);
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+        drop(pool);
+
+        let received = servers.remove(0).received();
+        assert_eq!(received, SimpleQuery::new("DISCARD ALL").encode());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_without_reset() -> Result<(), Box<dyn Error>> {
+        let (pool, mut servers, connects) = pool(vec![Vec::new()]);
+        let pool = pool.reset(false);
+
+        drop(pool.get()?);
+        drop(pool.get()?);
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+        drop(pool);
+
+        assert!(servers.remove(0).received().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_replaces_connection_that_fails_to_reset() -> Result<(), Box<dyn Error>> {
+        let mut failed =
+            ErrorResponse::new(Severity::Fatal, "57P01", "terminating connection").encode();
+        failed.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (pool, servers, connects) = pool(vec![failed, Vec::new()]);
+
+        drop(pool.get()?);
+        let client = pool.get()?;
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+        assert_eq!(pool.idle(), 0);
+        drop(client);
+        drop(pool);
+
+        let received: Vec<_> = servers.into_iter().map(MockServer::received).collect();
+        assert_eq!(received[0], SimpleQuery::new("DISCARD ALL").encode());
+        assert!(received[1].is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_closed_connection_is_not_kept() -> Result<(), Box<dyn Error>> {
+        let fatal = ErrorResponse::new(Severity::Fatal, "57P01", "terminating connection").encode();
+        let (pool, servers, connects) = pool(vec![fatal, Vec::new()]);
+
+        let mut client = pool.get()?;
+        assert!(client.query("SELECT 1").is_err());
+        assert!(client.is_closed());
+        drop(client);
+        assert_eq!(pool.idle(), 0);
+
+        drop(pool.get()?);
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+        drop(pool);
+
+        let received: Vec<_> = servers.into_iter().map(MockServer::received).collect();
+        assert!(received[1].is_empty());
+
+        Ok(())
+    }
+}
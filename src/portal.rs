@@ -0,0 +1,317 @@
+use std::error::Error;
+
+use crate::{
+    backend::ReadTimeoutGuard,
+    messages::{
+        backend::{BackendMessage, DataRow, ErrorResponse},
+        extended::{Bind, Close, Execute, Flush, Target},
+        frontend::Sync,
+    },
+    Client,
+};
+
+/// Reads a portal's rows a batch at a time, from [`Client::read_portal`].
+///
+/// Each batch is an Execute limited to `batch_size` rows followed by a
+/// Flush rather than a Sync, since outside a transaction a Sync would close
+/// the portal before its next batch. The Sync is sent once the portal
+/// completes, fails, or is dropped.
+///
+/// The Sync only closes a portal outside a transaction, so a named portal
+/// is also closed explicitly, leaving its name free to bind again.
+#[derive(Debug)]
+pub struct PortalReader<'a> {
+    client: &'a mut Client,
+    portal: String,
+    batch_size: u32,
+    finished: bool,
+    // Keeps the client's read timeout for as long as the portal is open.
+    _timeout: Option<ReadTimeoutGuard>,
+}
+
+impl Client {
+    /// Binds a portal with `bind`, to be read `batch_size` rows at a time.
+    ///
+    /// Prefer the unnamed portal, `Bind::new("", statement)`. A named portal
+    /// fails to bind while a cursor of the same name, such as one opened
+    /// with `DECLARE`, is still open in the transaction.
+    pub fn read_portal(
+        &mut self,
+        bind: Bind,
+        batch_size: u32,
+    ) -> Result<PortalReader<'_>, Box<dyn Error>> {
+        if batch_size == 0 {
+            return Err("batch_size must be at least one row".into());
+        }
+
+        let timeout = self.operation_timeout()?;
+        self.close_dropped_statements()?;
+        let portal = bind.portal.clone();
+        self.backend.send_message(bind)?;
+        Ok(PortalReader {
+            client: self,
+            portal,
+            batch_size,
+            finished: false,
+            _timeout: timeout,
+        })
+    }
+}
+
+impl PortalReader<'_> {
+    /// The next batch of at most `batch_size` rows, or None once the portal
+    /// has returned all of its rows.
+    pub fn next_batch(&mut self) -> Result<Option<Vec<DataRow>>, Box<dyn Error>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let backend = &mut self.client.backend;
+        backend.send_message(Execute::new(&self.portal, self.batch_size))?;
+        backend.send_message(Flush)?;
+
+        let mut rows = Vec::new();
+        loop {
+            match self.client.read_message()? {
+                BackendMessage::DataRow(data_row) => rows.push(data_row),
+                BackendMessage::PortalSuspended(_) => return Ok(Some(rows)),
+                BackendMessage::CommandComplete(_) | BackendMessage::EmptyQueryResponse(_) => {
+                    self.sync()?;
+                    return Ok((!rows.is_empty()).then_some(rows));
+                }
+                BackendMessage::ErrorResponse(mut error_response) => {
+                    self.sync()?;
+                    if error_response.is_duplicate_cursor() && error_response.hint.is_none() {
+                        error_response.hint = Some(format!(
+                            "close the open portal {:?}, or use a unique or unnamed portal",
+                            self.portal
+                        ));
+                    }
+                    return Err(error_response.into());
+                }
+                BackendMessage::ParameterStatus(status) => {
+                    self.client.parameters.insert(status.name, status.value);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Closes the portal and ends the implicit transaction, if there is one.
+    fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.finished = true;
+        if !self.portal.is_empty() {
+            self.client.backend.send_message(Close {
+                target: Target::Portal(self.portal.clone()),
+            })?;
+        }
+        self.client.backend.send_message(Sync)?;
+
+        let mut error: Option<ErrorResponse> = None;
+        loop {
+            match self.client.read_message()? {
+                BackendMessage::ErrorResponse(error_response) => {
+                    error.get_or_insert(error_response);
+                }
+                BackendMessage::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+
+        match error {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PortalReader<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(err) = self.sync() {
+            eprintln!("closing portal {:?}: {err}", self.portal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{CommandComplete, ReadyForQuery},
+            backend::Severity,
+            extended::{BindComplete, CloseComplete, PortalSuspended},
+            Message,
+        },
+        mock::MockServer,
+        state::{BackendKeyData, TransactionStatus},
+        Backend,
+    };
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    fn row(n: u32) -> DataRow {
+        DataRow::builder().string_field(n.to_string()).build()
+    }
+
+    #[test]
+    fn test_read_portal_in_batches() -> Result<(), Box<dyn Error>> {
+        let mut responses = BindComplete.encode();
+        for n in 1..=5 {
+            row(n).encode_into(&mut responses);
+            if n % 2 == 0 {
+                PortalSuspended.encode_into(&mut responses);
+            }
+        }
+        CommandComplete::builder()
+            .tag("SELECT 5")
+            .build()
+            .encode_into(&mut responses);
+        CloseComplete.encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode_into(&mut responses);
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut portal = client.read_portal(Bind::new("p", "s0"), 2)?;
+        assert_eq!(portal.next_batch()?, Some(vec![row(1), row(2)]));
+        assert_eq!(portal.next_batch()?, Some(vec![row(3), row(4)]));
+        assert_eq!(portal.next_batch()?, Some(vec![row(5)]));
+        assert_eq!(portal.next_batch()?, None);
+        drop(portal);
+        drop(client);
+
+        let mut expected = Bind::new("p", "s0").encode();
+        for _ in 0..3 {
+            Execute::new("p", 2).encode_into(&mut expected);
+            Flush.encode_into(&mut expected);
+        }
+        Close {
+            target: Target::Portal("p".to_string()),
+        }
+        .encode_into(&mut expected);
+        Sync.encode_into(&mut expected);
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_portal_reuses_name() -> Result<(), Box<dyn Error>> {
+        let in_transaction = ReadyForQuery {
+            transaction_status: TransactionStatus::InTransaction,
+        };
+        // The first portal completes in a transaction, so only the Close
+        // frees its name.
+        let mut responses = BindComplete.encode();
+        row(1).encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("SELECT 1")
+            .build()
+            .encode_into(&mut responses);
+        CloseComplete.encode_into(&mut responses);
+        in_transaction.encode_into(&mut responses);
+        // Then a cursor declared in SQL holds the name.
+        ErrorResponse::new(Severity::Error, "42P03", "cursor \"p\" already exists")
+            .encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::InFailedTransaction,
+        }
+        .encode_into(&mut responses);
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut portal = client.read_portal(Bind::new("p", "s0"), 10)?;
+        assert_eq!(portal.next_batch()?, Some(vec![row(1)]));
+        drop(portal);
+
+        let mut portal = client.read_portal(Bind::new("p", "s0"), 10)?;
+        let err = portal.next_batch().unwrap_err();
+        let error_response = err.downcast_ref::<ErrorResponse>().unwrap();
+        assert!(error_response.is_duplicate_cursor());
+        assert!(error_response.hint.as_ref().unwrap().contains("unnamed portal"));
+        drop(portal);
+        drop(client);
+
+        let close = Close {
+            target: Target::Portal("p".to_string()),
+        };
+        let mut expected = Vec::new();
+        for _ in 0..2 {
+            Bind::new("p", "s0").encode_into(&mut expected);
+            Execute::new("p", 10).encode_into(&mut expected);
+            Flush.encode_into(&mut expected);
+            close.encode_into(&mut expected);
+            Sync.encode_into(&mut expected);
+        }
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_portal_follows_key_rotation() -> Result<(), Box<dyn Error>> {
+        let key_data = BackendKeyData {
+            process_id: 42,
+            secret_key: 2222,
+        };
+        let mut responses = BindComplete.encode();
+        key_data.encode_into(&mut responses);
+        row(1).encode_into(&mut responses);
+        PortalSuspended.encode_into(&mut responses);
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let mut portal = client.read_portal(Bind::new("", "s0"), 1)?;
+        assert_eq!(portal.next_batch()?, Some(vec![row(1)]));
+        assert_eq!(portal.client.key_data, Some(key_data));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_portal_with_read_timeout() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        let backend = Backend::new(stream);
+        backend.set_read_timeout(Some(Duration::from_millis(50)))?;
+        // The kernel may have rounded it.
+        let connect_timeout = backend.read_timeout()?;
+        let mut client = Client::new(backend);
+
+        // The server answers only after the connect timeout has passed.
+        let answer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let mut responses = BindComplete.encode();
+            row(1).encode_into(&mut responses);
+            CommandComplete::builder()
+                .tag("SELECT 1")
+                .build()
+                .encode_into(&mut responses);
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode_into(&mut responses);
+            // Kept open until the client has finished reading.
+            server.write_all(&responses).map(|()| server)
+        });
+        client.with_read_timeout(None);
+        let mut portal = client.read_portal(Bind::new("", "s0"), 10)?;
+        assert_eq!(portal.next_batch()?, Some(vec![row(1)]));
+        drop(portal);
+        assert_eq!(client.backend.read_timeout()?, connect_timeout);
+        answer.join().unwrap()?;
+
+        Ok(())
+    }
+}
@@ -1,53 +1,48 @@
-use std::{error::Error, io::Read};
+use std::error::Error;
 use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 
-pub(crate) fn read_u8(reader: &mut impl Read) -> Result<u8, Box<dyn Error>> {
-    let mut buffer: [u8; 1] = [0; 1];
-    reader.read_exact(&mut buffer)?;
-    Ok(buffer[0])
-}
+use crate::ProtocolError;
 
-pub(crate) async fn read_u8_async(reader: &mut (impl AsyncRead + Unpin)) -> Result<u8, Box<dyn Error>> {
-    Ok(reader.read_u8().await?)
-}
+pub(crate) use rpsql_wire::{read_bytes, read_string, read_u16, read_u32, read_u8, ByteReader};
 
-pub(crate) fn read_u16(reader: &mut impl Read) -> Result<u16, Box<dyn Error>> {
-    let mut buffer: [u8; 2] = [0; 2];
-    reader.read_exact(&mut buffer)?;
-    Ok(u16::from_be_bytes(buffer))
+// Length of a tagged message's body, given the length field which counts itself.
+pub(crate) fn body_length(length: u32) -> Result<usize, ProtocolError> {
+    match length.checked_sub(4) {
+        Some(body_length) => Ok(body_length as usize),
+        None => Err(ProtocolError::LengthTooShort { length }),
+    }
 }
 
-pub(crate) fn read_u32(reader: &mut impl Read) -> Result<u32, Box<dyn Error>> {
-    let mut buffer: [u8; 4] = [0; 4];
-    reader.read_exact(&mut buffer)?;
-    Ok(u32::from_be_bytes(buffer))
+pub(crate) async fn read_u8_async(reader: &mut (impl AsyncRead + Unpin)) -> Result<u8, Box<dyn Error>> {
+    Ok(reader.read_u8().await?)
 }
 
 pub(crate) async fn read_u32_async(reader: &mut (impl AsyncRead + Unpin)) -> Result<u32, Box<dyn Error>> {
     Ok(reader.read_u32().await?)
 }
 
-pub(crate) fn read_bytes(length: usize, reader: &mut impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut buffer: Vec<u8> = vec![0; length];
-    reader.read_exact(&mut buffer)?;
-    Ok(buffer)
-}
-
+// Reads only as much as arrives, so an untrusted length cannot allocate more
+// than the peer actually sends.
 pub(crate) async fn read_bytes_async<R: AsyncRead + Unpin>(length: usize, reader: &mut BufReader<R>) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut buffer: Vec<u8> = vec![0; length];
-    reader.read_exact(&mut buffer).await?;
+    let mut buffer: Vec<u8> = Vec::new();
+    reader.take(length as u64).read_to_end(&mut buffer).await?;
+    if buffer.len() != length {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
     Ok(buffer)
 }
 
-pub(crate) fn read_string(reader: &mut impl Read) -> Result<String, Box<dyn Error>> {
-    let mut buffer: Vec<u8> = vec![];
-    loop {
-        let mut byte: [u8; 1] = [0; 1];
-        reader.read_exact(&mut byte)?;
-        if byte[0] == 0 {
-            break;
-        }
-        buffer.push(byte[0]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_bytes_async() -> Result<(), Box<dyn Error>> {
+        let mut reader = BufReader::new(&b"abcdef"[..]);
+        assert_eq!(read_bytes_async(4, &mut reader).await?, b"abcd");
+        assert!(read_bytes_async(u32::MAX as usize, &mut reader).await.is_err());
+
+        Ok(())
     }
-    Ok(String::from_utf8(buffer)?)
 }
+
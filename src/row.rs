@@ -0,0 +1,242 @@
+use core::fmt;
+use std::{any::type_name, error::Error, str::Utf8Error};
+
+use crate::{
+    messages::backend::{DataRow, RowDescription},
+    types::{Format, FromSql, Oid, TypeOid},
+    ProtocolError,
+};
+
+/// A DataRow read through its RowDescription. Both are borrowed, so values
+/// are decoded straight from the row's buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    description: &'a RowDescription,
+    data_row: &'a DataRow,
+}
+
+impl<'a> Row<'a> {
+    /// Fails if the row does not have one field per described column.
+    pub fn new(
+        description: &'a RowDescription,
+        data_row: &'a DataRow,
+    ) -> Result<Self, ProtocolError> {
+        if description.len() != data_row.len() {
+            return Err(ProtocolError::ColumnCountMismatch {
+                expected: description.len(),
+                got: data_row.len(),
+            });
+        }
+
+        Ok(Self {
+            description,
+            data_row,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.data_row.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data_row.is_empty()
+    }
+
+    pub fn raw(&self, index: usize) -> Option<&'a [u8]> {
+        self.data_row.field(index)
+    }
+
+    /// The text form of a column, whatever its type. None if the column is
+    /// out of range, NULL, or in binary format.
+    pub fn get_str(&self, index: usize) -> Option<Result<&'a str, Utf8Error>> {
+        if self.description.format_code(index)? != Format::Text.code() {
+            return None;
+        }
+        self.raw(index).map(std::str::from_utf8)
+    }
+
+    /// Like `column_index`, but a missing column is an error naming it.
+    pub fn require_column(&self, name: &str) -> Result<usize, FromRowError> {
+        self.column_index(name)
+            .ok_or_else(|| FromRowError::MissingColumn {
+                name: name.to_string(),
+            })
+    }
+
+    /// The index of the first column called `name`.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        (0..self.description.len()).find(|&index| {
+            self.description
+                .column(index)
+                .is_some_and(|c| c.name == name)
+        })
+    }
+
+    /// Decodes a column. A NULL is an error, unless `T` is an `Option`.
+    pub fn get<T: FromSql>(&self, index: usize) -> Result<T, Box<dyn Error>> {
+        match self.get_opt(index)? {
+            Some(value) => Ok(value),
+            None => T::from_null().ok_or_else(|| format!("column {index} is NULL").into()),
+        }
+    }
+
+    /// Like `get`, but a NULL comes back as None rather than an error.
+    pub fn get_opt<T: FromSql>(&self, index: usize) -> Result<Option<T>, Box<dyn Error>> {
+        let oid = self
+            .description
+            .data_type_oid(index)
+            .ok_or_else(|| format!("column {index} out of range"))?;
+        let format = Format::from_code(self.description.format_code(index).unwrap_or_default())?;
+        // An oid of zero means the type is unknown, so let T try.
+        if oid != Oid(0) && !T::accepts_format(oid.0, format) {
+            let column_type = match TypeOid::from_oid(oid) {
+                Some(type_oid) => format!("{type_oid:?}"),
+                None => format!("type oid {oid}"),
+            };
+            return Err(format!(
+                "cannot decode column {index} ({column_type}) as {}",
+                type_name::<T>()
+            )
+            .into());
+        }
+
+        self.raw(index)
+            .map(|raw| T::from_sql(format, raw))
+            .transpose()
+    }
+}
+
+/// A type that can be built from a row, usually with `#[derive(FromRow)]`.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row<'_>) -> Result<Self, Box<dyn Error>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FromRowError {
+    MissingColumn { name: String },
+}
+
+impl fmt::Display for FromRowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromRowError::MissingColumn { name } => write!(f, "no column named {name:?}"),
+        }
+    }
+}
+
+impl Error for FromRowError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::backend::ColumnMeta;
+
+    #[test]
+    fn test_row_get() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .string_field("id")
+            .string_field("name")
+            .build();
+        let data_row = DataRow::builder().string_field("7").null_field().build();
+        let row = Row::new(&description, &data_row)?;
+
+        assert_eq!(row.get::<i32>(0)?, 7);
+        assert_eq!(row.get::<String>(0)?, "7");
+        assert!(row.get::<String>(1).is_err());
+        assert!(row.get::<i32>(2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_get_opt() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .string_field("id")
+            .string_field("name")
+            .build();
+        let data_row = DataRow::builder().string_field("7").null_field().build();
+        let row = Row::new(&description, &data_row)?;
+
+        assert_eq!(row.column_index("name"), Some(1));
+        assert_eq!(row.column_index("missing"), None);
+        assert_eq!(row.get_opt::<i32>(0)?, Some(7));
+        assert_eq!(row.get_opt::<String>(1)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_get_option() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .binary_field("id", TypeOid::Int4.as_u32())
+            .binary_field("parent", TypeOid::Int4.as_u32())
+            .build();
+        let data_row = DataRow::builder()
+            .binary_field(7i32.to_be_bytes())
+            .null_field()
+            .build();
+        let row = Row::new(&description, &data_row)?;
+
+        assert_eq!(row.get::<Option<i32>>(0)?, Some(7));
+        assert_eq!(row.get::<Option<i32>>(1)?, None);
+        assert_eq!(
+            row.get::<i32>(1).unwrap_err().to_string(),
+            "column 1 is NULL"
+        );
+        assert!(row.get::<Option<String>>(0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_get_str() -> Result<(), Box<dyn Error>> {
+        // A user-defined enum, whose oid is only known to the server.
+        let mood_oid = 16_385;
+        let description = RowDescription::builder()
+            .field(ColumnMeta::new("mood", mood_oid, Format::Text))
+            .binary_field("id", TypeOid::Int4.as_u32())
+            .string_field("note")
+            .field(ColumnMeta::new("binary_mood", mood_oid, Format::Binary))
+            .build();
+        let data_row = DataRow::builder()
+            .string_field("happy")
+            .binary_field(7i32.to_be_bytes())
+            .null_field()
+            .binary_field(b"happy")
+            .build();
+        let row = Row::new(&description, &data_row)?;
+
+        assert_eq!(row.get_str(0), Some(Ok("happy")));
+        assert_eq!(row.get::<String>(0)?, "happy");
+        assert_eq!(row.get_str(1), None);
+        assert_eq!(row.get_str(2), None);
+        assert_eq!(row.get_str(3), None);
+        assert_eq!(row.get_str(4), None);
+        // A binary value of an unknown type is not known to be text.
+        assert!(row.get::<String>(3).is_err());
+        assert!(row.get::<i32>(1).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_column_count_mismatch() {
+        let description = RowDescription::builder()
+            .string_field("id")
+            .string_field("name")
+            .build();
+        let data_row = DataRow::builder()
+            .string_field("7")
+            .string_field("seven")
+            .string_field("extra")
+            .build();
+
+        assert_eq!(
+            Row::new(&description, &data_row).unwrap_err(),
+            ProtocolError::ColumnCountMismatch {
+                expected: 2,
+                got: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::error::Error;
+
+use crate::{
+    messages::backend::{DataRow, RowDescription},
+    types::{Format, FromSql, TypeOid},
+    ProtocolError,
+};
+
+/// A collected result set: its RowDescription and every DataRow.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RowSet {
+    description: RowDescription,
+    rows: Vec<DataRow>,
+}
+
+/// One column of a RowSet, decoded into a vector of its type. Types without
+/// a variant of their own come out as text, or as raw bytes if the column
+/// is in binary format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Bool(Vec<Option<bool>>),
+    Int2(Vec<Option<i16>>),
+    Int4(Vec<Option<i32>>),
+    Int8(Vec<Option<i64>>),
+    Float4(Vec<Option<f32>>),
+    Float8(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    Bytes(Vec<Option<Vec<u8>>>),
+}
+
+impl Column {
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Bool(values) => values.len(),
+            Column::Int2(values) => values.len(),
+            Column::Int4(values) => values.len(),
+            Column::Int8(values) => values.len(),
+            Column::Float4(values) => values.len(),
+            Column::Float8(values) => values.len(),
+            Column::Text(values) => values.len(),
+            Column::Bytes(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl RowSet {
+    /// Fails if any row does not have one field per described column.
+    pub fn new(description: RowDescription, rows: Vec<DataRow>) -> Result<Self, ProtocolError> {
+        if let Some(row) = rows.iter().find(|row| row.len() != description.len()) {
+            return Err(ProtocolError::ColumnCountMismatch {
+                expected: description.len(),
+                got: row.len(),
+            });
+        }
+
+        Ok(Self { description, rows })
+    }
+
+    pub fn description(&self) -> &RowDescription {
+        &self.description
+    }
+
+    pub fn data_rows(&self) -> &[DataRow] {
+        &self.rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Decodes the result set column by column, choosing each column's
+    /// decoder once rather than once per value.
+    pub fn columns(&self) -> Result<Vec<Column>, Box<dyn Error>> {
+        (0..self.description.len())
+            .map(|index| self.column(index))
+            .collect()
+    }
+
+    fn column(&self, index: usize) -> Result<Column, Box<dyn Error>> {
+        let oid = self.description.data_type_oid(index).unwrap_or_default();
+        let format = Format::from_code(self.description.format_code(index).unwrap_or_default())?;
+
+        Ok(match (TypeOid::from_oid(oid), format) {
+            (Some(TypeOid::Bool), _) => Column::Bool(self.decode(index, format)?),
+            (Some(TypeOid::Int2), _) => Column::Int2(self.decode(index, format)?),
+            (Some(TypeOid::Int4), _) => Column::Int4(self.decode(index, format)?),
+            (Some(TypeOid::Int8), _) => Column::Int8(self.decode(index, format)?),
+            (Some(TypeOid::Float4), _) => Column::Float4(self.decode(index, format)?),
+            (Some(TypeOid::Float8), _) => Column::Float8(self.decode(index, format)?),
+            (Some(TypeOid::Bytea), _) => Column::Bytes(self.decode(index, format)?),
+            (_, Format::Text) => Column::Text(self.decode(index, format)?),
+            (_, Format::Binary) => Column::Bytes(
+                self.rows
+                    .iter()
+                    .map(|row| row.field(index).map(<[u8]>::to_vec))
+                    .collect(),
+            ),
+        })
+    }
+
+    fn decode<T: FromSql>(
+        &self,
+        index: usize,
+        format: Format,
+    ) -> Result<Vec<Option<T>>, Box<dyn Error>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                row.field(index)
+                    .map(|raw| T::from_sql(format, raw))
+                    .transpose()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .binary_field("id", TypeOid::Int4.as_u32())
+            .string_field("name")
+            .build();
+        let rows = vec![
+            DataRow::builder()
+                .binary_field(1i32.to_be_bytes())
+                .string_field("one")
+                .build(),
+            DataRow::builder()
+                .binary_field(2i32.to_be_bytes())
+                .null_field()
+                .build(),
+        ];
+        let row_set = RowSet::new(description, rows)?;
+
+        assert_eq!(
+            row_set.columns()?,
+            vec![
+                Column::Int4(vec![Some(1), Some(2)]),
+                Column::Text(vec![Some("one".to_string()), None]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_count_mismatch() {
+        let description = RowDescription::builder().string_field("id").build();
+        let rows = vec![DataRow::builder().string_field("1").null_field().build()];
+
+        assert_eq!(
+            RowSet::new(description, rows).unwrap_err(),
+            ProtocolError::ColumnCountMismatch {
+                expected: 1,
+                got: 2
+            }
+        );
+    }
+}
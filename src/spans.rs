@@ -0,0 +1,295 @@
+// Spans for the `tracing` feature, named and with fields after the
+// OpenTelemetry semantic conventions for database clients. Without the
+// feature, every span is a no-op.
+//
+// The sync client enters its spans for as long as a call runs. The async
+// client must not hold an entered span across an await, so it instruments
+// its futures with the same spans instead. Those carry no server address,
+// since an AsyncBackend may sit on any stream.
+//
+// The connect span records the host as given, and later spans the IP
+// address that host resolved to, both as `server.address`.
+
+pub(crate) use imp::{query_async, record_query, startup_async, Span};
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use std::future::Future;
+    use tracing::{
+        field::Empty, info_span, instrument::Instrumented, span::EnteredSpan, Instrument,
+    };
+
+    use crate::Backend;
+
+    pub(crate) struct Span {
+        _span: EnteredSpan,
+    }
+
+    impl Span {
+        pub(crate) fn connect(host: &str, port: u16) -> Self {
+            Self {
+                _span: info_span!(
+                    "connect",
+                    db.system = "postgresql",
+                    server.address = host,
+                    server.port = port,
+                )
+                .entered(),
+            }
+        }
+
+        pub(crate) fn startup(
+            backend: &Backend,
+            user: Option<&str>,
+            database: Option<&str>,
+        ) -> Self {
+            Self {
+                _span: info_span!(
+                    "startup",
+                    db.system = "postgresql",
+                    db.user = user,
+                    db.name = database,
+                    server.address = server_address(backend),
+                )
+                .entered(),
+            }
+        }
+
+        pub(crate) fn query(backend: &Backend, statement: &str) -> Self {
+            Self {
+                _span: info_span!(
+                    "query",
+                    db.system = "postgresql",
+                    db.statement = statement,
+                    server.address = server_address(backend),
+                    db.rows = Empty,
+                    db.sqlstate = Empty,
+                )
+                .entered(),
+            }
+        }
+    }
+
+    pub(crate) fn startup_async<F: Future>(
+        user: Option<&str>,
+        database: Option<&str>,
+        future: F,
+    ) -> Instrumented<F> {
+        future.instrument(info_span!(
+            "startup",
+            db.system = "postgresql",
+            db.user = user,
+            db.name = database,
+        ))
+    }
+
+    pub(crate) fn query_async<F: Future>(statement: &str, future: F) -> Instrumented<F> {
+        future.instrument(info_span!(
+            "query",
+            db.system = "postgresql",
+            db.statement = statement,
+            db.rows = Empty,
+            db.sqlstate = Empty,
+        ))
+    }
+
+    /// Records how a query ended on the query span it ran in.
+    pub(crate) fn record_query(rows: u64, sqlstate: Option<&str>) {
+        let span = tracing::Span::current();
+        span.record("db.rows", rows);
+        if let Some(sqlstate) = sqlstate {
+            span.record("db.sqlstate", sqlstate);
+        }
+    }
+
+    fn server_address(backend: &Backend) -> Option<String> {
+        let address = backend.peer_addr().ok()?;
+        Some(address.ip().to_string())
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use std::future::Future;
+
+    use crate::Backend;
+
+    pub(crate) struct Span;
+
+    impl Span {
+        pub(crate) fn connect(_host: &str, _port: u16) -> Self {
+            Self
+        }
+
+        pub(crate) fn startup(
+            _backend: &Backend,
+            _user: Option<&str>,
+            _database: Option<&str>,
+        ) -> Self {
+            Self
+        }
+
+        pub(crate) fn query(_backend: &Backend, _statement: &str) -> Self {
+            Self
+        }
+    }
+
+    pub(crate) fn startup_async<F: Future>(
+        _user: Option<&str>,
+        _database: Option<&str>,
+        future: F,
+    ) -> F {
+        future
+    }
+
+    pub(crate) fn query_async<F: Future>(_statement: &str, future: F) -> F {
+        future
+    }
+
+    pub(crate) fn record_query(_rows: u64, _sqlstate: Option<&str>) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{
+        messages::{
+            backend::{CommandComplete, DataRow, ReadyForQuery},
+            Message,
+        },
+        mock::MockServer,
+        state::TransactionStatus,
+        AsyncBackend, AsyncClient, Backend, Client,
+    };
+    use std::{
+        collections::HashMap,
+        error::Error,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+    use tokio::io::AsyncWriteExt;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    #[derive(Debug, Default)]
+    struct RecordedSpan {
+        name: &'static str,
+        fields: HashMap<&'static str, String>,
+    }
+
+    impl Visit for RecordedSpan {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.fields.insert(field.name(), format!("{value:?}"));
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<HashMap<Id, RecordedSpan>>>,
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+        fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, _context: Context<'_, S>) {
+            let mut span = RecordedSpan {
+                name: attributes.metadata().name(),
+                ..RecordedSpan::default()
+            };
+            attributes.record(&mut span);
+            self.spans.lock().unwrap().insert(id.clone(), span);
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _context: Context<'_, S>) {
+            if let Some(span) = self.spans.lock().unwrap().get_mut(id) {
+                values.record(span);
+            }
+        }
+    }
+
+    // The response to `SELECT 1`.
+    fn responses() -> Vec<u8> {
+        let mut responses = DataRow::builder().string_field("1").build().encode();
+        responses.extend(CommandComplete::builder().tag("SELECT 1").build().encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        responses
+    }
+
+    #[test]
+    fn test_query_span() -> Result<(), Box<dyn Error>> {
+        let (_server, stream) = MockServer::start(responses());
+        let mut client = Client::new(Backend::new(stream));
+
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || client.query("SELECT 1"))?;
+
+        let spans = recorder.spans.lock().unwrap();
+        let span = spans
+            .values()
+            .find(|span| span.name == "query")
+            .ok_or("no query span")?;
+        assert_eq!(span.fields["db.system"], "postgresql");
+        assert_eq!(span.fields["db.statement"], "SELECT 1");
+        assert_eq!(span.fields["server.address"], "127.0.0.1");
+        assert_eq!(span.fields["db.rows"], "1");
+        assert!(!span.fields.contains_key("db.sqlstate"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_span() -> Result<(), Box<dyn Error>> {
+        let (_server, address) = MockServer::listen(Vec::new());
+
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            Backend::connect("127.0.0.1", address.port())
+        })?;
+
+        let spans = recorder.spans.lock().unwrap();
+        let span = spans
+            .values()
+            .find(|span| span.name == "connect")
+            .ok_or("no connect span")?;
+        assert_eq!(span.fields["db.system"], "postgresql");
+        assert_eq!(span.fields["server.address"], "127.0.0.1");
+        assert_eq!(span.fields["server.port"], address.port().to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_query_span() -> Result<(), Box<dyn Error>> {
+        let (client_stream, mut server_stream) = tokio::io::duplex(4096);
+        server_stream.write_all(&responses()).await?;
+        let mut client = AsyncClient::new(AsyncBackend::new(client_stream));
+
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        let _default = tracing::subscriber::set_default(subscriber);
+        client.query("SELECT 1").await?;
+
+        let spans = recorder.spans.lock().unwrap();
+        let span = spans
+            .values()
+            .find(|span| span.name == "query")
+            .ok_or("no query span")?;
+        assert_eq!(span.fields["db.statement"], "SELECT 1");
+        assert_eq!(span.fields["db.rows"], "1");
+        assert!(!span.fields.contains_key("server.address"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+/// Splits a SimpleQuery string into its statements, the way the server does.
+/// Semicolons inside string literals, quoted identifiers, dollar-quoted
+/// bodies and comments do not end a statement. The statements are trimmed,
+/// and empty ones are left out.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        i = match bytes[i] {
+            b';' => {
+                push_statement(&mut statements, &sql[start..i]);
+                start = i + 1;
+                i + 1
+            }
+            b'\'' => {
+                let escapes = i > 0
+                    && matches!(bytes[i - 1], b'E' | b'e')
+                    && (i < 2 || !is_identifier_byte(bytes[i - 2]));
+                skip_quoted(bytes, i, b'\'', escapes)
+            }
+            b'"' => skip_quoted(bytes, i, b'"', false),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => match sql[i..].find('\n') {
+                Some(end) => i + end + 1,
+                None => bytes.len(),
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'*') => skip_block_comment(bytes, i),
+            b'$' if i == 0 || !is_identifier_byte(bytes[i - 1]) => match dollar_tag(&sql[i..]) {
+                Some(tag) => {
+                    let body = i + tag.len();
+                    match sql[body..].find(tag) {
+                        Some(end) => body + end + tag.len(),
+                        None => bytes.len(),
+                    }
+                }
+                None => i + 1,
+            },
+            _ => i + 1,
+        };
+    }
+
+    push_statement(&mut statements, &sql[start..]);
+    statements
+}
+
+fn push_statement<'a>(statements: &mut Vec<&'a str>, statement: &'a str) {
+    let statement = statement.trim();
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || !byte.is_ascii()
+}
+
+// Returns the index just past the closing quote of the literal opening at
+// `start`. A doubled quote stands for itself, as does an escaped one in an
+// E'' string.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8, escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 2,
+            byte if byte == quote => {
+                if bytes.get(i + 1) == Some(&quote) {
+                    i += 2;
+                } else {
+                    return i + 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+// Block comments nest.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match &bytes[i..i + 2] {
+            b"/*" => {
+                depth += 1;
+                i += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+// The `$tag$` opening a dollar-quoted string at the start of `sql`, if it
+// is one. Tags follow identifier rules, so `$1` is a parameter instead.
+fn dollar_tag(sql: &str) -> Option<&str> {
+    let end = sql[1..].find('$')? + 1;
+    let tag = &sql[1..end];
+    let valid = match tag.as_bytes().first() {
+        None => true,
+        Some(first) => {
+            !first.is_ascii_digit()
+                && tag
+                    .bytes()
+                    .all(|byte| is_identifier_byte(byte) && byte != b'$')
+        }
+    };
+    valid.then(|| &sql[..=end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("SELECT 1; SELECT 2;;\n"),
+            vec!["SELECT 1", "SELECT 2"]
+        );
+        assert_eq!(split_statements(" ; "), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_semicolon_in_string() {
+        assert_eq!(
+            split_statements("INSERT INTO t VALUES ('a;''b'); SELECT E'\\';'; SELECT \"x;y\""),
+            vec![
+                "INSERT INTO t VALUES ('a;''b')",
+                "SELECT E'\\';'",
+                "SELECT \"x;y\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_semicolon_in_dollar_quotes() {
+        let sql = "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN; RETURN $1; END $body$ \
+                   LANGUAGE plpgsql; SELECT $$;$$";
+        assert_eq!(
+            split_statements(sql),
+            vec![
+                "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN; RETURN $1; END $body$ \
+                 LANGUAGE plpgsql",
+                "SELECT $$;$$"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_semicolon_in_comments() {
+        assert_eq!(
+            split_statements("SELECT 1 -- one; two\n; /* a /* ; */ ; */ SELECT 2"),
+            vec!["SELECT 1 -- one; two", "/* a /* ; */ ; */ SELECT 2"]
+        );
+    }
+}
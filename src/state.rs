@@ -1,43 +1,161 @@
 use crate::{messages::Message, readers::*};
 use core::fmt;
-use std::{error::Error, io::Read, str};
+use std::error::Error;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum Authentication {
     #[default]
     Ok,
+    CleartextPassword,
+    MD5Password {
+        salt: [u8; 4],
+    },
+    SASL {
+        mechanisms: Vec<String>,
+    },
+    SASLContinue {
+        data: Vec<u8>,
+    },
+    SASLFinal {
+        data: Vec<u8>,
+    },
 }
 
 impl Authentication {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let authentication_type = read_u32(stream)?;
 
         match authentication_type {
             0 => Ok(Authentication::Ok),
+            3 => Ok(Authentication::CleartextPassword),
+            5 => {
+                let mut salt = [0; 4];
+                stream.read_exact_bytes(&mut salt)?;
+                Ok(Authentication::MD5Password { salt })
+            }
+            10 => {
+                let mut mechanisms = vec![];
+                loop {
+                    let mechanism = read_string(stream)?;
+                    if mechanism.is_empty() {
+                        break;
+                    }
+                    mechanisms.push(mechanism);
+                }
+                Ok(Authentication::SASL { mechanisms })
+            }
+            11 => Ok(Authentication::SASLContinue {
+                data: stream.read_remaining()?,
+            }),
+            12 => Ok(Authentication::SASLFinal {
+                data: stream.read_remaining()?,
+            }),
             _ => Err(format!("Unsupported authentication type: {}", authentication_type).into()),
         }
     }
 }
 
-impl Message for Authentication {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        buffer.push(b'R');
-
+impl Authentication {
+    /// A human name for the method the server is asking for, for messages
+    /// and diagnostics.
+    pub fn describe(&self) -> &'static str {
         match self {
-            Authentication::Ok => {
-                let length: u32 = 8;
-                let r#type: u32 = 0;
+            Authentication::Ok => "trust (ok)",
+            Authentication::CleartextPassword => "cleartext password",
+            Authentication::MD5Password { .. } => "MD5 password",
+            Authentication::SASL { mechanisms }
+                if mechanisms.iter().any(|mechanism| mechanism == "SCRAM-SHA-256") =>
+            {
+                "SCRAM-SHA-256"
+            }
+            Authentication::SASL { .. } => "SASL",
+            Authentication::SASLContinue { .. } => "SASL continue",
+            Authentication::SASLFinal { .. } => "SASL final",
+        }
+    }
+}
 
-                buffer.extend_from_slice(&length.to_be_bytes());
-                buffer.extend_from_slice(&r#type.to_be_bytes());
+impl Message for Authentication {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        let (r#type, payload): (u32, Vec<u8>) = match self {
+            Authentication::Ok => (0, vec![]),
+            Authentication::CleartextPassword => (3, vec![]),
+            Authentication::MD5Password { salt } => (5, salt.to_vec()),
+            Authentication::SASL { mechanisms } => {
+                let mut payload = vec![];
+                for mechanism in mechanisms {
+                    payload.extend_from_slice(mechanism.as_bytes());
+                    payload.push(0);
+                }
+                payload.push(0);
+                (10, payload)
             }
+            Authentication::SASLContinue { data } => (11, data.clone()),
+            Authentication::SASLFinal { data } => (12, data.clone()),
         };
 
-        buffer
+        buffer.push(b'R');
+
+        // 4 bytes for length
+        // 4 bytes for authentication type
+        let length: u32 = 4 + 4 + payload.len() as u32;
+        buffer.extend_from_slice(&length.to_be_bytes());
+        buffer.extend_from_slice(&r#type.to_be_bytes());
+        buffer.extend_from_slice(&payload);
     }
 }
 
+#[test]
+fn test_backend_key_data_debug_redacts_secret() {
+    let key_data = BackendKeyData {
+        process_id: 4321,
+        secret_key: 98_765_432,
+    };
+    let debug = format!("{key_data:?}");
+    assert!(debug.contains("4321"));
+    assert!(!debug.contains("98765432"));
+}
+
+#[test]
+fn test_negotiate_protocol_version() {
+    crate::test_support::assert_roundtrip(NegotiateProtocolVersion {
+        newest_minor_version: 0,
+        unrecognized_options: vec!["_pq_.a".to_string(), "_pq_.b".to_string()],
+    });
+}
+
+#[test]
+fn test_authentication_describe() {
+    let sasl = |mechanism: &str| Authentication::SASL {
+        mechanisms: vec![mechanism.to_string()],
+    };
+    let described: Vec<_> = [
+        Authentication::Ok,
+        Authentication::CleartextPassword,
+        Authentication::MD5Password { salt: [0; 4] },
+        sasl("SCRAM-SHA-256"),
+        sasl("SCRAM-SHA-256-PLUS"),
+        Authentication::SASLContinue { data: vec![] },
+        Authentication::SASLFinal { data: vec![] },
+    ]
+    .iter()
+    .map(Authentication::describe)
+    .collect();
+
+    assert_eq!(
+        described,
+        [
+            "trust (ok)",
+            "cleartext password",
+            "MD5 password",
+            "SCRAM-SHA-256",
+            "SASL",
+            "SASL continue",
+            "SASL final",
+        ]
+    );
+}
+
 #[test]
 fn test_authentication_ok() {
     let message = Authentication::Ok;
@@ -45,14 +163,47 @@ fn test_authentication_ok() {
     assert_eq!(encoded, vec![b'R', 0, 0, 0, 8, 0, 0, 0, 0]);
 }
 
-#[derive(Debug)]
+#[test]
+fn test_authentication_md5_password() -> Result<(), Box<dyn Error>> {
+    let message = Authentication::MD5Password {
+        salt: [0x01, 0x02, 0x03, 0x04],
+    };
+    let encoded = message.encode();
+    assert_eq!(
+        encoded,
+        vec![b'R', 0, 0, 0, 12, 0, 0, 0, 5, 0x01, 0x02, 0x03, 0x04]
+    );
+
+    let mut cursor = std::io::Cursor::new(&encoded[5..]);
+    assert_eq!(Authentication::read_next_message(&mut cursor)?, message);
+
+    Ok(())
+}
+
+#[test]
+fn test_authentication_sasl() -> Result<(), Box<dyn Error>> {
+    let message = Authentication::SASL {
+        mechanisms: vec!["SCRAM-SHA-256".to_string()],
+    };
+    let encoded = message.encode();
+    let mut expected = vec![b'R', 0, 0, 0, 23, 0, 0, 0, 10];
+    expected.extend_from_slice(b"SCRAM-SHA-256\0\0");
+    assert_eq!(encoded, expected);
+
+    let mut cursor = std::io::Cursor::new(&encoded[5..]);
+    assert_eq!(Authentication::read_next_message(&mut cursor)?, message);
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParameterStatus {
     pub name: String,
     pub value: String,
 }
 
 impl ParameterStatus {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let name = read_string(stream)?;
         let value = read_string(stream)?;
 
@@ -61,8 +212,7 @@ impl ParameterStatus {
 }
 
 impl Message for ParameterStatus {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'S');
 
         let length: u32 = 4 + self.name.len() as u32 + 1 + self.value.len() as u32 + 1;
@@ -73,19 +223,28 @@ impl Message for ParameterStatus {
 
         buffer.extend_from_slice(self.value.as_bytes());
         buffer.push(0);
-
-        buffer
     }
 }
 
-#[derive(Debug, Default)]
+/// Debug masks `secret_key`, which lets anyone who sees it cancel the
+/// session's queries.
+#[derive(Default, Clone, PartialEq, Eq)]
 pub struct BackendKeyData {
     pub process_id: u32,
     pub secret_key: u32,
 }
 
+impl fmt::Debug for BackendKeyData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendKeyData")
+            .field("process_id", &self.process_id)
+            .field("secret_key", &"***")
+            .finish()
+    }
+}
+
 impl BackendKeyData {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let process_id = read_u32(stream)?;
         let secret_key = read_u32(stream)?;
 
@@ -97,8 +256,7 @@ impl BackendKeyData {
 }
 
 impl Message for BackendKeyData {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'K');
 
         let length: u32 = 12;
@@ -106,84 +264,71 @@ impl Message for BackendKeyData {
 
         buffer.extend_from_slice(&self.process_id.to_be_bytes());
         buffer.extend_from_slice(&self.secret_key.to_be_bytes());
-
-        buffer
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-pub enum TransactionStatus {
-    #[default]
-    Unknown,
-    Idle,
-    InTransaction,
-    InFailedTransaction,
-}
-
-impl TransactionStatus {
-    pub(crate) fn from_u8(value: u8) -> Self {
-        match value {
-            b'I' => TransactionStatus::Idle,
-            b'T' => TransactionStatus::InTransaction,
-            b'E' => TransactionStatus::InFailedTransaction,
-            _ => {
-                panic!(
-                    "unknown transaction status: {}",
-                    str::from_utf8(&[value]).unwrap()
-                );
-            }
-        }
-    }
+/// Sent during startup when the server does not support the requested minor
+/// protocol version, or some of the `_pq_.` options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiateProtocolVersion {
+    pub newest_minor_version: u32,
+    pub unrecognized_options: Vec<String>,
+}
 
-    pub(crate) fn to_u8(&self) -> u8 {
-        match self {
-            TransactionStatus::Idle => b'I',
-            TransactionStatus::InTransaction => b'T',
-            TransactionStatus::InFailedTransaction => b'E',
-            _ => {
-                panic!("unknown transaction status: {:?}", self);
-            }
-        }
+impl NegotiateProtocolVersion {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
+        let newest_minor_version = read_u32(stream)?;
+        let count = read_u32(stream)?;
+        let unrecognized_options = (0..count)
+            .map(|_| read_string(stream))
+            .collect::<Result<_, _>>()?;
+
+        Ok(NegotiateProtocolVersion {
+            newest_minor_version,
+            unrecognized_options,
+        })
     }
 }
 
-impl fmt::Display for TransactionStatus {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            TransactionStatus::Idle => write!(f, "Idle"),
-            TransactionStatus::InTransaction => write!(f, "In Transaction"),
-            TransactionStatus::InFailedTransaction => write!(f, "In Failed Transaction"),
-            _ => {
-                panic!("unknown transaction status: {:?}", self);
-            }
+impl Message for NegotiateProtocolVersion {
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.newest_minor_version.to_be_bytes());
+        body.extend_from_slice(&(self.unrecognized_options.len() as u32).to_be_bytes());
+        for option in &self.unrecognized_options {
+            body.extend_from_slice(option.as_bytes());
+            body.push(0);
         }
+
+        buffer.push(b'v');
+        buffer.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        buffer.extend_from_slice(&body);
     }
 }
 
-#[derive(Debug, Default)]
+pub use rpsql_wire::TransactionStatus;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct ReadyForQuery {
     pub transaction_status: TransactionStatus,
 }
 
 impl ReadyForQuery {
-    pub fn read_next_message(stream: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+    pub fn read_next_message(stream: &mut impl ByteReader) -> Result<Self, Box<dyn Error>> {
         let transaction_status = read_u8(stream)?;
-        let transaction_status = TransactionStatus::from_u8(transaction_status);
+        let transaction_status = TransactionStatus::from_u8(transaction_status)?;
 
         Ok(ReadyForQuery { transaction_status })
     }
 }
 
 impl Message for ReadyForQuery {
-    fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
         buffer.push(b'Z');
 
         let length: u32 = 5;
         buffer.extend_from_slice(&length.to_be_bytes());
 
         buffer.push(self.transaction_status.to_u8());
-
-        buffer
     }
 }
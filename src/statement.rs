@@ -0,0 +1,690 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex, Weak},
+    time::Instant,
+};
+
+use crate::{
+    messages::{
+        backend::{
+            BackendMessage, ColumnMeta, CommandComplete, DataRow, ErrorResponse, RowDescription,
+        },
+        extended::{Bind, Close, Describe, Execute, Parse, Target},
+        frontend::Sync,
+    },
+    spans::Span,
+    types::{Format, Oid},
+    Client, RowSet,
+};
+
+/// A statement prepared on the server by [`Client::prepare`].
+///
+/// Once it and all of its clones are dropped, the statement is closed on
+/// the server before the client's next query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub(crate) name: String,
+    pub(crate) query: String,
+    pub(crate) parameter_types: Vec<Oid>,
+    pub(crate) description: Option<RowDescription>,
+    pub(crate) handle: Option<Arc<StatementHandle>>,
+}
+
+// Shared by a Statement and its clones. Dropping the last of them queues a
+// Close for the server-side statement, since Drop cannot do any IO on the
+// connection itself.
+#[derive(Debug)]
+pub(crate) struct StatementHandle {
+    name: String,
+    pending_closes: Weak<Mutex<Vec<String>>>,
+}
+
+impl PartialEq for StatementHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for StatementHandle {}
+
+impl Drop for StatementHandle {
+    fn drop(&mut self) {
+        // The client may already be gone, and the statement with it.
+        if let Some(pending_closes) = self.pending_closes.upgrade() {
+            // A poisoned queue only leaks the statement until the session ends.
+            if let Ok(mut pending_closes) = pending_closes.lock() {
+                pending_closes.push(std::mem::take(&mut self.name));
+            }
+        }
+    }
+}
+
+// The cache holds its statements weakly, so that it does not keep them
+// prepared after the caller has dropped them.
+#[derive(Debug)]
+pub(crate) struct CachedStatement {
+    statement: Statement,
+    handle: Weak<StatementHandle>,
+}
+
+impl Statement {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn parameter_types(&self) -> &[Oid] {
+        &self.parameter_types
+    }
+
+    /// The columns the statement returns, or None if it returns no rows.
+    pub fn description(&self) -> Option<&RowDescription> {
+        self.description.as_ref()
+    }
+
+    /// The key for this statement, with its parameter types as the server
+    /// resolved them.
+    pub fn key(&self) -> StatementKey {
+        StatementKey::new(self.query.clone(), self.parameter_types.clone())
+    }
+}
+
+/// What makes one prepared statement different from another: its query
+/// text and parameter types. The same query with other parameter types is
+/// another statement.
+///
+/// A parameter type of 0, or a missing one, leaves the type to the server,
+/// and such a key only equals itself. Once the server has resolved the
+/// types, [`Client::prepare_typed`] also caches the statement under the key
+/// with the resolved types, so that asking for those types explicitly finds
+/// it too. The reverse does not hold: after preparing with explicit types,
+/// leaving them unspecified prepares the query again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StatementKey {
+    query: String,
+    parameter_types: Vec<Oid>,
+}
+
+impl StatementKey {
+    pub fn new(query: impl Into<String>, parameter_types: Vec<Oid>) -> Self {
+        Self {
+            query: query.into(),
+            parameter_types,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn parameter_types(&self) -> &[Oid] {
+        &self.parameter_types
+    }
+}
+
+/// The format [`Client::execute`] asks the server to send result columns in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    #[default]
+    Text,
+    Binary,
+    /// One format for each column.
+    PerColumn(Vec<Format>),
+}
+
+impl ResultFormat {
+    fn formats(&self, columns: usize) -> Result<Vec<Format>, Box<dyn Error>> {
+        match self {
+            ResultFormat::Text => Ok(vec![Format::Text; columns]),
+            ResultFormat::Binary => Ok(vec![Format::Binary; columns]),
+            ResultFormat::PerColumn(formats) if formats.len() == columns => Ok(formats.clone()),
+            ResultFormat::PerColumn(formats) => Err(format!(
+                "{} result formats given for {columns} columns",
+                formats.len()
+            )
+            .into()),
+        }
+    }
+}
+
+impl Client {
+    /// Prepares `query` as a named statement, leaving its parameter types
+    /// to the server. Statements are cached by their [`StatementKey`], so
+    /// preparing the same query again is free.
+    pub fn prepare(&mut self, query: &str) -> Result<Statement, Box<dyn Error>> {
+        self.prepare_typed(query, &[])
+    }
+
+    /// Like `prepare`, but with the types of the first parameters given.
+    pub fn prepare_typed(
+        &mut self,
+        query: &str,
+        parameter_types: &[Oid],
+    ) -> Result<Statement, Box<dyn Error>> {
+        let key = StatementKey::new(query, parameter_types.to_vec());
+        if let Some(cached) = self.statements.get(&key) {
+            if let Some(handle) = cached.handle.upgrade() {
+                return Ok(Statement {
+                    handle: Some(handle),
+                    ..cached.statement.clone()
+                });
+            }
+        }
+        let _timeout = self.operation_timeout()?;
+        self.close_dropped_statements()?;
+
+        let name = format!("s{}", self.next_statement);
+        self.next_statement += 1;
+
+        let mut parse = Parse::new(&name, query);
+        parse.parameter_types = key.parameter_types.clone();
+        self.backend.send_message(parse)?;
+        self.backend.send_message(Describe {
+            target: Target::Statement(name.clone()),
+        })?;
+        self.backend.send_message(Sync)?;
+
+        let mut statement = Statement {
+            name,
+            query: query.to_string(),
+            parameter_types: vec![],
+            description: None,
+            handle: None,
+        };
+        let mut error: Option<ErrorResponse> = None;
+        loop {
+            match self.read_message()? {
+                BackendMessage::ParameterDescription(parameter_description) => {
+                    statement.parameter_types = parameter_description.types;
+                }
+                BackendMessage::RowDescription(row_description) => {
+                    statement.description = Some(row_description);
+                }
+                BackendMessage::ErrorResponse(error_response) => {
+                    error.get_or_insert(error_response);
+                }
+                BackendMessage::ParameterStatus(status) => {
+                    self.parameters.insert(status.name, status.value);
+                }
+                BackendMessage::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+
+        if let Some(error) = error {
+            return Err(error.into());
+        }
+        Ok(self.cache_statement(key, statement))
+    }
+
+    // Adds a freshly prepared statement to the cache, under the key it was
+    // asked for and the one with its resolved types, and gives it the handle
+    // that closes it once dropped.
+    pub(crate) fn cache_statement(
+        &mut self,
+        key: StatementKey,
+        mut statement: Statement,
+    ) -> Statement {
+        let handle = Arc::new(StatementHandle {
+            name: statement.name.clone(),
+            pending_closes: Arc::downgrade(&self.pending_closes),
+        });
+        for key in [statement.key(), key] {
+            self.statements.insert(
+                key,
+                CachedStatement {
+                    statement: statement.clone(),
+                    handle: Arc::downgrade(&handle),
+                },
+            );
+        }
+        statement.handle = Some(handle);
+        statement
+    }
+
+    /// Closes the statements that have been dropped since the last query.
+    /// This is best effort: the server's errors are logged, not returned.
+    pub(crate) fn close_dropped_statements(&mut self) -> Result<(), Box<dyn Error>> {
+        let names = std::mem::take(&mut *self.pending_closes.lock().unwrap());
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        for name in names {
+            self.backend.send_message(Close {
+                target: Target::Statement(name),
+            })?;
+        }
+        self.backend.send_message(Sync)?;
+        loop {
+            match self.read_message()? {
+                BackendMessage::ErrorResponse(error_response) => {
+                    println!("error closing dropped statements: {error_response}");
+                }
+                BackendMessage::ParameterStatus(status) => {
+                    self.parameters.insert(status.name, status.value);
+                }
+                BackendMessage::ReadyForQuery(_) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs a prepared statement with text-format `parameters`, and returns
+    /// its rows in `format`.
+    pub fn execute(
+        &mut self,
+        statement: &Statement,
+        parameters: &[Option<&[u8]>],
+        format: ResultFormat,
+    ) -> Result<RowSet, Box<dyn Error>> {
+        let columns: Vec<&ColumnMeta> = match &statement.description {
+            Some(description) => (0..description.len())
+                .filter_map(|index| description.column(index))
+                .collect(),
+            None => vec![],
+        };
+        let formats = format.formats(columns.len())?;
+
+        // No codes means every column is text.
+        let result_formats = match format {
+            ResultFormat::Text => vec![],
+            _ => formats.iter().map(Format::code).collect(),
+        };
+        let result = self.run_portal(statement, parameters, result_formats)?;
+
+        // A portal's RowDescription is only sent when the portal itself is
+        // described, so usually the statement's, from prepare, stands in
+        // for it. That was described before any formats were chosen, so
+        // its columns all claim to be text.
+        let description = result.description.unwrap_or_else(|| {
+            columns
+                .into_iter()
+                .zip(formats)
+                .fold(RowDescription::builder(), |builder, (column, format)| {
+                    builder.field(ColumnMeta {
+                        format_code: format.code(),
+                        ..column.clone()
+                    })
+                })
+                .build()
+        });
+        Ok(RowSet::new(description, result.rows)?)
+    }
+
+    /// Runs a prepared statement with text-format `parameters`, and returns
+    /// the number of rows it affected. Any rows it returns, such as from
+    /// `UPDATE ... RETURNING`, are read and discarded.
+    pub fn execute_count(
+        &mut self,
+        statement: &Statement,
+        parameters: &[Option<&[u8]>],
+    ) -> Result<u64, Box<dyn Error>> {
+        let result = self.run_portal(statement, parameters, vec![])?;
+        Ok(result
+            .command_complete
+            .and_then(|command_complete| command_complete.rows_affected())
+            .unwrap_or_default())
+    }
+
+    // Binds `statement` to the unnamed portal, executes it and syncs.
+    fn run_portal(
+        &mut self,
+        statement: &Statement,
+        parameters: &[Option<&[u8]>],
+        result_formats: Vec<u16>,
+    ) -> Result<PortalResult, Box<dyn Error>> {
+        let mut bind = Bind::new("", &statement.name);
+        bind.parameters = parameters
+            .iter()
+            .map(|parameter| parameter.map(<[u8]>::to_vec))
+            .collect();
+        bind.result_formats = result_formats;
+        let _span = Span::query(&self.backend, &statement.query);
+        let _timeout = self.operation_timeout()?;
+        self.close_dropped_statements()?;
+        let start = Instant::now();
+        self.backend.send_message(bind)?;
+        self.backend.send_message(Execute::new("", 0))?;
+        self.backend.send_message(Sync)?;
+
+        let mut description: Option<RowDescription> = None;
+        let mut rows: Vec<DataRow> = Vec::new();
+        let mut command_complete: Option<CommandComplete> = None;
+        let mut error: Option<ErrorResponse> = None;
+        loop {
+            match self.read_message()? {
+                BackendMessage::RowDescription(row_description) => {
+                    description = Some(row_description);
+                }
+                BackendMessage::DataRow(data_row) => rows.push(data_row),
+                BackendMessage::CommandComplete(complete) => command_complete = Some(complete),
+                BackendMessage::NoticeMessage(notice) => self.handle_notice(&notice),
+                BackendMessage::ErrorResponse(error_response) => {
+                    error.get_or_insert(error_response);
+                }
+                BackendMessage::ParameterStatus(status) => {
+                    self.parameters.insert(status.name, status.value);
+                }
+                BackendMessage::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+        self.observe_query(
+            &statement.query,
+            start,
+            command_complete.as_ref(),
+            error.as_ref(),
+        );
+        if let Some(error) = error {
+            return Err(error.into());
+        }
+        Ok(PortalResult {
+            description,
+            rows,
+            command_complete,
+        })
+    }
+}
+
+// What the server sent back for one run of a portal.
+struct PortalResult {
+    // Only present if the server described the portal.
+    description: Option<RowDescription>,
+    rows: Vec<DataRow>,
+    command_complete: Option<CommandComplete>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{
+            backend::{NoData, ReadyForQuery},
+            extended::{BindComplete, CloseComplete, ParameterDescription, ParseComplete},
+            frontend::SimpleQuery,
+            Message,
+        },
+        mock::MockServer,
+        state::{BackendKeyData, TransactionStatus},
+        Backend, Column,
+    };
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    #[test]
+    fn test_execute_binary_results() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .field(ColumnMeta::new("id", 23, Format::Text))
+            .field(ColumnMeta::new("n", 20, Format::Text))
+            .build();
+        let mut responses = BindComplete.encode();
+        DataRow::builder()
+            .binary_field(7i32.to_be_bytes())
+            .binary_field(8i64.to_be_bytes())
+            .build()
+            .encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("SELECT 1")
+            .build()
+            .encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode_into(&mut responses);
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let statement = Statement {
+            name: "s0".to_string(),
+            query: "SELECT id, n FROM t WHERE id = $1".to_string(),
+            parameter_types: vec![Oid(23)],
+            description: Some(description),
+            handle: None,
+        };
+
+        let rows = client.execute(&statement, &[Some(b"7")], ResultFormat::Binary)?;
+        let columns = rows.columns()?;
+        assert_eq!(columns[0], Column::Int4(vec![Some(7)]));
+        assert_eq!(columns[1], Column::Int8(vec![Some(8)]));
+        drop(client);
+
+        let mut bind = Bind::new("", "s0");
+        bind.parameters = vec![Some(b"7".to_vec())];
+        bind.result_formats = vec![1, 1];
+        let mut expected = bind.encode();
+        Execute::new("", 0).encode_into(&mut expected);
+        Sync.encode_into(&mut expected);
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_without_fresh_row_description() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder()
+            .field(ColumnMeta::new("id", 23, Format::Text))
+            .build();
+        let mut responses = Vec::new();
+        for (id, fresh) in [("1", true), ("2", false)] {
+            BindComplete.encode_into(&mut responses);
+            if fresh {
+                description.encode_into(&mut responses);
+            }
+            DataRow::builder()
+                .string_field(id)
+                .build()
+                .encode_into(&mut responses);
+            CommandComplete::builder()
+                .tag("SELECT 1")
+                .build()
+                .encode_into(&mut responses);
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode_into(&mut responses);
+        }
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let statement = Statement {
+            name: "s0".to_string(),
+            query: "SELECT id FROM t WHERE id = $1".to_string(),
+            parameter_types: vec![Oid(23)],
+            description: Some(description),
+            handle: None,
+        };
+
+        for id in [1, 2] {
+            let parameter = id.to_string();
+            let rows = client.execute(
+                &statement,
+                &[Some(parameter.as_bytes())],
+                ResultFormat::Text,
+            )?;
+            assert_eq!(rows.columns()?[0], Column::Int4(vec![Some(id)]));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_count_with_returning() -> Result<(), Box<dyn Error>> {
+        let mut responses = BindComplete.encode();
+        for id in ["1", "2"] {
+            DataRow::builder()
+                .string_field(id)
+                .build()
+                .encode_into(&mut responses);
+        }
+        CommandComplete::builder()
+            .tag("UPDATE 2")
+            .build()
+            .encode_into(&mut responses);
+        ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        }
+        .encode_into(&mut responses);
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+        let statement = Statement {
+            name: "s0".to_string(),
+            query: "UPDATE t SET n = n + 1 WHERE n < $1 RETURNING id".to_string(),
+            parameter_types: vec![Oid(23)],
+            description: Some(
+                RowDescription::builder()
+                    .field(ColumnMeta::new("id", 23, Format::Text))
+                    .build(),
+            ),
+            handle: None,
+        };
+
+        assert_eq!(client.execute_count(&statement, &[Some(b"10")])?, 2);
+        drop(client);
+
+        let mut bind = Bind::new("", "s0");
+        bind.parameters = vec![Some(b"10".to_vec())];
+        let mut expected = bind.encode();
+        Execute::new("", 0).encode_into(&mut expected);
+        Sync.encode_into(&mut expected);
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropped_statement_is_closed() -> Result<(), Box<dyn Error>> {
+        let mut responses = ParseComplete.encode();
+        ParameterDescription { types: vec![] }.encode_into(&mut responses);
+        NoData.encode_into(&mut responses);
+        let ready = ReadyForQuery {
+            transaction_status: TransactionStatus::Idle,
+        };
+        ready.encode_into(&mut responses);
+        CloseComplete.encode_into(&mut responses);
+        ready.encode_into(&mut responses);
+        CommandComplete::builder()
+            .tag("SELECT 1")
+            .build()
+            .encode_into(&mut responses);
+        ready.encode_into(&mut responses);
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let statement = client.prepare("SELECT 1")?;
+        let clone = statement.clone();
+        drop(statement);
+        assert!(client.pending_closes.lock().unwrap().is_empty());
+        drop(clone);
+        client.query("SELECT 1")?;
+        drop(client);
+
+        let mut expected = Parse::new("s0", "SELECT 1").encode();
+        Describe {
+            target: Target::Statement("s0".to_string()),
+        }
+        .encode_into(&mut expected);
+        Sync.encode_into(&mut expected);
+        Close {
+            target: Target::Statement("s0".to_string()),
+        }
+        .encode_into(&mut expected);
+        Sync.encode_into(&mut expected);
+        SimpleQuery::new("SELECT 1").encode_into(&mut expected);
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    fn hash(key: &StatementKey) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_statement_key() {
+        let key = StatementKey::new("SELECT $1", vec![Oid(23)]);
+        let same = StatementKey::new("SELECT $1".to_string(), vec![Oid(23)]);
+        let other_type = StatementKey::new("SELECT $1", vec![Oid(20)]);
+        let unspecified = StatementKey::new("SELECT $1", vec![Oid(0)]);
+
+        assert_eq!(key, same);
+        assert_eq!(hash(&key), hash(&same));
+        assert_ne!(key, other_type);
+        assert_ne!(hash(&key), hash(&other_type));
+        assert_ne!(key, unspecified);
+    }
+
+    #[test]
+    fn test_prepare_is_cached() -> Result<(), Box<dyn Error>> {
+        let description = RowDescription::builder().binary_field("id", 23).build();
+        let mut responses = ParseComplete.encode();
+        responses.extend(
+            ParameterDescription {
+                types: vec![Oid(23)],
+            }
+            .encode(),
+        );
+        responses.extend(description.encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        let statement = client.prepare("SELECT $1::int4")?;
+        assert_eq!(statement.name(), "s0");
+        assert_eq!(statement.parameter_types(), [Oid(23)]);
+        assert_eq!(statement.description(), Some(&description));
+        assert_eq!(client.prepare("SELECT $1::int4")?, statement);
+        assert_eq!(
+            client.prepare_typed("SELECT $1::int4", &[Oid(23)])?,
+            statement
+        );
+        drop(client);
+
+        let mut expected = Parse::new("s0", "SELECT $1::int4").encode();
+        expected.extend(
+            Describe {
+                target: Target::Statement("s0".to_string()),
+            }
+            .encode(),
+        );
+        expected.extend(Sync.encode());
+        assert_eq!(server.received(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_follows_key_rotation() -> Result<(), Box<dyn Error>> {
+        let key_data = BackendKeyData {
+            process_id: 42,
+            secret_key: 2222,
+        };
+        let mut responses = ParseComplete.encode();
+        responses.extend(ParameterDescription { types: vec![] }.encode());
+        responses.extend(NoData.encode());
+        responses.extend(key_data.encode());
+        responses.extend(
+            ReadyForQuery {
+                transaction_status: TransactionStatus::Idle,
+            }
+            .encode(),
+        );
+        let (_server, stream) = MockServer::start(responses);
+        let mut client = Client::new(Backend::new(stream));
+
+        client.prepare("SELECT")?;
+        assert_eq!(client.key_data, Some(key_data));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::{error::Error, fmt::Debug, io::Cursor};
+
+use crate::{
+    messages::{
+        backend::{
+            self, BackendMessage, CommandComplete, DataRow, ErrorResponse, NoticeMessage,
+            NotificationResponse, RowDescription,
+        },
+        copy::{CopyData, CopyFail, CopyInResponse, CopyOutResponse},
+        extended::{Bind, Close, Describe, Execute, ParameterDescription, Parse},
+        frontend::{
+            FrontendMessage, PasswordMessage, SASLInitialResponse, SASLResponse, SimpleQuery,
+        },
+        ssl::{SSLMessage, SSLRequest, SSLResponse},
+        startup::{Startup, StartupRequest},
+        Message,
+    },
+    state::{self, Authentication, BackendKeyData, NegotiateProtocolVersion, ParameterStatus},
+};
+
+/// A message that can be decoded from its own encoding.
+pub(crate) trait ReadBack: Sized {
+    fn read_back(stream: &mut Cursor<&[u8]>) -> Result<Self, Box<dyn Error>>;
+}
+
+// Tagged messages, whose `read_next_message` starts after the tag and length.
+macro_rules! tagged {
+    ($($message:ty),* $(,)?) => {
+        $(
+            impl ReadBack for $message {
+                fn read_back(stream: &mut Cursor<&[u8]>) -> Result<Self, Box<dyn Error>> {
+                    let length = u32::from_be_bytes(stream.get_ref()[1..5].try_into()?);
+                    assert_eq!(length as usize, stream.get_ref().len() - 1, "length field");
+                    stream.set_position(5);
+                    <$message>::read_next_message(stream)
+                }
+            }
+        )*
+    };
+}
+
+// Messages whose `read_next_message` reads the whole encoding.
+macro_rules! whole {
+    ($($message:ty),* $(,)?) => {
+        $(
+            impl ReadBack for $message {
+                fn read_back(stream: &mut Cursor<&[u8]>) -> Result<Self, Box<dyn Error>> {
+                    <$message>::read_next_message(stream)
+                }
+            }
+        )*
+    };
+}
+
+tagged!(
+    Authentication,
+    BackendKeyData,
+    Bind,
+    Close,
+    CommandComplete,
+    CopyData,
+    CopyFail,
+    CopyInResponse,
+    CopyOutResponse,
+    DataRow,
+    Describe,
+    ErrorResponse,
+    Execute,
+    NegotiateProtocolVersion,
+    NoticeMessage,
+    NotificationResponse,
+    ParameterDescription,
+    ParameterStatus,
+    Parse,
+    PasswordMessage,
+    RowDescription,
+    SASLInitialResponse,
+    SASLResponse,
+    SimpleQuery,
+    backend::ReadyForQuery,
+    state::ReadyForQuery,
+);
+
+whole!(
+    BackendMessage,
+    FrontendMessage,
+    SSLMessage,
+    SSLRequest,
+    SSLResponse,
+    Startup,
+    StartupRequest,
+);
+
+/// Encodes `message`, decodes it again, and checks that nothing was lost
+/// and every byte was read.
+pub(crate) fn assert_roundtrip<M: Message + ReadBack + PartialEq + Debug>(message: M) {
+    let encoded = message.encode();
+    let mut stream = Cursor::new(encoded.as_slice());
+    let decoded = M::read_back(&mut stream)
+        .unwrap_or_else(|err| panic!("failed to read back {message:?}: {err}"));
+
+    assert_eq!(decoded, message);
+    assert_eq!(stream.position() as usize, encoded.len(), "trailing bytes");
+}
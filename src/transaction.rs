@@ -0,0 +1,59 @@
+use std::{
+    error::Error,
+    ops::{Deref, DerefMut},
+};
+
+use crate::Client;
+
+/// A transaction on a Client, begun by `Client::transaction`. Dropping it
+/// without calling `commit` or `rollback` leaves the connection inside the
+/// transaction and logs a warning.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    client: &'a mut Client,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn begin(client: &'a mut Client) -> Result<Self, Box<dyn Error>> {
+        client.simple_query("BEGIN")?;
+        Ok(Self {
+            client,
+            finished: false,
+        })
+    }
+
+    pub fn commit(mut self) -> Result<(), Box<dyn Error>> {
+        self.finished = true;
+        self.client.simple_query("COMMIT")?;
+        Ok(())
+    }
+
+    pub fn rollback(mut self) -> Result<(), Box<dyn Error>> {
+        self.finished = true;
+        self.client.simple_query("ROLLBACK")?;
+        Ok(())
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        self.client
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            eprintln!("transaction dropped without commit or rollback");
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+
+use super::{Oid, ToSql, TypeOid};
+
+// The length sent in place of a NULL element's.
+const NULL_LENGTH: i32 = -1;
+
+// Array types are not in TypeOid, so that their text form still decodes as
+// a String.
+fn array_oid(element: Oid) -> Oid {
+    let oid = match TypeOid::from_oid(element) {
+        Some(TypeOid::Bool) => 1000,
+        Some(TypeOid::Int2) => 1005,
+        Some(TypeOid::Int4) => 1007,
+        Some(TypeOid::Text) => 1009,
+        Some(TypeOid::Int8) => 1016,
+        Some(TypeOid::Float4) => 1021,
+        Some(TypeOid::Float8) => 1022,
+        _ => 0,
+    };
+    Oid(oid)
+}
+
+// A one dimensional array, indexed from 1.
+fn encode_array<T: ToSql>(elements: &[T], buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let dimensions: i32 = if elements.is_empty() { 0 } else { 1 };
+    let has_null = elements.iter().any(ToSql::is_null) as i32;
+    buffer.extend_from_slice(&dimensions.to_be_bytes());
+    buffer.extend_from_slice(&has_null.to_be_bytes());
+    buffer.extend_from_slice(&T::type_oid().0.to_be_bytes());
+    if dimensions == 1 {
+        buffer.extend_from_slice(&i32::try_from(elements.len())?.to_be_bytes());
+        buffer.extend_from_slice(&1i32.to_be_bytes());
+    }
+
+    for element in elements {
+        if element.is_null() {
+            buffer.extend_from_slice(&NULL_LENGTH.to_be_bytes());
+            continue;
+        }
+
+        let length_at = buffer.len();
+        buffer.extend_from_slice(&[0; 4]);
+        element.to_sql(buffer)?;
+        let length = i32::try_from(buffer.len() - length_at - 4)?;
+        buffer[length_at..length_at + 4].copy_from_slice(&length.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+impl<T: ToSql> ToSql for &[T] {
+    fn type_oid() -> Oid {
+        array_oid(T::type_oid())
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        encode_array(self, buffer)
+    }
+}
+
+impl<T: ToSql> ToSql for Vec<T> {
+    fn type_oid() -> Oid {
+        array_oid(T::type_oid())
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        encode_array(self, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int4_array() -> Result<(), Box<dyn Error>> {
+        let mut buffer = Vec::new();
+        vec![1i32, 2, 3].to_sql(&mut buffer)?;
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 0, 0, 1,  // dimensions
+            0, 0, 0, 0,  // no NULLs
+            0, 0, 0, 23, // int4
+            0, 0, 0, 3,  // length
+            0, 0, 0, 1,  // lower bound
+            0, 0, 0, 4, 0, 0, 0, 1,
+            0, 0, 0, 4, 0, 0, 0, 2,
+            0, 0, 0, 4, 0, 0, 0, 3,
+        ];
+        assert_eq!(buffer, expected);
+        assert_eq!(<&[i32]>::type_oid(), Oid(1007));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_with_null() -> Result<(), Box<dyn Error>> {
+        let mut buffer = Vec::new();
+        [Some(7i32), None].as_slice().to_sql(&mut buffer)?;
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 0, 0, 1,
+            0, 0, 0, 1, // has NULLs
+            0, 0, 0, 23,
+            0, 0, 0, 2,
+            0, 0, 0, 1,
+            0, 0, 0, 4, 0, 0, 0, 7,
+            0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        assert_eq!(buffer, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_array() -> Result<(), Box<dyn Error>> {
+        let mut buffer = Vec::new();
+        Vec::<i64>::new().to_sql(&mut buffer)?;
+        assert_eq!(buffer, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 20]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str,
+};
+
+use crate::types::{Format, FromSql, TypeOid};
+
+// Address families as sent by the server, which are not the platform's
+// AF_INET and AF_INET6.
+const PGSQL_AF_INET: u8 = 2;
+const PGSQL_AF_INET6: u8 = 3;
+
+/// An address with its netmask length, as stored in both `inet` and `cidr`.
+/// In a `cidr` value the bits past the prefix are always zero; an `inet`
+/// may have any host bits set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Self, Box<dyn Error>> {
+        let max = max_prefix(&address);
+        if prefix > max {
+            return Err(format!("netmask /{prefix} is too long for {address}").into());
+        }
+        Ok(Self { address, prefix })
+    }
+
+    fn from_binary(raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let [family, prefix, _is_cidr, length, address @ ..] = raw else {
+            return Err(format!("expected at least 4 bytes for inet, got {}", raw.len()).into());
+        };
+        if *length as usize != address.len() {
+            return Err(format!(
+                "inet address length {length} does not match its {} bytes",
+                address.len()
+            )
+            .into());
+        }
+
+        let address = match *family {
+            PGSQL_AF_INET => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(address)?)),
+            PGSQL_AF_INET6 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(address)?)),
+            other => return Err(format!("unknown inet address family: {other}").into()),
+        };
+        Self::new(address, *prefix)
+    }
+
+    // `192.168.1.0/24`, or a bare address for an `inet` with a full-length
+    // netmask.
+    fn from_text(text: &str) -> Result<Self, Box<dyn Error>> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address.parse::<IpAddr>()?, Some(prefix.parse()?)),
+            None => (text.parse::<IpAddr>()?, None),
+        };
+        Self::new(address, prefix.unwrap_or_else(|| max_prefix(&address)))
+    }
+}
+
+fn max_prefix(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl FromSql for Cidr {
+    fn accepts(oid: u32) -> bool {
+        oid == TypeOid::Cidr.as_u32() || oid == TypeOid::Inet.as_u32()
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        match format {
+            Format::Text => Self::from_text(str::from_utf8(raw)?),
+            Format::Binary => Self::from_binary(raw),
+        }
+    }
+}
+
+// The address of an `inet`, without its netmask.
+impl FromSql for IpAddr {
+    fn accepts(oid: u32) -> bool {
+        oid == TypeOid::Inet.as_u32()
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Ok(Cidr::from_sql(format, raw)?.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_inet() -> Result<(), Box<dyn Error>> {
+        let address = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5));
+
+        assert!(IpAddr::accepts(869));
+        assert_eq!(IpAddr::from_sql(Format::Text, b"192.168.1.5")?, address);
+        assert_eq!(
+            Cidr::from_sql(Format::Text, b"192.168.1.5/24")?,
+            Cidr::new(address, 24)?
+        );
+
+        let raw = [PGSQL_AF_INET, 24, 0, 4, 192, 168, 1, 5];
+        assert_eq!(IpAddr::from_sql(Format::Binary, &raw)?, address);
+        assert_eq!(
+            Cidr::from_sql(Format::Binary, &raw)?,
+            Cidr::new(address, 24)?
+        );
+        assert!(IpAddr::from_sql(Format::Binary, &raw[..7]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipv6_cidr() -> Result<(), Box<dyn Error>> {
+        let expected = Cidr::new("2001:db8::".parse()?, 32)?;
+
+        assert!(Cidr::accepts(650));
+        assert_eq!(Cidr::from_sql(Format::Text, b"2001:db8::/32")?, expected);
+
+        let mut raw = vec![PGSQL_AF_INET6, 32, 1, 16];
+        raw.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        raw.extend_from_slice(&[0; 12]);
+        assert_eq!(Cidr::from_sql(Format::Binary, &raw)?, expected);
+
+        assert!(Cidr::from_sql(Format::Text, b"2001:db8::/129").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+use std::{error::Error, str};
+
+use crate::types::{Format, FromSql, TypeOid};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+const DAYS_PER_MONTH: i64 = 30;
+
+// Months, days and microseconds are kept apart, as postgres does, because
+// neither a month nor a day has a fixed length.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl Interval {
+    pub fn new(months: i32, days: i32, micros: i64) -> Self {
+        Self {
+            months,
+            days,
+            micros,
+        }
+    }
+
+    /// Decodes the binary form from a server built without integer
+    /// datetimes (`integer_datetimes` reported as `off`, which only servers
+    /// before PostgreSQL 10 can be), where the time is a float8 of seconds
+    /// rather than an int8 of microseconds. [`FromSql`] assumes integer
+    /// datetimes.
+    pub fn from_float_binary(raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if raw.len() != 16 {
+            return Err(format!("expected 16 bytes for interval, got {}", raw.len()).into());
+        }
+
+        let seconds = f64::from_be_bytes(raw[0..8].try_into()?);
+        let micros = (seconds * MICROS_PER_SECOND as f64).round();
+        if !micros.is_finite() || micros.abs() >= i64::MAX as f64 {
+            return Err("interval out of range".into());
+        }
+
+        Ok(Self {
+            micros: micros as i64,
+            days: i32::from_be_bytes(raw[8..12].try_into()?),
+            months: i32::from_be_bytes(raw[12..16].try_into()?),
+        })
+    }
+
+    fn from_binary(raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if raw.len() != 16 {
+            return Err(format!("expected 16 bytes for interval, got {}", raw.len()).into());
+        }
+
+        Ok(Self {
+            micros: i64::from_be_bytes(raw[0..8].try_into()?),
+            days: i32::from_be_bytes(raw[8..12].try_into()?),
+            months: i32::from_be_bytes(raw[12..16].try_into()?),
+        })
+    }
+
+    // Accepts every IntervalStyle the server can send: postgres
+    // (`1 year 2 mons 3 days 04:05:06`), postgres_verbose
+    // (`@ 1 year 2 mons ago`), sql_standard (`1-2 3 4:05:06`) and iso_8601
+    // (`P1Y2M3DT4H5M6S`).
+    fn from_text(text: &str) -> Result<Self, Box<dyn Error>> {
+        let text = text.trim();
+        match text {
+            "infinity" => return Ok(Self::new(i32::MAX, i32::MAX, i64::MAX)),
+            "-infinity" => return Ok(Self::new(i32::MIN, i32::MIN, i64::MIN)),
+            _ => {}
+        }
+
+        match text.strip_prefix('P') {
+            Some(iso_8601) => parse_iso_8601(iso_8601),
+            None => parse_postgres(text),
+        }
+    }
+}
+
+impl FromSql for Interval {
+    fn accepts(oid: u32) -> bool {
+        oid == TypeOid::Interval.as_u32()
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        match format {
+            Format::Text => Self::from_text(str::from_utf8(raw)?),
+            Format::Binary => Self::from_binary(raw),
+        }
+    }
+}
+
+fn parse_postgres(text: &str) -> Result<Interval, Box<dyn Error>> {
+    let text = text.strip_prefix('@').unwrap_or(text);
+
+    let mut fields = Fields::default();
+    let mut ago = false;
+    let mut tokens = text.split_whitespace().peekable();
+    while let Some(token) = tokens.next() {
+        if ago {
+            return Err(format!("unexpected {token:?} after \"ago\" in interval").into());
+        }
+        if token.eq_ignore_ascii_case("ago") {
+            ago = true;
+            continue;
+        }
+        if token.contains(':') {
+            fields.micros = checked(fields.micros.checked_add(parse_time(token)?))?;
+            continue;
+        }
+        if let Some((years, months)) = parse_year_month(token) {
+            fields.add(years, Unit::Months(12))?;
+            fields.add(months, Unit::Months(1))?;
+            continue;
+        }
+
+        let split = token
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(token.len());
+        let (number, unit) = token.split_at(split);
+        let unit = match (unit, tokens.peek()) {
+            ("", Some(next)) if next.contains(':') => "day",
+            ("", Some(next))
+                if next.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && !next.eq_ignore_ascii_case("ago") =>
+            {
+                tokens.next().unwrap_or_default()
+            }
+            // A bare number counts seconds.
+            ("", _) => "second",
+            (unit, _) => unit,
+        };
+        fields.add(number, Unit::parse(unit)?)?;
+    }
+
+    fields.finish(ago)
+}
+
+fn parse_iso_8601(text: &str) -> Result<Interval, Box<dyn Error>> {
+    let mut fields = Fields::default();
+    let mut time = false;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        if c.is_ascii_digit() || matches!(c, '.' | '-' | '+') {
+            continue;
+        }
+
+        let number = &text[start..i];
+        start = i + c.len_utf8();
+        if c == 'T' && number.is_empty() && !time {
+            time = true;
+            continue;
+        }
+        if number.is_empty() {
+            return Err(format!("missing number before {c:?} in interval").into());
+        }
+
+        let unit = match (time, c) {
+            (false, 'Y') => Unit::Months(12),
+            (false, 'M') => Unit::Months(1),
+            (false, 'W') => Unit::Days(7),
+            (false, 'D') => Unit::Days(1),
+            (true, 'H') => Unit::Micros(3_600 * MICROS_PER_SECOND),
+            (true, 'M') => Unit::Micros(60 * MICROS_PER_SECOND),
+            (true, 'S') => Unit::Micros(MICROS_PER_SECOND),
+            _ => return Err(format!("unexpected {c:?} in interval").into()),
+        };
+        fields.add(number, unit)?;
+    }
+
+    if start != text.len() {
+        return Err("missing unit at end of interval".into());
+    }
+    fields.finish(false)
+}
+
+// `[-+]H:MM[:SS[.ffffff]]`, where the hours may exceed a day.
+fn parse_time(token: &str) -> Result<i64, Box<dyn Error>> {
+    let (negative, token) = strip_sign(token);
+
+    let mut parts = token.split(':');
+    let hours: i64 = parts.next().unwrap_or_default().parse()?;
+    let minutes: i64 = parts.next().unwrap_or_default().parse()?;
+    let seconds = parts.next().unwrap_or("0");
+    if parts.next().is_some() {
+        return Err(format!("invalid time in interval: {token:?}").into());
+    }
+
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let seconds: i64 = seconds.parse()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid time in interval: {token:?}").into());
+    }
+    // Anything past microseconds is dropped.
+    let fraction: i64 = format!("{fraction:0<6}")[..6].parse()?;
+
+    let micros = checked(
+        hours
+            .checked_mul(60)
+            .and_then(|minutes_total| minutes_total.checked_add(minutes))
+            .and_then(|minutes| minutes.checked_mul(60))
+            .and_then(|seconds_total| seconds_total.checked_add(seconds))
+            .and_then(|seconds| seconds.checked_mul(MICROS_PER_SECOND))
+            .and_then(|micros| micros.checked_add(fraction)),
+    )?;
+    Ok(if negative { -micros } else { micros })
+}
+
+// The sql_standard year-month field, `[-+]Y-M`.
+fn parse_year_month(token: &str) -> Option<(&str, &str)> {
+    let (negative, unsigned) = strip_sign(token);
+    let (years, months) = unsigned.split_once('-')?;
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(years) || !is_number(months) {
+        return None;
+    }
+
+    if negative {
+        // Keep the sign on both halves by slicing it back out of `token`.
+        let months_start = token.len() - months.len();
+        Some((&token[..months_start - 1], &token[months_start - 1..]))
+    } else {
+        Some((years, months))
+    }
+}
+
+fn strip_sign(token: &str) -> (bool, &str) {
+    match token.as_bytes().first() {
+        Some(b'-') => (true, &token[1..]),
+        Some(b'+') => (false, &token[1..]),
+        _ => (false, token),
+    }
+}
+
+fn checked(value: Option<i64>) -> Result<i64, Box<dyn Error>> {
+    value.ok_or_else(|| "interval out of range".into())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Months(i64),
+    Days(i64),
+    Micros(i64),
+}
+
+impl Unit {
+    fn parse(unit: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(match unit.to_ascii_lowercase().as_str() {
+            "millennium" | "millennia" | "millenniums" | "mil" | "mils" => Unit::Months(12_000),
+            "century" | "centuries" | "cent" | "c" => Unit::Months(1_200),
+            "decade" | "decades" | "dec" | "decs" => Unit::Months(120),
+            "year" | "years" | "yr" | "yrs" | "y" => Unit::Months(12),
+            "month" | "months" | "mon" | "mons" => Unit::Months(1),
+            "week" | "weeks" | "w" => Unit::Days(7),
+            "day" | "days" | "d" => Unit::Days(1),
+            "hour" | "hours" | "hr" | "hrs" | "h" => Unit::Micros(3_600 * MICROS_PER_SECOND),
+            "minute" | "minutes" | "min" | "mins" | "m" => Unit::Micros(60 * MICROS_PER_SECOND),
+            "second" | "seconds" | "sec" | "secs" | "s" => Unit::Micros(MICROS_PER_SECOND),
+            "millisecond" | "milliseconds" | "msec" | "msecs" | "ms" => Unit::Micros(1_000),
+            "microsecond" | "microseconds" | "usec" | "usecs" | "us" => Unit::Micros(1),
+            other => return Err(format!("unknown interval unit: {other:?}").into()),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Fields {
+    months: i64,
+    days: i64,
+    micros: i64,
+}
+
+impl Fields {
+    // Adds `number` of `unit`. Like postgres, a fraction of a month spills
+    // into days (of 30) and a fraction of a day into microseconds.
+    fn add(&mut self, number: &str, unit: Unit) -> Result<(), Box<dyn Error>> {
+        let (negative, unsigned) = strip_sign(number);
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(format!("missing number in interval: {number:?}").into());
+        }
+
+        let mut whole: i64 = if whole.is_empty() { 0 } else { whole.parse()? };
+        let mut fraction: f64 = if fraction.is_empty() {
+            0.0
+        } else {
+            format!("0.{fraction}").parse()?
+        };
+        if negative {
+            whole = -whole;
+            fraction = -fraction;
+        }
+
+        match unit {
+            Unit::Months(scale) => {
+                self.months = checked(self.months.checked_add(checked(whole.checked_mul(scale))?))?;
+
+                let months = fraction * scale as f64;
+                self.months = checked(self.months.checked_add(months.trunc() as i64))?;
+                self.add_fractional_days(months.fract() * DAYS_PER_MONTH as f64)?;
+            }
+            Unit::Days(scale) => {
+                self.days = checked(self.days.checked_add(checked(whole.checked_mul(scale))?))?;
+                self.add_fractional_days(fraction * scale as f64)?;
+            }
+            Unit::Micros(scale) => {
+                let micros = checked(whole.checked_mul(scale))?;
+                let micros = checked(micros.checked_add((fraction * scale as f64).round() as i64))?;
+                self.micros = checked(self.micros.checked_add(micros))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_fractional_days(&mut self, days: f64) -> Result<(), Box<dyn Error>> {
+        self.days = checked(self.days.checked_add(days.trunc() as i64))?;
+        let micros = (days.fract() * MICROS_PER_DAY as f64).round() as i64;
+        self.micros = checked(self.micros.checked_add(micros))?;
+        Ok(())
+    }
+
+    fn finish(self, negate: bool) -> Result<Interval, Box<dyn Error>> {
+        let (months, days, micros) = if negate {
+            (
+                checked(self.months.checked_neg())?,
+                checked(self.days.checked_neg())?,
+                checked(self.micros.checked_neg())?,
+            )
+        } else {
+            (self.months, self.days, self.micros)
+        };
+
+        Ok(Interval {
+            months: i32::try_from(months).map_err(|_| "interval out of range")?,
+            days: i32::try_from(days).map_err(|_| "interval out of range")?,
+            micros,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HMS: i64 = (4 * 3600 + 5 * 60 + 6) * MICROS_PER_SECOND;
+
+    fn text(text: &str) -> Result<Interval, Box<dyn Error>> {
+        Interval::from_sql(Format::Text, text.as_bytes())
+    }
+
+    #[test]
+    fn test_interval_binary() -> Result<(), Box<dyn Error>> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(-HMS).to_be_bytes());
+        raw.extend_from_slice(&(-3i32).to_be_bytes());
+        raw.extend_from_slice(&(-14i32).to_be_bytes());
+
+        assert!(Interval::accepts(1186));
+        assert_eq!(
+            Interval::from_sql(Format::Binary, &raw)?,
+            Interval::new(-14, -3, -HMS)
+        );
+        assert!(Interval::from_sql(Format::Binary, &raw[..12]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval_float_binary() -> Result<(), Box<dyn Error>> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(-14706.5f64).to_be_bytes());
+        raw.extend_from_slice(&(-3i32).to_be_bytes());
+        raw.extend_from_slice(&(-14i32).to_be_bytes());
+
+        assert_eq!(
+            Interval::from_float_binary(&raw)?,
+            Interval::new(-14, -3, -HMS - 500_000)
+        );
+        raw[..8].copy_from_slice(&f64::NAN.to_be_bytes());
+        assert!(Interval::from_float_binary(&raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval_text() -> Result<(), Box<dyn Error>> {
+        let expected = Interval::new(14, 3, HMS);
+        assert_eq!(text("1 year 2 mons 3 days 04:05:06")?, expected);
+        assert_eq!(
+            text("@ 1 year 2 mons 3 days 4 hours 5 mins 6 secs")?,
+            expected
+        );
+        assert_eq!(text("1-2 3 4:05:06")?, expected);
+        assert_eq!(text("P1Y2M3DT4H5M6S")?, expected);
+
+        assert_eq!(text("00:00:00")?, Interval::default());
+        assert_eq!(text("2 weeks")?, Interval::new(0, 14, 0));
+        assert_eq!(text("1.5 years")?, Interval::new(18, 0, 0));
+        assert_eq!(
+            text("1 day 100:00:00.25")?,
+            Interval::new(0, 1, 100 * 3600 * MICROS_PER_SECOND + 250_000)
+        );
+
+        assert!(text("3 fortnights").is_err());
+        assert!(text("1 year ago 2 days").is_err());
+        assert!(text("P1Y2").is_err());
+
+        // Out of range, rather than overflowing.
+        assert!(text("9223372036854775807 days 0.5 weeks").is_err());
+        assert!(text("768614336404564650 years 0.9 years").is_err());
+        assert!(text("9223372036854.775808 secs").is_err());
+        assert!(text("@ -9223372036854.775808 secs ago").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_interval_text() -> Result<(), Box<dyn Error>> {
+        let expected = Interval::new(-14, 3, -HMS - 500_000);
+        assert_eq!(text("-1 years -2 mons +3 days -04:05:06.5")?, expected);
+        assert_eq!(text("-1-2 +3 -4:05:06.5")?, expected);
+        assert_eq!(text("P-1Y-2M3DT-4H-5M-6.5S")?, expected);
+        assert_eq!(
+            text("@ 1 year 2 mons -3 days 4 hours 5 mins 6.5 secs ago")?,
+            expected
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use std::error::Error;
+
+mod array;
+mod inet;
+mod interval;
+mod oid;
+pub use inet::Cidr;
+pub use interval::Interval;
+pub use oid::{Oid, TypeOid};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl Format {
+    pub fn from_code(code: u16) -> Result<Self, Box<dyn Error>> {
+        match code {
+            0 => Ok(Format::Text),
+            1 => Ok(Format::Binary),
+            other => Err(format!("unknown format code: {other}").into()),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            Format::Text => 0,
+            Format::Binary => 1,
+        }
+    }
+}
+
+/// Decodes a non-NULL column value sent in either format.
+pub trait FromSql: Sized {
+    fn accepts(oid: u32) -> bool;
+
+    /// Like `accepts`, for a column sent in `format`. Types that can only
+    /// read some oids in one format override it.
+    fn accepts_format(oid: u32, _format: Format) -> bool {
+        Self::accepts(oid)
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>>;
+
+    /// The value a NULL decodes to, if the type has one.
+    fn from_null() -> Option<Self> {
+        None
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn accepts(oid: u32) -> bool {
+        T::accepts(oid)
+    }
+
+    fn accepts_format(oid: u32, format: Format) -> bool {
+        T::accepts_format(oid, format)
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        T::from_sql(format, raw).map(Some)
+    }
+
+    fn from_null() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// Encodes a parameter value in the binary format.
+pub trait ToSql {
+    /// The type the value is sent as, or zero to leave it to the server.
+    fn type_oid() -> Oid
+    where
+        Self: Sized;
+
+    /// Appends the value, without its length, to `buffer`.
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>>;
+
+    fn is_null(&self) -> bool {
+        false
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn type_oid() -> Oid {
+        T::type_oid()
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Some(value) => value.to_sql(buffer),
+            None => Ok(()),
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        self.is_none()
+    }
+}
+
+macro_rules! to_sql_number {
+    ($type:ty, $oid:ident) => {
+        impl ToSql for $type {
+            fn type_oid() -> Oid {
+                TypeOid::$oid.into()
+            }
+
+            fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+                buffer.extend_from_slice(&self.to_be_bytes());
+                Ok(())
+            }
+        }
+    };
+}
+
+to_sql_number!(i16, Int2);
+to_sql_number!(i32, Int4);
+to_sql_number!(i64, Int8);
+to_sql_number!(f32, Float4);
+to_sql_number!(f64, Float8);
+
+impl ToSql for bool {
+    fn type_oid() -> Oid {
+        TypeOid::Bool.into()
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        buffer.push(*self as u8);
+        Ok(())
+    }
+}
+
+impl ToSql for &str {
+    fn type_oid() -> Oid {
+        TypeOid::Text.into()
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        buffer.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+}
+
+impl ToSql for String {
+    fn type_oid() -> Oid {
+        TypeOid::Text.into()
+    }
+
+    fn to_sql(&self, buffer: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.as_str().to_sql(buffer)
+    }
+}
+
+fn text(raw: &[u8]) -> Result<&str, Box<dyn Error>> {
+    Ok(std::str::from_utf8(raw)?)
+}
+
+fn fixed<const N: usize>(raw: &[u8]) -> Result<[u8; N], Box<dyn Error>> {
+    raw.try_into()
+        .map_err(|_| format!("expected {N} bytes, got {}", raw.len()).into())
+}
+
+macro_rules! from_sql_number {
+    ($type:ty, $oid:ident) => {
+        impl FromSql for $type {
+            fn accepts(oid: u32) -> bool {
+                oid == TypeOid::$oid.as_u32()
+            }
+
+            fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+                match format {
+                    Format::Text => Ok(text(raw)?.parse()?),
+                    Format::Binary => Ok(<$type>::from_be_bytes(fixed(raw)?)),
+                }
+            }
+        }
+    };
+}
+
+from_sql_number!(i16, Int2);
+from_sql_number!(i32, Int4);
+from_sql_number!(i64, Int8);
+from_sql_number!(f32, Float4);
+from_sql_number!(f64, Float8);
+
+impl FromSql for bool {
+    fn accepts(oid: u32) -> bool {
+        oid == TypeOid::Bool.as_u32()
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        match (format, raw) {
+            (Format::Text, b"t") | (Format::Binary, [1]) => Ok(true),
+            (Format::Text, b"f") | (Format::Binary, [0]) => Ok(false),
+            _ => Err(format!("invalid bool: {raw:?}").into()),
+        }
+    }
+}
+
+impl FromSql for String {
+    fn accepts(oid: u32) -> bool {
+        matches!(
+            TypeOid::from_u32(oid),
+            Some(
+                TypeOid::Text
+                    | TypeOid::Varchar
+                    | TypeOid::Bpchar
+                    | TypeOid::Name
+                    | TypeOid::Unknown
+            )
+        )
+    }
+
+    // User-defined enums and composites have oids that are not known here,
+    // so they are read as their text form. Their binary forms are not text.
+    fn accepts_format(oid: u32, format: Format) -> bool {
+        Self::accepts(oid) || (format == Format::Text && TypeOid::from_u32(oid).is_none())
+    }
+
+    fn from_sql(_format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Ok(text(raw)?.to_string())
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn accepts(oid: u32) -> bool {
+        oid == TypeOid::Bytea.as_u32()
+    }
+
+    fn from_sql(format: Format, raw: &[u8]) -> Result<Self, Box<dyn Error>> {
+        match format {
+            Format::Binary => Ok(raw.to_vec()),
+            // bytea_output = hex
+            Format::Text => {
+                let hex = text(raw)?
+                    .strip_prefix("\\x")
+                    .ok_or("expected hex encoded bytea")?;
+                if hex.len() % 2 != 0 || !hex.is_ascii() {
+                    return Err("invalid hex encoded bytea".into());
+                }
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[test]
+fn test_from_sql_primitives() -> Result<(), Box<dyn Error>> {
+    assert_eq!(i32::from_sql(Format::Text, b"-42")?, -42);
+    assert_eq!(i32::from_sql(Format::Binary, &(-42i32).to_be_bytes())?, -42);
+    assert!(i32::from_sql(Format::Binary, &[0, 1]).is_err());
+    assert_eq!(f64::from_sql(Format::Text, b"1.5")?, 1.5);
+    assert!(bool::from_sql(Format::Text, b"t")?);
+    assert!(!bool::from_sql(Format::Binary, &[0])?);
+    assert_eq!(String::from_sql(Format::Text, b"hello")?, "hello");
+    assert_eq!(
+        Vec::<u8>::from_sql(Format::Text, b"\\xdead")?,
+        vec![0xde, 0xad]
+    );
+
+    Ok(())
+}
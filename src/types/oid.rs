@@ -0,0 +1,100 @@
+use std::fmt;
+
+/// An object identifier, as the server sends them for types and tables.
+/// Zero means there is none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Oid(pub u32);
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u32> for Oid {
+    fn from(oid: u32) -> Self {
+        Self(oid)
+    }
+}
+
+impl From<Oid> for u32 {
+    fn from(oid: Oid) -> Self {
+        oid.0
+    }
+}
+
+impl From<TypeOid> for Oid {
+    fn from(type_oid: TypeOid) -> Self {
+        Self(type_oid.as_u32())
+    }
+}
+
+macro_rules! type_oids {
+    ($($name:ident = $oid:literal,)+) => {
+        /// The OIDs of built-in types, which are the same on every server.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u32)]
+        pub enum TypeOid {
+            $($name = $oid,)+
+        }
+
+        impl TypeOid {
+            pub fn from_u32(oid: u32) -> Option<Self> {
+                match oid {
+                    $($oid => Some(TypeOid::$name),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+type_oids! {
+    Bool = 16,
+    Bytea = 17,
+    Char = 18,
+    Name = 19,
+    Int8 = 20,
+    Int2 = 21,
+    Int4 = 23,
+    Text = 25,
+    Oid = 26,
+    Json = 114,
+    Xml = 142,
+    Cidr = 650,
+    Float4 = 700,
+    Float8 = 701,
+    Unknown = 705,
+    Inet = 869,
+    Bpchar = 1042,
+    Varchar = 1043,
+    Date = 1082,
+    Time = 1083,
+    Timestamp = 1114,
+    Timestamptz = 1184,
+    Interval = 1186,
+    Numeric = 1700,
+    Uuid = 2950,
+    Jsonb = 3802,
+}
+
+impl TypeOid {
+    pub const fn as_u32(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_oid(oid: Oid) -> Option<Self> {
+        Self::from_u32(oid.0)
+    }
+}
+
+#[test]
+fn test_type_oid() {
+    assert_eq!(TypeOid::from_u32(23), Some(TypeOid::Int4));
+    assert_eq!(TypeOid::Int4.as_u32(), 23);
+    assert_eq!(TypeOid::from_u32(3802), Some(TypeOid::Jsonb));
+    assert_eq!(TypeOid::from_u32(0), None);
+    assert_eq!(TypeOid::from_oid(Oid(25)), Some(TypeOid::Text));
+    assert_eq!(Oid::from(TypeOid::Int4), Oid(23));
+    assert_eq!(Oid(16_385).to_string(), "16385");
+}
@@ -0,0 +1,237 @@
+use std::{
+    error::Error,
+    io::{BufRead, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rpsql::{
+    auth,
+    messages::{
+        backend::{BackendMessage, CommandKind},
+        frontend::{PasswordMessage, SimpleQuery},
+        startup::{Startup, StartupResponse},
+    },
+    state::Authentication,
+    types::Oid,
+    AsyncClient, Backend, Config,
+};
+
+// The example server binary, listening on an ephemeral loopback port.
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    fn spawn(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--listen", "127.0.0.1:0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("spawn server");
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).expect("server address");
+        let address = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .expect("server address")
+            .to_string();
+
+        // Keep draining the server's logging so it never blocks on a full pipe.
+        thread::spawn(move || stdout.lines().for_each(drop));
+
+        Self { child, address }
+    }
+
+    fn connect(&self) -> Result<Backend, Box<dyn Error>> {
+        Ok(Backend::new(TcpStream::connect(&self.address)?))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn startup(
+    server: &Server,
+    user: &str,
+    password: &str,
+) -> Result<Vec<StartupResponse>, Box<dyn Error>> {
+    let mut backend = server.connect()?;
+
+    let mut startup = Startup::new();
+    startup.add_parameter("user", user);
+    backend.send_message(startup)?;
+
+    // The password has to be sent mid-startup, so each response is read
+    // through its own short-lived iterator.
+    let mut responses = vec![];
+    loop {
+        let Some(response) = backend.read_startup_messages()?.next() else {
+            break;
+        };
+        if let StartupResponse::Authentication(Authentication::MD5Password { salt }) = response {
+            backend.send_message(PasswordMessage::md5(user, password, salt))?;
+        }
+        let ready = matches!(response, StartupResponse::ReadyForQuery(_));
+        responses.push(response);
+        if ready {
+            break;
+        }
+    }
+    Ok(responses)
+}
+
+fn error_code(responses: &[StartupResponse]) -> Option<&str> {
+    responses.iter().find_map(|response| match response {
+        StartupResponse::ErrorResponse(error_response) => Some(error_response.code.as_str()),
+        _ => None,
+    })
+}
+
+#[test]
+fn test_md5_authentication() -> Result<(), Box<dyn Error>> {
+    let password_hash = auth::md5_hash("postgres", "secret");
+    let server = Server::spawn(&["--require-password", "--password-hash", &password_hash]);
+
+    let responses = startup(&server, "postgres", "secret")?;
+    assert!(matches!(
+        responses.last(),
+        Some(StartupResponse::ReadyForQuery(_))
+    ));
+    assert_eq!(error_code(&responses), None);
+
+    let responses = startup(&server, "postgres", "wrong")?;
+    assert_eq!(error_code(&responses), Some("28P01"));
+
+    let responses = startup(&server, "nobody", "secret")?;
+    assert_eq!(error_code(&responses), Some("28000"));
+
+    Ok(())
+}
+
+#[test]
+fn test_command_complete_tags() -> Result<(), Box<dyn Error>> {
+    let server = Server::spawn(&[]);
+    let mut backend = server.connect()?;
+
+    let mut startup = Startup::new();
+    startup.add_parameter("user", "postgres");
+    backend.send_message(startup)?;
+    assert_eq!(backend.read_startup_messages()?.count(), 3);
+
+    let mut command_kind = |query: &str| -> Result<Option<CommandKind>, Box<dyn Error>> {
+        let messages = backend.send_and_collect(SimpleQuery::new_trusted(query))?;
+        Ok(messages.into_iter().find_map(|message| match message {
+            BackendMessage::CommandComplete(command_complete) => Some(command_complete.kind()),
+            _ => None,
+        }))
+    };
+
+    assert_eq!(command_kind("SELECT 1")?, Some(CommandKind::Select(1)));
+    assert_eq!(
+        command_kind("INSERT INTO greetings VALUES ('hi')")?,
+        Some(CommandKind::Insert {
+            oid: Oid(0),
+            rows: 1
+        })
+    );
+    assert_eq!(
+        command_kind("BEGIN;")?,
+        Some(CommandKind::Other("BEGIN".to_string()))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_config_fails_over_to_next_host() -> Result<(), Box<dyn Error>> {
+    // Nothing listens on a port once its listener is dropped.
+    let unreachable = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server = Server::spawn(&[]);
+    let address: SocketAddr = server.address.parse()?;
+
+    let mut client = Config::new()
+        .add_host(unreachable.ip().to_string(), unreachable.port())
+        .add_host(address.ip().to_string(), address.port())
+        .user("postgres")
+        .connect()?;
+
+    assert_eq!(client.query("SELECT 1")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_cancel_request() -> Result<(), Box<dyn Error>> {
+    let server = Server::spawn(&[]);
+    let address: SocketAddr = server.address.parse()?;
+    let mut client = Config::new()
+        .add_host(address.ip().to_string(), address.port())
+        .user("postgres")
+        .connect()?;
+
+    let cancel_token = client.cancel_token()?;
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        cancel_token.cancel().map_err(|err| err.to_string())
+    });
+
+    let start = Instant::now();
+    let err = client.query("SELECT pg_sleep(30)").unwrap_err();
+    canceller.join().unwrap()?;
+    assert!(err.to_string().contains("canceling statement"), "{err}");
+    assert!(start.elapsed() < Duration::from_secs(30));
+
+    // The session carries on after the cancelled query.
+    assert_eq!(client.query("SELECT 1")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_query() -> Result<(), Box<dyn Error>> {
+    let server = Server::spawn(&[]);
+    let address: SocketAddr = server.address.parse()?;
+    let mut client = Config::new()
+        .add_host(address.ip().to_string(), address.port())
+        .user("postgres")
+        .connect()?;
+
+    // The server answers with EmptyQueryResponse, which is not an error.
+    assert!(client.query("")?.is_empty());
+    assert_eq!(client.query("SELECT 1")?.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_client_query() -> Result<(), Box<dyn Error>> {
+    let password_hash = auth::md5_hash("postgres", "secret");
+    let server = Server::spawn(&["--require-password", "--password-hash", &password_hash]);
+    let address: SocketAddr = server.address.parse()?;
+
+    let mut client = AsyncClient::connect(
+        &Config::new()
+            .add_host(address.ip().to_string(), address.port())
+            .user("postgres")
+            .password("secret"),
+    )
+    .await?;
+
+    let rows = client.query("SELECT 'Hello, world!'").await?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].field(0), Some(&b"Hello, world!"[..]));
+    client.shutdown().await?;
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    error::Error,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rpsql::{
+    messages::backend::{DataRow, RowDescription},
+    Row,
+};
+
+// Counts every allocation made by this test binary.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_integer_decoding_does_not_allocate() -> Result<(), Box<dyn Error>> {
+    let description = RowDescription::builder()
+        .string_field("id")
+        .string_field("count")
+        .build();
+    let data_row = DataRow::builder()
+        .string_field("42")
+        .string_field("-7")
+        .build();
+    let row = Row::new(&description, &data_row)?;
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    let id: i32 = row.get(0)?;
+    let count: i64 = row.get(1)?;
+    let after = ALLOCATIONS.load(Ordering::SeqCst);
+
+    assert_eq!((id, count), (42, -7));
+    assert_eq!(after - before, 0);
+
+    Ok(())
+}